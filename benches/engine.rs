@@ -0,0 +1,138 @@
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::hint::black_box;
+use tokio_util::sync::CancellationToken;
+use transactions::csv::{CsvRecord, ParallelConfig, TransactionType, read_transactions};
+use transactions::generate::{self, GeneratorConfig};
+use transactions::{Account, ClientId, Engine, Transaction};
+
+const ROWS: usize = 10_000;
+
+fn generated_csv(config: GeneratorConfig) -> Vec<u8> {
+    let config = GeneratorConfig {
+        rows: ROWS,
+        ..config
+    };
+    let mut output = Vec::new();
+    generate::write_csv(&config, &mut output).expect("Failed to generate CSV");
+    output
+}
+
+/// A row as `read_transactions` used to deserialize it, with an owned `type` string
+#[derive(Deserialize)]
+struct SerdeRecord {
+    #[serde(rename = "type")]
+    tx_type: String,
+    client: ClientId,
+    tx: u32,
+    amount: Option<Decimal>,
+}
+
+/// The serde-based reader `read_transactions` replaced, kept as a baseline
+fn read_transactions_serde(
+    input: &[u8],
+) -> impl Iterator<Item = Result<Transaction, Box<dyn Error>>> + '_ {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input)
+        .into_deserialize::<SerdeRecord>()
+        .map(|result| {
+            let record = result?;
+            let record = CsvRecord {
+                tx_type: record.tx_type.parse::<TransactionType>()?,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+            };
+            Ok(record.into_transaction()?)
+        })
+}
+
+fn bench_csv_parsing(c: &mut Criterion) {
+    let csv = generated_csv(GeneratorConfig::default());
+    let mut group = c.benchmark_group("csv");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("read_transactions", |b| {
+        b.iter(|| read_transactions(black_box(&csv[..])).count())
+    });
+    group.bench_function("read_transactions_serde", |b| {
+        b.iter(|| read_transactions_serde(black_box(&csv[..])).count())
+    });
+    group.finish();
+}
+
+fn bench_process_transaction(c: &mut Criterion) {
+    // The generator hands out new IDs from one counter shared by all clients, so
+    // all rows can go through one account. Error rows that reuse a client's
+    // deposit ID are still rejected as duplicates, just as they would be per
+    // client. Without chargebacks it never locks and every row does real work.
+    let config = GeneratorConfig {
+        chargeback_rate: 0.0,
+        ..GeneratorConfig::default()
+    };
+    let transactions: Vec<Transaction> = read_transactions(&generated_csv(config)[..])
+        .map(|result| result.expect("Generated rows parse"))
+        .collect();
+
+    let mut group = c.benchmark_group("account");
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.bench_function("process_transaction", |b| {
+        b.iter_batched(
+            || (Account::new(1), transactions.clone()),
+            |(mut account, transactions)| {
+                for transaction in transactions {
+                    account.process_transaction(transaction).ok();
+                }
+                account
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let dir = tempfile::tempdir().expect("Failed to create benchmark directory");
+    let path = dir.path().join("pipeline.csv");
+    fs::write(&path, generated_csv(GeneratorConfig::default()))
+        .expect("Failed to write benchmark input");
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start runtime");
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.sample_size(20);
+    group.bench_function("process_csv", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let engine = Engine::default();
+                engine.process_csv(&path, CancellationToken::new()).await
+            })
+        })
+    });
+    group.bench_function("process_csv_parallel", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                // Small chunks so the 10,000 rows are split across workers
+                let engine = Engine::builder()
+                    .parallel_parse(ParallelConfig {
+                        chunk_size: 32 * 1024,
+                        ..ParallelConfig::default()
+                    })
+                    .build();
+                engine.process_csv(&path, CancellationToken::new()).await
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_csv_parsing,
+    bench_process_transaction,
+    bench_pipeline
+);
+criterion_main!(benches);
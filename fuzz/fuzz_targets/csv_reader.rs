@@ -0,0 +1,29 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+use transactions::{Account, ClientId, invariants, rebuild};
+
+// Arbitrary bytes as a CSV file: the reader must not panic, and whatever it
+// accepts must leave every account consistent.
+fuzz_target!(|data: &[u8]| {
+    let mut accounts: HashMap<ClientId, Account> = HashMap::new();
+
+    for transaction in transactions::csv::read_transactions(data).flatten() {
+        let client = transaction.client_id();
+        let account = accounts
+            .entry(client)
+            .or_insert_with(|| Account::new(client));
+        account.process_transaction(transaction).ok();
+    }
+
+    for (client, account) in &accounts {
+        let violations = invariants::check_account(account);
+        assert!(violations.is_empty(), "{:?}", violations);
+
+        let rebuilt = rebuild::replay(*client, account.ledger(), account.dispute_policy())
+            .expect("History is replayable");
+        let differences = rebuilt.compare(account);
+        assert!(differences.is_empty(), "{:?}", differences);
+    }
+});
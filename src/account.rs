@@ -1,264 +1,896 @@
-use crate::transaction::Transaction;
+use crate::invariants::{self, InvariantViolation};
+use crate::limits::{LimitUsage, Limits, LimitsConfig};
+use crate::rebuild::{self, RebuildReport};
+use crate::spill::{SpillConfig, SpillLedgerStore, SpillPool};
+use crate::store::{AccountStore, LedgerStore};
+use crate::transaction::{
+    AmountPolicy, ClientId, DisputePolicy, Transaction, TransactionError, TransactionState,
+};
+use log::error;
 use rust_decimal::Decimal;
-use std::{collections::HashMap, error::Error, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, io,
+    sync::{Arc, Mutex},
+};
 use tokio::sync::RwLock;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountError {
+    Locked,
+    DuplicateTransaction(u32),
+    InsufficientFunds,
+    TransactionNotFound,
+    NotMoneyTransaction(&'static str),
+    DisputeReversed,
+}
+
+impl AccountError {
+    /// Stable machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            AccountError::Locked => "account_locked",
+            AccountError::DuplicateTransaction(_) => "duplicate_transaction",
+            AccountError::InsufficientFunds => "insufficient_funds",
+            AccountError::TransactionNotFound => "transaction_not_found",
+            AccountError::NotMoneyTransaction(_) => "not_money_transaction",
+            AccountError::DisputeReversed => "transaction_reversed",
+        }
+    }
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::Locked => write!(f, "Account is locked"),
+            AccountError::DuplicateTransaction(tx) => {
+                write!(f, "Transaction ID {} already exists", tx)
+            }
+            AccountError::InsufficientFunds => write!(f, "Insufficient funds"),
+            AccountError::TransactionNotFound => write!(f, "Transaction not found"),
+            AccountError::NotMoneyTransaction(action) => {
+                write!(f, "Cannot {} non-money transaction", action)
+            }
+            AccountError::DisputeReversed => write!(f, "Cannot dispute a reversed transaction"),
+        }
+    }
+}
+
+impl Error for AccountError {}
+
+/// What happened to a transaction that was accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessOutcome {
+    Applied,
+    /// A dispute, resolve or chargeback row that was already applied, e.g. from a
+    /// resent file. Balances are left untouched.
+    Replayed,
+}
+
+/// One applied transaction in a ledger's history. Money transactions are looked
+/// up by id in the ledger for their amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerEvent {
+    Deposit(u32),
+    Withdrawal(u32),
+    Dispute(u32),
+    Resolve(u32),
+    Chargeback(u32),
+    Reversal(u32),
+}
+
+impl From<&Transaction> for LedgerEvent {
+    fn from(transaction: &Transaction) -> Self {
+        let tx = transaction.transaction_id();
+        match transaction {
+            Transaction::Deposit(_) => LedgerEvent::Deposit(tx),
+            Transaction::Withdrawal(_) => LedgerEvent::Withdrawal(tx),
+            Transaction::Dispute(_) => LedgerEvent::Dispute(tx),
+            Transaction::Resolve(_) => LedgerEvent::Resolve(tx),
+            Transaction::Chargeback(_) => LedgerEvent::Chargeback(tx),
+            Transaction::Reversal(_) => LedgerEvent::Reversal(tx),
+        }
+    }
+}
+
+/// Transactions and history of one account.
+///
+/// Transactions live in a `LedgerStore`, in memory unless another store is
+/// given. The history is kept in memory too, unless the store keeps it, in
+/// which case only events recorded since the last `flush` are.
 #[derive(Debug, Clone)]
 pub struct Ledger {
-    transactions: HashMap<u32, Transaction>,
+    store: Box<dyn LedgerStore>,
+    history: Vec<LedgerEvent>,
+    /// Number of history events already passed to the store
+    flushed: usize,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ledger {
     pub fn new() -> Self {
+        Self::with_store(HashMap::new())
+    }
+
+    pub fn with_store(store: impl LedgerStore + 'static) -> Self {
+        Self::restore(Box::new(store), Vec::new())
+    }
+
+    /// A ledger whose transactions and history were persisted earlier. Pass an
+    /// empty `history` for stores that keep it themselves.
+    pub fn restore(store: Box<dyn LedgerStore>, history: Vec<LedgerEvent>) -> Self {
         Self {
-            transactions: HashMap::new(),
+            store,
+            flushed: history.len(),
+            history,
+        }
+    }
+
+    /// Append an applied transaction to the history
+    pub fn record(&mut self, event: LedgerEvent) {
+        self.history.push(event);
+    }
+
+    /// Every applied transaction, in the order it was applied
+    pub fn history(&self) -> impl Iterator<Item = io::Result<LedgerEvent>> + '_ {
+        self.store
+            .history()
+            .chain(self.history.iter().copied().map(Ok))
+    }
+
+    /// Persist changed transactions and new history events to the store
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.store.flush(&self.history[self.flushed..])?;
+        if self.store.keeps_history() {
+            self.history.clear();
         }
+        self.flushed = self.history.len();
+        Ok(())
     }
 
     pub fn add_transaction(&mut self, tx_id: u32, transaction: Transaction) {
-        self.transactions.insert(tx_id, transaction);
+        self.store.insert(tx_id, transaction);
     }
 
-    pub fn get_transaction(&self, tx_id: u32) -> Option<&Transaction> {
-        self.transactions.get(&tx_id)
+    pub fn contains(&self, tx_id: u32) -> io::Result<bool> {
+        self.store.contains(tx_id)
     }
 
-    pub fn get_transaction_mut(&mut self, tx_id: u32) -> Option<&mut Transaction> {
-        self.transactions.get_mut(&tx_id)
+    pub fn get_transaction(&self, tx_id: u32) -> io::Result<Option<Cow<'_, Transaction>>> {
+        self.store.get(tx_id)
     }
 
-    pub fn is_disputed(&self, tx_id: u32) -> bool {
-        if let Some(tx) = self.transactions.get(&tx_id) {
-            match tx {
-                Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx) => {
-                    money_tx.is_disputed()
-                }
-                _ => false,
-            }
-        } else {
-            false
-        }
+    pub fn get_transaction_mut(&mut self, tx_id: u32) -> io::Result<Option<&mut Transaction>> {
+        self.store.get_mut(tx_id)
     }
 
-    pub fn is_chargedback(&self, tx_id: u32) -> bool {
-        if let Some(tx) = self.transactions.get(&tx_id) {
-            match tx {
-                Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx) => {
-                    money_tx.is_chargedback()
-                }
-                _ => false,
+    /// Every transaction, in no particular order
+    pub fn transactions(&self) -> impl Iterator<Item = io::Result<Cow<'_, Transaction>>> {
+        self.store.transactions()
+    }
+
+    /// State of a deposit or withdrawal, or `None` for any other transaction
+    /// or an unknown id
+    fn money_state(&self, tx_id: u32) -> io::Result<Option<TransactionState>> {
+        Ok(match self.get_transaction(tx_id)?.as_deref() {
+            Some(Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx)) => {
+                Some(money_tx.state)
             }
-        } else {
-            false
-        }
+            _ => None,
+        })
+    }
+
+    pub fn is_disputed(&self, tx_id: u32) -> io::Result<bool> {
+        Ok(self.money_state(tx_id)? == Some(TransactionState::Disputed))
+    }
+
+    pub fn is_chargedback(&self, tx_id: u32) -> io::Result<bool> {
+        Ok(self.money_state(tx_id)? == Some(TransactionState::Chargedback))
+    }
+
+    pub fn is_reversed(&self, tx_id: u32) -> io::Result<bool> {
+        Ok(self.money_state(tx_id)? == Some(TransactionState::Reversed))
     }
 }
 
+/// One client's balances and ledger. Balances only change by processing transactions.
 #[derive(Debug, Clone)]
 pub struct Account {
-    pub client: u16,
-    pub ledger: Ledger,
-    pub available: Decimal,
-    pub held: Decimal,
-    pub total: Decimal,
-    pub locked: bool,
+    pub(crate) client: ClientId,
+    pub(crate) ledger: Ledger,
+    pub(crate) available: Decimal,
+    pub(crate) held: Decimal,
+    pub(crate) total: Decimal,
+    pub(crate) locked: bool,
+    pub(crate) limits: Limits,
+    pub(crate) dispute_policy: DisputePolicy,
+    pub(crate) amount_policy: AmountPolicy,
+    pub(crate) limit_usage: LimitUsage,
+    /// Part of a negative `available` that withdrawals drew on the credit line,
+    /// as opposed to funds held by a dispute
+    pub(crate) credit_drawn: Decimal,
 }
 
 impl Account {
-    pub fn new(client: u16) -> Self {
+    pub fn new(client: impl Into<ClientId>) -> Self {
+        Self::with_limits(client, Limits::default())
+    }
+
+    pub fn with_limits(client: impl Into<ClientId>, limits: Limits) -> Self {
         Self {
-            client,
+            client: client.into(),
             ledger: Ledger::new(),
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             total: Decimal::ZERO,
             locked: false,
+            limits,
+            dispute_policy: DisputePolicy::default(),
+            amount_policy: AmountPolicy::default(),
+            limit_usage: LimitUsage::default(),
+            credit_drawn: Decimal::ZERO,
         }
     }
 
-    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<(), Box<dyn Error>> {
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    pub fn total(&self) -> Decimal {
+        self.total
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn dispute_policy(&self) -> &DisputePolicy {
+        &self.dispute_policy
+    }
+
+    pub fn amount_policy(&self) -> &AmountPolicy {
+        &self.amount_policy
+    }
+
+    pub fn process_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<ProcessOutcome, Box<dyn Error>> {
+        if self.is_replay(&transaction)? {
+            return Ok(ProcessOutcome::Replayed);
+        }
+
+        let event = LedgerEvent::from(&transaction);
+        let outcome = self.apply(transaction)?;
+        self.ledger.record(event);
+        Ok(outcome)
+    }
+
+    fn apply(&mut self, transaction: Transaction) -> Result<ProcessOutcome, Box<dyn Error>> {
         if self.locked && !matches!(transaction, Transaction::Chargeback(_)) {
-            return Err("Account is locked".into());
+            return Err(AccountError::Locked.into());
         }
 
         match transaction {
             Transaction::Deposit(money_tx) => {
                 // Check if transaction ID already exists
-                if self.ledger.get_transaction(money_tx.id.tx).is_some() {
-                    return Err(format!("Transaction ID {} already exists", money_tx.id.tx).into());
+                if self.ledger.contains(money_tx.id.tx)? {
+                    return Err(AccountError::DuplicateTransaction(money_tx.id.tx).into());
                 }
 
+                self.amount_policy.check(money_tx.amount)?;
+                self.limits.check_deposit(money_tx.amount)?;
+                self.limit_usage
+                    .check(&self.limits, money_tx.timestamp, None)?;
+
                 self.deposit(money_tx.amount);
+                self.limit_usage
+                    .record(&self.limits, money_tx.timestamp, None);
                 self.ledger
                     .add_transaction(money_tx.id.tx, Transaction::Deposit(money_tx));
-                Ok(())
+                Ok(ProcessOutcome::Applied)
             }
             Transaction::Withdrawal(money_tx) => {
                 // Check if transaction ID already exists
-                if self.ledger.get_transaction(money_tx.id.tx).is_some() {
-                    return Err(format!("Transaction ID {} already exists", money_tx.id.tx).into());
+                if self.ledger.contains(money_tx.id.tx)? {
+                    return Err(AccountError::DuplicateTransaction(money_tx.id.tx).into());
                 }
 
+                self.amount_policy.check(money_tx.amount)?;
+                self.limits.check_withdrawal(money_tx.amount)?;
+                self.limit_usage
+                    .check(&self.limits, money_tx.timestamp, Some(money_tx.amount))?;
+
                 self.withdraw(money_tx.amount)?;
+                self.limit_usage
+                    .record(&self.limits, money_tx.timestamp, Some(money_tx.amount));
                 self.ledger
                     .add_transaction(money_tx.id.tx, Transaction::Withdrawal(money_tx));
-                Ok(())
+                Ok(ProcessOutcome::Applied)
             }
             Transaction::Dispute(client_tx) => {
                 // Check if transaction is already disputed or chargedback
-                if self.ledger.is_disputed(client_tx.tx) {
-                    return Err("Transaction is already under dispute".into());
+                if self.ledger.is_disputed(client_tx.tx)? {
+                    return Err(TransactionError::AlreadyDisputed.into());
+                }
+                if self.ledger.is_chargedback(client_tx.tx)? {
+                    return Err(TransactionError::AlreadyChargedback.into());
                 }
-                if self.ledger.is_chargedback(client_tx.tx) {
-                    return Err("Cannot dispute a chargedback transaction".into());
+                if self.ledger.is_reversed(client_tx.tx)? {
+                    return Err(AccountError::DisputeReversed.into());
                 }
 
                 // Get mutable reference to the transaction and mark it as disputed
-                let amount = if let Some(tx) = self.ledger.get_transaction_mut(client_tx.tx) {
+                let amount = if let Some(tx) = self.ledger.get_transaction_mut(client_tx.tx)? {
                     match tx {
                         Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx) => {
-                            money_tx.mark_disputed()?;
+                            money_tx.mark_disputed_under(&self.dispute_policy)?;
                             money_tx.amount
                         }
-                        _ => return Err("Cannot dispute non-money transaction".into()),
+                        _ => return Err(AccountError::NotMoneyTransaction("dispute").into()),
                     }
                 } else {
-                    return Err("Transaction not found".into());
+                    return Err(AccountError::TransactionNotFound.into());
                 };
 
                 self.dispute(amount);
-                Ok(())
+                Ok(ProcessOutcome::Applied)
             }
             Transaction::Resolve(client_tx) => {
                 // Check if transaction is actually disputed
-                if !self.ledger.is_disputed(client_tx.tx) {
-                    return Err("Transaction is not under dispute".into());
+                if !self.ledger.is_disputed(client_tx.tx)? {
+                    return Err(TransactionError::NotDisputed.into());
                 }
 
                 // Get mutable reference to the transaction and resolve it
-                let amount = if let Some(tx) = self.ledger.get_transaction_mut(client_tx.tx) {
+                let amount = if let Some(tx) = self.ledger.get_transaction_mut(client_tx.tx)? {
                     match tx {
                         Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx) => {
                             money_tx.resolve_dispute()?;
                             money_tx.amount
                         }
-                        _ => return Err("Cannot resolve non-money transaction".into()),
+                        _ => return Err(AccountError::NotMoneyTransaction("resolve").into()),
                     }
                 } else {
-                    return Err("Transaction not found".into());
+                    return Err(AccountError::TransactionNotFound.into());
                 };
 
                 self.resolve(amount);
-                Ok(())
+                Ok(ProcessOutcome::Applied)
             }
             Transaction::Chargeback(client_tx) => {
                 // Check if transaction is actually disputed
-                if !self.ledger.is_disputed(client_tx.tx) {
-                    return Err("Transaction is not under dispute".into());
+                if !self.ledger.is_disputed(client_tx.tx)? {
+                    return Err(TransactionError::NotDisputed.into());
                 }
 
                 // Get mutable reference to the transaction and mark it as chargedback
-                let amount = if let Some(tx) = self.ledger.get_transaction_mut(client_tx.tx) {
+                let amount = if let Some(tx) = self.ledger.get_transaction_mut(client_tx.tx)? {
                     match tx {
                         Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx) => {
                             money_tx.mark_chargedback()?;
                             money_tx.amount
                         }
-                        _ => return Err("Cannot chargeback non-money transaction".into()),
+                        _ => {
+                            return Err(AccountError::NotMoneyTransaction("chargeback").into());
+                        }
                     }
                 } else {
-                    return Err("Transaction not found".into());
+                    return Err(AccountError::TransactionNotFound.into());
                 };
 
                 self.chargeback(amount);
-                Ok(())
+                Ok(ProcessOutcome::Applied)
+            }
+            Transaction::Reversal(client_tx) => {
+                // Reversing a deposit takes its funds back like a withdrawal, so it
+                // must fit in `available` plus the credit line
+                let cover = self.available + self.credit_limit();
+
+                // Mark the original transaction as reversed and undo its balance effect
+                match self.ledger.get_transaction_mut(client_tx.tx)? {
+                    Some(Transaction::Deposit(money_tx)) => {
+                        money_tx.check_reversible()?;
+                        if money_tx.amount > cover {
+                            return Err(AccountError::InsufficientFunds.into());
+                        }
+                        money_tx.mark_reversed()?;
+                        let amount = money_tx.amount;
+                        self.reverse_deposit(amount);
+                    }
+                    Some(Transaction::Withdrawal(money_tx)) => {
+                        money_tx.mark_reversed()?;
+                        let amount = money_tx.amount;
+                        self.reverse_withdrawal(amount);
+                    }
+                    Some(_) => return Err(AccountError::NotMoneyTransaction("reverse").into()),
+                    None => return Err(AccountError::TransactionNotFound.into()),
+                }
+                Ok(ProcessOutcome::Applied)
             }
         }
     }
 
-    pub fn deposit(&mut self, amount: Decimal) {
+    /// Whether a dispute, resolve or chargeback row repeats one already applied to `tx`.
+    ///
+    /// A dispute after a resolve is a new dispute, not a replay.
+    fn is_replay(&self, transaction: &Transaction) -> io::Result<bool> {
+        let (client_tx, replayed_states): (_, &[TransactionState]) = match transaction {
+            Transaction::Dispute(client_tx) => (
+                client_tx,
+                &[TransactionState::Disputed, TransactionState::Chargedback],
+            ),
+            Transaction::Resolve(client_tx) => (client_tx, &[TransactionState::Resolved]),
+            Transaction::Chargeback(client_tx) => (client_tx, &[TransactionState::Chargedback]),
+            _ => return Ok(false),
+        };
+
+        Ok(self
+            .ledger
+            .money_state(client_tx.tx)?
+            .is_some_and(|state| replayed_states.contains(&state)))
+    }
+
+    pub(crate) fn deposit(&mut self, amount: Decimal) {
         if !self.locked {
             self.available += amount;
             self.total += amount;
+            self.repay_credit();
         }
     }
 
-    pub fn withdraw(&mut self, amount: Decimal) -> Result<(), String> {
+    pub(crate) fn withdraw(&mut self, amount: Decimal) -> Result<(), AccountError> {
         if self.locked {
-            return Err("Account is locked".to_string());
+            return Err(AccountError::Locked);
         }
 
-        if self.available >= amount {
+        if self.available + self.credit_limit() >= amount {
+            self.credit_drawn += amount - self.available.max(Decimal::ZERO).min(amount);
             self.available -= amount;
             self.total -= amount;
             Ok(())
         } else {
-            Err("Insufficient funds".to_string())
+            Err(AccountError::InsufficientFunds)
         }
     }
 
-    pub fn dispute(&mut self, amount: Decimal) {
+    pub(crate) fn dispute(&mut self, amount: Decimal) {
         if !self.locked {
             self.available -= amount;
             self.held += amount;
         }
     }
 
-    pub fn resolve(&mut self, amount: Decimal) {
+    pub(crate) fn resolve(&mut self, amount: Decimal) {
         if !self.locked {
             self.held -= amount;
             self.available += amount;
+            self.repay_credit();
+        }
+    }
+
+    /// Take back a deposit. Funds already withdrawn are drawn on the credit line;
+    /// the caller checks that `available` plus the line covers `amount`.
+    pub(crate) fn reverse_deposit(&mut self, amount: Decimal) {
+        if !self.locked {
+            self.credit_drawn += amount - self.available.max(Decimal::ZERO).min(amount);
+            self.available -= amount;
+            self.total -= amount;
+        }
+    }
+
+    pub(crate) fn reverse_withdrawal(&mut self, amount: Decimal) {
+        if !self.locked {
+            self.available += amount;
+            self.total += amount;
+            self.repay_credit();
         }
     }
 
-    pub fn chargeback(&mut self, amount: Decimal) {
+    /// Funds coming back into `available` pay off drawn credit first
+    fn repay_credit(&mut self) {
+        self.credit_drawn = self.credit_drawn.min((-self.available).max(Decimal::ZERO));
+    }
+
+    /// Credit the client may draw on. A locked account has no credit.
+    pub fn credit_limit(&self) -> Decimal {
+        if self.locked {
+            return Decimal::ZERO;
+        }
+        self.limits.credit_limit.unwrap_or(Decimal::ZERO)
+    }
+
+    /// Amount of credit currently drawn by withdrawals. Funds held by a dispute
+    /// can also take `available` below zero but are not credit.
+    pub fn credit_used(&self) -> Decimal {
+        self.credit_drawn
+    }
+
+    /// Take back held funds, locking the account unless the dispute policy says otherwise
+    pub(crate) fn chargeback(&mut self, amount: Decimal) {
         self.held -= amount;
         self.total -= amount;
-        self.locked = true;
+        if self.dispute_policy.lock_on_chargeback {
+            self.locked = true;
+        }
+    }
+}
+
+/// The balances of one account without its ledger, for reporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountBalance {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// See `Account::credit_used`
+    pub credit_used: Decimal,
+}
+
+impl From<&Account> for AccountBalance {
+    fn from(account: &Account) -> Self {
+        Self {
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            credit_used: account.credit_used(),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct AccountManager {
-    accounts: Arc<RwLock<HashMap<u16, Account>>>,
+    accounts: Arc<RwLock<Box<dyn AccountStore>>>,
+    limits: Arc<LimitsConfig>,
+    dispute_policy: DisputePolicy,
+    amount_policy: AmountPolicy,
+    /// Check the account's invariants after every transaction
+    check_each_transaction: bool,
+    invariant_violations: Arc<Mutex<Vec<InvariantViolation>>>,
+    /// Shared memory budget for ledger transactions, with the rest on disk
+    ledger_spill: Option<SpillPool>,
+}
+
+impl Default for AccountManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AccountManager {
     pub fn new() -> Self {
+        Self::with_limits(LimitsConfig::default())
+    }
+
+    pub fn with_limits(limits: LimitsConfig) -> Self {
+        Self {
+            accounts: Arc::new(RwLock::new(Box::new(HashMap::new()))),
+            limits: Arc::new(limits),
+            dispute_policy: DisputePolicy::default(),
+            amount_policy: AmountPolicy::default(),
+            check_each_transaction: false,
+            invariant_violations: Arc::default(),
+            ledger_spill: None,
+        }
+    }
+
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    pub fn with_amount_policy(mut self, amount_policy: AmountPolicy) -> Self {
+        self.amount_policy = amount_policy;
+        self
+    }
+
+    /// Check balance invariants after every transaction. Meant for debugging:
+    /// it scans the account's ledger each time.
+    pub fn with_invariant_checks(mut self, check_each_transaction: bool) -> Self {
+        self.check_each_transaction = check_each_transaction;
+        self
+    }
+
+    /// Keep at most `config.max_in_memory` transactions in memory across all
+    /// accounts, moving the least recently used and every ledger's history to a
+    /// file under `config.dir`
+    pub fn with_ledger_spill(mut self, config: SpillConfig) -> Self {
+        self.ledger_spill = Some(SpillPool::new(config));
+        self
+    }
+
+    /// Keep accounts in `store` instead of memory. Accounts already in the store
+    /// get this manager's limits and policies, so call this after `with_limits`,
+    /// `with_dispute_policy` and `with_amount_policy`.
+    pub fn with_account_store(self, mut store: Box<dyn AccountStore>) -> Self {
+        let limits = self.limits.clone();
+        let (dispute_policy, amount_policy) = (self.dispute_policy, self.amount_policy);
+        store.configure(Arc::new(move |account: &mut Account| {
+            account.limits = limits.for_client(account.client);
+            account.dispute_policy = dispute_policy;
+            account.amount_policy = amount_policy;
+        }));
         Self {
-            accounts: Arc::new(RwLock::new(HashMap::new())),
+            accounts: Arc::new(RwLock::new(store)),
+            ..self
+        }
+    }
+
+    fn new_account(&self, store: &dyn AccountStore, client_id: ClientId) -> Account {
+        let mut account = Account::with_limits(client_id, self.limits.for_client(client_id));
+        account.dispute_policy = self.dispute_policy;
+        account.amount_policy = self.amount_policy;
+        if let Some(ledger_store) = store.new_ledger(client_id) {
+            account.ledger = Ledger::restore(ledger_store, Vec::new());
+        } else if let Some(pool) = &self.ledger_spill {
+            account.ledger = Ledger::with_store(SpillLedgerStore::new(pool.clone(), client_id));
         }
+        account
     }
 
     pub async fn process_transaction(
         &self,
         transaction: Transaction,
-    ) -> Result<(), Box<dyn Error>> {
-        let client_id = transaction.client_id();
+    ) -> Result<ProcessOutcome, Box<dyn Error>> {
+        let mut accounts = self.accounts.write().await;
+        let result = self.apply(accounts.as_mut(), transaction);
+        accounts.commit()?;
+        result
+    }
+
+    /// Process `transactions` in order under one acquisition of the accounts lock.
+    ///
+    /// Returns one result per transaction, the same as calling `process_transaction`
+    /// for each in turn. The account store commits once for the whole batch; if
+    /// that fails, every transaction in the batch fails with its error.
+    pub async fn process_batch(
+        &self,
+        transactions: Vec<Transaction>,
+    ) -> Vec<Result<ProcessOutcome, Box<dyn Error>>> {
+        let count = transactions.len();
+        match self.process_and_commit(transactions, None).await {
+            Ok(results) => results,
+            Err(e) => (0..count)
+                .map(|_| Err(io::Error::new(e.kind(), e.to_string()).into()))
+                .collect(),
+        }
+    }
 
+    /// Like `process_batch`, for the last batch of a file that was read to the
+    /// end: the store records `fingerprint` as ingested in the same commit. If
+    /// the commit fails, none of the batch is kept and the error is returned
+    /// instead of the results.
+    pub async fn process_last_batch(
+        &self,
+        transactions: Vec<Transaction>,
+        fingerprint: &str,
+    ) -> io::Result<Vec<Result<ProcessOutcome, Box<dyn Error>>>> {
+        self.process_and_commit(transactions, Some(fingerprint))
+            .await
+    }
+
+    async fn process_and_commit(
+        &self,
+        transactions: Vec<Transaction>,
+        fingerprint: Option<&str>,
+    ) -> io::Result<Vec<Result<ProcessOutcome, Box<dyn Error>>>> {
         let mut accounts = self.accounts.write().await;
+        let results: Vec<_> = transactions
+            .into_iter()
+            .map(|transaction| self.apply(accounts.as_mut(), transaction))
+            .collect();
+        if let Some(fingerprint) = fingerprint {
+            accounts.record_ingested(fingerprint);
+        }
+        accounts.commit()?;
+        Ok(results)
+    }
+
+    /// Whether the account store recorded a file with this fingerprint as ingested
+    pub async fn is_ingested(&self, fingerprint: &str) -> io::Result<bool> {
+        self.accounts.read().await.is_ingested(fingerprint)
+    }
+
+    fn apply(
+        &self,
+        accounts: &mut dyn AccountStore,
+        transaction: Transaction,
+    ) -> Result<ProcessOutcome, Box<dyn Error>> {
+        let client_id = transaction.client_id();
+        if accounts.get_mut(client_id)?.is_none() {
+            let account = self.new_account(accounts, client_id);
+            accounts.insert(account);
+        }
         let account = accounts
-            .entry(client_id)
-            .or_insert_with(|| Account::new(client_id));
-        account.process_transaction(transaction)
+            .get_mut(client_id)?
+            .expect("account was just inserted");
+        let tx_id = transaction.transaction_id();
+        let result = account.process_transaction(transaction);
+
+        if self.check_each_transaction {
+            let violations = invariants::check_account(account);
+            for violation in &violations {
+                error!(
+                    "Invariant violated after transaction {}: {}",
+                    tx_id, violation
+                );
+            }
+            self.invariant_violations.lock().unwrap().extend(violations);
+        }
+
+        // A transaction that could not be saved did not happen, whatever `result` says
+        if let Err(e) = accounts.save(client_id) {
+            error!(
+                "Error saving client {} after transaction {}: {}",
+                client_id, tx_id, e
+            );
+            return Err(e.into());
+        }
+        result
+    }
+
+    /// Violations found by per-transaction checks so far
+    pub fn invariant_violations(&self) -> Vec<InvariantViolation> {
+        self.invariant_violations.lock().unwrap().clone()
     }
 
-    #[cfg(test)]
-    pub async fn get_account(&self, client: u16) -> Option<Account> {
+    /// Check the invariants of every account now, sorted by client
+    pub async fn check_invariants(&self) -> io::Result<Vec<InvariantViolation>> {
         let accounts = self.accounts.read().await;
-        accounts.get(&client).cloned()
+        let mut violations = Vec::new();
+        for account in accounts.accounts() {
+            violations.extend(invariants::check_account(account?.as_ref()));
+        }
+        violations.sort_by_key(|violation| violation.client());
+        Ok(violations)
     }
 
-    pub async fn accounts(&self) -> HashMap<u16, Account> {
+    pub async fn get_account(&self, client: impl Into<ClientId>) -> io::Result<Option<Account>> {
         let accounts = self.accounts.read().await;
-        accounts.clone()
+        Ok(accounts.get(client.into())?.map(Cow::into_owned))
     }
 
-    #[cfg(test)]
-    pub async fn total_accounts(&self) -> usize {
+    /// One transaction of `client`, read from its ledger store without cloning the account
+    pub async fn get_transaction(
+        &self,
+        client: impl Into<ClientId>,
+        tx_id: u32,
+    ) -> io::Result<Option<Transaction>> {
+        let accounts = self.accounts.read().await;
+        match accounts.get(client.into())? {
+            Some(account) => Ok(account.ledger.get_transaction(tx_id)?.map(Cow::into_owned)),
+            None => Ok(None),
+        }
+    }
+
+    /// Balances of one account, without cloning its ledger
+    pub async fn get_balance(
+        &self,
+        client: impl Into<ClientId>,
+    ) -> io::Result<Option<AccountBalance>> {
+        let accounts = self.accounts.read().await;
+        Ok(accounts
+            .get(client.into())?
+            .map(|account| AccountBalance::from(account.as_ref())))
+    }
+
+    /// Balances of every account sorted by client, without cloning any ledger
+    pub async fn balances(&self) -> io::Result<Vec<AccountBalance>> {
+        let accounts = self.accounts.read().await;
+        let mut balances = accounts
+            .accounts()
+            .map(|account| Ok(AccountBalance::from(account?.as_ref())))
+            .collect::<io::Result<Vec<_>>>()?;
+        balances.sort_by_key(|balance| balance.client);
+        Ok(balances)
+    }
+
+    /// Run `f` over every account, in no particular order, without cloning the
+    /// ones held in memory. Transactions wait until `f` returns.
+    pub async fn with_accounts<R>(
+        &self,
+        f: impl FnOnce(&mut dyn Iterator<Item = io::Result<Cow<'_, Account>>>) -> R,
+    ) -> R {
+        let accounts = self.accounts.read().await;
+        f(&mut accounts.accounts())
+    }
+
+    /// Clone of every account, ledgers included. Prefer `balances` or
+    /// `with_accounts` for large runs.
+    pub async fn accounts(&self) -> io::Result<HashMap<ClientId, Account>> {
+        let accounts = self.accounts.read().await;
+        accounts
+            .accounts()
+            .map(|account| {
+                let account = account?.into_owned();
+                Ok((account.client, account))
+            })
+            .collect()
+    }
+
+    pub async fn total_accounts(&self) -> io::Result<usize> {
         let accounts = self.accounts.read().await;
         accounts.len()
     }
+
+    /// Rebuild every account's balances from its ledger history alone and
+    /// compare them with the current ones, leaving the accounts unchanged
+    pub async fn verify(&self) -> io::Result<RebuildReport> {
+        let accounts = self.accounts.read().await;
+        Self::replay_all(accounts.as_ref())
+    }
+
+    fn replay_all(accounts: &dyn AccountStore) -> io::Result<RebuildReport> {
+        let mut report = RebuildReport::default();
+
+        for account in accounts.accounts() {
+            let account = account?;
+            match rebuild::replay(account.client, &account.ledger, &account.dispute_policy) {
+                Ok(balances) => {
+                    report.violations.extend(balances.compare(&account));
+                    report.accounts.insert(account.client, balances);
+                }
+                Err(violation) => report.violations.push(violation),
+            }
+        }
+        report
+            .violations
+            .sort_by_key(|violation| violation.client());
+        Ok(report)
+    }
+
+    /// Like `verify`, then replace the balances of every account that differs
+    /// with the rebuilt ones and save it. Accounts whose history cannot be
+    /// replayed are left as they are. The report lists the differences found
+    /// before the balances were replaced. Transactions wait until both are done,
+    /// so none is lost between the replay and the restore.
+    pub async fn rebuild(&self) -> io::Result<RebuildReport> {
+        let mut accounts = self.accounts.write().await;
+        let report = Self::replay_all(accounts.as_ref())?;
+        let mismatched: HashSet<_> = report
+            .violations
+            .iter()
+            .map(|violation| violation.client())
+            .collect();
+
+        for client in mismatched {
+            let Some(balances) = report.accounts.get(&client) else {
+                continue;
+            };
+            if let Some(account) = accounts.get_mut(client)? {
+                balances.restore(account);
+                accounts.save(client)?;
+            }
+        }
+        accounts.commit()?;
+        Ok(report)
+    }
 }
 
 #[cfg(test)]
@@ -273,7 +905,7 @@ mod tests {
         assert_eq!(account.available, Decimal::ZERO);
         assert_eq!(account.held, Decimal::ZERO);
         assert_eq!(account.total, Decimal::ZERO);
-        assert_eq!(account.locked, false);
+        assert!(!account.locked);
     }
 
     #[test]
@@ -305,6 +937,68 @@ mod tests {
         assert_eq!(account.available, dec!(50.00));
     }
 
+    #[test]
+    fn test_withdraw_within_credit_limit() {
+        let limits = Limits {
+            credit_limit: Some(dec!(100.00)),
+            ..Limits::default()
+        };
+        let mut account = Account::with_limits(1, limits);
+        account.deposit(dec!(50.00));
+
+        assert!(account.withdraw(dec!(120.00)).is_ok());
+        assert_eq!(account.available, dec!(-70.00));
+        assert_eq!(account.total, dec!(-70.00));
+        assert_eq!(account.credit_used(), dec!(70.00));
+
+        // Only 30.00 of credit left
+        assert!(account.withdraw(dec!(30.01)).is_err());
+        assert!(account.withdraw(dec!(30.00)).is_ok());
+        assert_eq!(account.credit_used(), dec!(100.00));
+    }
+
+    #[test]
+    fn test_locked_account_has_no_credit() {
+        let limits = Limits {
+            credit_limit: Some(dec!(100.00)),
+            ..Limits::default()
+        };
+        let mut account = Account::with_limits(1, limits);
+        account.deposit(dec!(50.00));
+        account.dispute(dec!(50.00));
+        account.chargeback(dec!(50.00));
+
+        assert!(account.locked);
+        assert_eq!(account.credit_limit(), Decimal::ZERO);
+        assert!(account.withdraw(dec!(10.00)).is_err());
+        assert_eq!(account.credit_used(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_disputed_funds_are_not_credit() {
+        let limits = Limits {
+            credit_limit: Some(dec!(100.00)),
+            ..Limits::default()
+        };
+        let mut account = Account::with_limits(1, limits);
+        account.deposit(dec!(50.00));
+        assert!(account.withdraw(dec!(40.00)).is_ok());
+        account.dispute(dec!(50.00));
+        assert_eq!(account.available, dec!(-40.00));
+        assert_eq!(account.credit_used(), Decimal::ZERO);
+
+        // Only the part below zero at withdrawal time is drawn on the credit line
+        assert!(account.withdraw(dec!(30.00)).is_ok());
+        assert_eq!(account.credit_used(), dec!(30.00));
+
+        // Releasing the dispute pays off the credit
+        account.resolve(dec!(50.00));
+        assert_eq!(account.available, dec!(-20.00));
+        assert_eq!(account.credit_used(), dec!(20.00));
+        account.deposit(dec!(25.00));
+        assert_eq!(account.credit_used(), Decimal::ZERO);
+    }
+
     #[test]
     fn test_dispute() {
         let mut account = Account::new(1);
@@ -338,7 +1032,7 @@ mod tests {
         assert_eq!(account.available, dec!(70.00));
         assert_eq!(account.held, Decimal::ZERO);
         assert_eq!(account.total, dec!(70.00));
-        assert_eq!(account.locked, true);
+        assert!(account.locked);
     }
 
     #[tokio::test]
@@ -353,17 +1047,106 @@ mod tests {
         let deposit2 = Transaction::Deposit(MoneyTransaction::new(2, 2, dec!(200.00)).unwrap());
         manager.process_transaction(deposit2).await.unwrap();
 
-        assert_eq!(manager.total_accounts().await, 2);
+        assert_eq!(manager.total_accounts().await.unwrap(), 2);
         assert_eq!(
-            manager.get_account(1).await.unwrap().available,
+            manager.get_account(1).await.unwrap().unwrap().available,
             dec!(100.00)
         );
         assert_eq!(
-            manager.get_account(2).await.unwrap().available,
+            manager.get_account(2).await.unwrap().unwrap().available,
             dec!(200.00)
         );
     }
 
+    #[tokio::test]
+    async fn test_account_manager_get_transaction() {
+        use crate::transaction::Transaction;
+
+        let manager = AccountManager::new();
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(10.00)).unwrap(),
+            Transaction::deposit(1, 2, dec!(5.00)).unwrap(),
+            Transaction::dispute(1, 2),
+        ] {
+            manager.process_transaction(transaction).await.unwrap();
+        }
+
+        match manager.get_transaction(1, 2).await.unwrap() {
+            Some(Transaction::Deposit(money_tx)) => {
+                assert_eq!(money_tx.amount, dec!(5.00));
+                assert!(money_tx.is_disputed());
+            }
+            other => panic!("Expected a deposit, got {:?}", other),
+        }
+        assert!(manager.get_transaction(1, 3).await.unwrap().is_none());
+        assert!(manager.get_transaction(2, 1).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_account_manager_balances() {
+        use crate::transaction::Transaction;
+
+        let manager = AccountManager::new();
+        for (client, tx) in [(3, 3), (1, 1), (2, 2)] {
+            let deposit = Transaction::deposit(client, tx, dec!(10.00)).unwrap();
+            manager.process_transaction(deposit).await.unwrap();
+        }
+        manager
+            .process_transaction(Transaction::dispute(2, 2))
+            .await
+            .unwrap();
+
+        let balances = manager.balances().await.unwrap();
+        let clients: Vec<_> = balances.iter().map(|balance| balance.client).collect();
+        assert_eq!(clients, vec![1, 2, 3]);
+        assert_eq!(balances[1].held, dec!(10.00));
+        assert_eq!(manager.get_balance(2).await.unwrap(), Some(balances[1]));
+        assert_eq!(manager.get_balance(4).await.unwrap(), None);
+
+        let ledger_sizes = manager
+            .with_accounts(|accounts| {
+                accounts
+                    .map(|account| account.unwrap().ledger.history().count())
+                    .sum::<usize>()
+            })
+            .await;
+        assert_eq!(ledger_sizes, 4);
+    }
+
+    #[tokio::test]
+    async fn test_account_manager_process_batch() {
+        use crate::transaction::Transaction;
+
+        let transactions = vec![
+            Transaction::deposit(1, 1, dec!(10.00)).unwrap(),
+            Transaction::withdrawal(1, 2, dec!(15.00)).unwrap(),
+            Transaction::dispute(1, 1),
+            Transaction::dispute(1, 1),
+            Transaction::deposit(2, 3, dec!(5.00)).unwrap(),
+        ];
+
+        let one_by_one = AccountManager::new();
+        let mut expected = Vec::new();
+        for transaction in transactions.clone() {
+            expected.push(one_by_one.process_transaction(transaction).await);
+        }
+
+        let batched = AccountManager::new();
+        let results = batched.process_batch(transactions).await;
+        assert_eq!(results.len(), expected.len());
+        for (result, expected) in results.iter().zip(&expected) {
+            match (result, expected) {
+                (Ok(outcome), Ok(expected)) => assert_eq!(outcome, expected),
+                (Err(e), Err(expected)) => assert_eq!(e.to_string(), expected.to_string()),
+                _ => panic!("Expected {:?}, got {:?}", expected, result),
+            }
+        }
+        assert_eq!(
+            batched.balances().await.unwrap(),
+            one_by_one.balances().await.unwrap()
+        );
+    }
+
     #[test]
     fn test_resolve_without_dispute() {
         use crate::transaction::{ClientTransaction, MoneyTransaction, Transaction};
@@ -419,17 +1202,56 @@ mod tests {
         // First dispute - should succeed
         let dispute1 = Transaction::Dispute(ClientTransaction::new(1, 1));
         let result1 = account.process_transaction(dispute1);
-        assert!(result1.is_ok());
+        assert_eq!(result1.unwrap(), ProcessOutcome::Applied);
 
-        // Second dispute on same transaction - should fail
+        // Second dispute on same transaction is a replay and holds nothing more
         let dispute2 = Transaction::Dispute(ClientTransaction::new(1, 1));
         let result2 = account.process_transaction(dispute2);
 
-        assert!(result2.is_err());
-        assert_eq!(
-            result2.unwrap_err().to_string(),
-            "Transaction is already under dispute"
-        );
+        assert_eq!(result2.unwrap(), ProcessOutcome::Replayed);
+        assert_eq!(account.held, dec!(100.00));
+        assert_eq!(account.available, dec!(0.00));
+    }
+
+    #[test]
+    fn test_replayed_resolve_and_chargeback() {
+        use crate::transaction::{ClientTransaction, MoneyTransaction, Transaction};
+
+        let mut account = Account::new(1);
+        for tx in 1..=2 {
+            let deposit = Transaction::Deposit(MoneyTransaction::new(1, tx, dec!(10.00)).unwrap());
+            account.process_transaction(deposit).unwrap();
+        }
+
+        // The whole dispute lifecycle of tx 1 and tx 2, sent twice
+        let rows = [
+            Transaction::Dispute(ClientTransaction::new(1, 1)),
+            Transaction::Resolve(ClientTransaction::new(1, 1)),
+            Transaction::Dispute(ClientTransaction::new(1, 2)),
+            Transaction::Chargeback(ClientTransaction::new(1, 2)),
+        ];
+        for row in rows.clone() {
+            assert_eq!(
+                account.process_transaction(row).unwrap(),
+                ProcessOutcome::Applied
+            );
+        }
+
+        // The resolved tx 1 can legitimately be disputed again, so only resolve is a replay
+        let outcomes: Vec<_> = rows[1..]
+            .iter()
+            .map(|row| account.process_transaction(row.clone()).unwrap())
+            .collect();
+        assert_eq!(outcomes, vec![ProcessOutcome::Replayed; 3]);
+
+        assert_eq!(account.available, dec!(10.00));
+        assert_eq!(account.held, dec!(0.00));
+        assert_eq!(account.total, dec!(10.00));
+        assert!(account.locked);
+
+        // A resolve of a charged-back transaction was never applied, so it is still an error
+        let resolve = Transaction::Resolve(ClientTransaction::new(1, 2));
+        assert!(account.process_transaction(resolve).is_err());
     }
 
     #[test]
@@ -449,14 +1271,14 @@ mod tests {
         account.process_transaction(dispute).unwrap();
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(100.00));
-        assert!(account.ledger.is_disputed(1));
+        assert!(account.ledger.is_disputed(1).unwrap());
 
         // Resolve the dispute
         let resolve = Transaction::Resolve(ClientTransaction::new(1, 1));
         account.process_transaction(resolve).unwrap();
         assert_eq!(account.available, dec!(100.00));
         assert_eq!(account.held, dec!(0.00));
-        assert!(!account.ledger.is_disputed(1));
+        assert!(!account.ledger.is_disputed(1).unwrap());
     }
 
     #[test]
@@ -478,7 +1300,7 @@ mod tests {
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(100.00));
         assert_eq!(account.total, dec!(100.00));
-        assert!(account.ledger.is_disputed(1));
+        assert!(account.ledger.is_disputed(1).unwrap());
 
         // Chargeback the dispute
         let chargeback = Transaction::Chargeback(ClientTransaction::new(1, 1));
@@ -486,8 +1308,8 @@ mod tests {
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(0.00));
         assert_eq!(account.total, dec!(0.00));
-        assert_eq!(account.locked, true);
-        assert!(!account.ledger.is_disputed(1));
+        assert!(account.locked);
+        assert!(!account.ledger.is_disputed(1).unwrap());
     }
 
     #[test]
@@ -583,19 +1405,19 @@ mod tests {
         account.process_transaction(deposit).unwrap();
 
         // Transaction should not be chargedback initially
-        assert!(!account.ledger.is_chargedback(1));
+        assert!(!account.ledger.is_chargedback(1).unwrap());
 
         // Dispute the transaction
         let dispute = Transaction::Dispute(ClientTransaction::new(1, 1));
         account.process_transaction(dispute).unwrap();
-        assert!(!account.ledger.is_chargedback(1));
+        assert!(!account.ledger.is_chargedback(1).unwrap());
 
         let chargeback = Transaction::Chargeback(ClientTransaction::new(1, 1));
         account.process_transaction(chargeback).unwrap();
 
         // Transaction should now be marked as chargedback
-        assert!(account.ledger.is_chargedback(1));
-        assert_eq!(account.locked, true);
+        assert!(account.ledger.is_chargedback(1).unwrap());
+        assert!(account.locked);
     }
 
     #[test]
@@ -630,7 +1452,7 @@ mod tests {
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(225.00));
         assert_eq!(account.total, dec!(225.00));
-        assert_eq!(account.locked, false);
+        assert!(!account.locked);
 
         // First chargeback - locks the account
         let chargeback1 = Transaction::Chargeback(ClientTransaction::new(1, 1));
@@ -639,8 +1461,8 @@ mod tests {
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(125.00)); // 225 - 100
         assert_eq!(account.total, dec!(125.00)); // 225 - 100
-        assert_eq!(account.locked, true);
-        assert!(account.ledger.is_chargedback(1));
+        assert!(account.locked);
+        assert!(account.ledger.is_chargedback(1).unwrap());
 
         // Second chargeback - should still work even though account is locked
         let chargeback2 = Transaction::Chargeback(ClientTransaction::new(1, 2));
@@ -653,8 +1475,8 @@ mod tests {
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(75.00)); // 125 - 50
         assert_eq!(account.total, dec!(75.00)); // 125 - 50
-        assert_eq!(account.locked, true);
-        assert!(account.ledger.is_chargedback(2));
+        assert!(account.locked);
+        assert!(account.ledger.is_chargedback(2).unwrap());
 
         // Third chargeback - should also work
         let chargeback3 = Transaction::Chargeback(ClientTransaction::new(1, 3));
@@ -667,8 +1489,8 @@ mod tests {
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(0.00)); // 75 - 75
         assert_eq!(account.total, dec!(0.00)); // 75 - 75
-        assert_eq!(account.locked, true);
-        assert!(account.ledger.is_chargedback(3));
+        assert!(account.locked);
+        assert!(account.ledger.is_chargedback(3).unwrap());
     }
 
     #[test]
@@ -687,7 +1509,7 @@ mod tests {
         let chargeback1 = Transaction::Chargeback(ClientTransaction::new(1, 1));
         account.process_transaction(chargeback1).unwrap();
 
-        assert_eq!(account.locked, true);
+        assert!(account.locked);
 
         // Try to process a new deposit - should fail
         let deposit2 = Transaction::Deposit(MoneyTransaction::new(1, 2, dec!(50.00)).unwrap());
@@ -713,17 +1535,17 @@ mod tests {
         account.process_transaction(deposit).unwrap();
 
         // Initially should not be disputed
-        assert!(!account.ledger.is_disputed(1));
+        assert!(!account.ledger.is_disputed(1).unwrap());
 
         // After disputing, should return true
         let dispute = Transaction::Dispute(ClientTransaction::new(1, 1));
         account.process_transaction(dispute).unwrap();
-        assert!(account.ledger.is_disputed(1));
+        assert!(account.ledger.is_disputed(1).unwrap());
 
         // After resolving, should not be disputed
         let resolve = Transaction::Resolve(ClientTransaction::new(1, 1));
         account.process_transaction(resolve).unwrap();
-        assert!(!account.ledger.is_disputed(1));
+        assert!(!account.ledger.is_disputed(1).unwrap());
     }
 
     #[test]
@@ -737,17 +1559,17 @@ mod tests {
         account.process_transaction(deposit).unwrap();
 
         // Initially should not be chargedback
-        assert!(!account.ledger.is_chargedback(1));
+        assert!(!account.ledger.is_chargedback(1).unwrap());
 
         // Dispute the transaction
         let dispute = Transaction::Dispute(ClientTransaction::new(1, 1));
         account.process_transaction(dispute).unwrap();
-        assert!(!account.ledger.is_chargedback(1));
+        assert!(!account.ledger.is_chargedback(1).unwrap());
 
         // After chargeback, should return true
         let chargeback = Transaction::Chargeback(ClientTransaction::new(1, 1));
         account.process_transaction(chargeback).unwrap();
-        assert!(account.ledger.is_chargedback(1));
+        assert!(account.ledger.is_chargedback(1).unwrap());
     }
 
     #[test]
@@ -755,8 +1577,279 @@ mod tests {
         let account = Account::new(1);
 
         // For a non-existent transaction, both should return false
-        assert!(!account.ledger.is_disputed(999));
-        assert!(!account.ledger.is_chargedback(999));
+        assert!(!account.ledger.is_disputed(999).unwrap());
+        assert!(!account.ledger.is_chargedback(999).unwrap());
+    }
+
+    #[test]
+    fn test_reversal_of_deposit() {
+        use crate::transaction::{ClientTransaction, MoneyTransaction, Transaction};
+
+        let mut account = Account::new(1);
+
+        let deposit1 = Transaction::Deposit(MoneyTransaction::new(1, 1, dec!(100.00)).unwrap());
+        account.process_transaction(deposit1).unwrap();
+        let deposit2 = Transaction::Deposit(MoneyTransaction::new(1, 2, dec!(40.00)).unwrap());
+        account.process_transaction(deposit2).unwrap();
+
+        let reversal = Transaction::Reversal(ClientTransaction::new(1, 2));
+        account.process_transaction(reversal).unwrap();
+
+        assert_eq!(account.available, dec!(100.00));
+        assert_eq!(account.held, dec!(0.00));
+        assert_eq!(account.total, dec!(100.00));
+        assert!(!account.locked);
+        assert!(account.ledger.is_reversed(2).unwrap());
+
+        // A reversed transaction cannot be disputed or reversed again
+        let dispute = Transaction::Dispute(ClientTransaction::new(1, 2));
+        let result = account.process_transaction(dispute);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Cannot dispute a reversed transaction"
+        );
+
+        let reversal = Transaction::Reversal(ClientTransaction::new(1, 2));
+        let result = account.process_transaction(reversal);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Transaction has already been reversed"
+        );
+        assert_eq!(account.available, dec!(100.00));
+    }
+
+    #[test]
+    fn test_reversal_of_withdrawal() {
+        use crate::transaction::{ClientTransaction, MoneyTransaction, Transaction};
+
+        let mut account = Account::new(1);
+
+        let deposit = Transaction::Deposit(MoneyTransaction::new(1, 1, dec!(100.00)).unwrap());
+        account.process_transaction(deposit).unwrap();
+        let withdrawal = Transaction::Withdrawal(MoneyTransaction::new(1, 2, dec!(30.00)).unwrap());
+        account.process_transaction(withdrawal).unwrap();
+
+        let reversal = Transaction::Reversal(ClientTransaction::new(1, 2));
+        account.process_transaction(reversal).unwrap();
+
+        assert_eq!(account.available, dec!(100.00));
+        assert_eq!(account.total, dec!(100.00));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn test_reversal_of_spent_deposit_rejected() {
+        use crate::transaction::Transaction;
+
+        let mut account = Account::new(1);
+        account
+            .process_transaction(Transaction::deposit(1, 1, dec!(100.00)).unwrap())
+            .unwrap();
+        account
+            .process_transaction(Transaction::withdrawal(1, 2, dec!(80.00)).unwrap())
+            .unwrap();
+
+        let result = account.process_transaction(Transaction::reversal(1, 1));
+        assert_eq!(result.unwrap_err().to_string(), "Insufficient funds");
+        assert_eq!(account.available, dec!(20.00));
+        assert_eq!(account.total, dec!(20.00));
+        assert_eq!(account.credit_used(), dec!(0));
+        assert!(!account.ledger.is_reversed(1).unwrap());
+    }
+
+    #[test]
+    fn test_reversal_of_spent_deposit_draws_credit() {
+        use crate::transaction::Transaction;
+
+        let limits = Limits {
+            credit_limit: Some(dec!(100.00)),
+            ..Limits::default()
+        };
+        let mut account = Account::with_limits(1, limits);
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(100.00)).unwrap(),
+            Transaction::withdrawal(1, 2, dec!(80.00)).unwrap(),
+            Transaction::reversal(1, 1),
+        ] {
+            account.process_transaction(transaction).unwrap();
+        }
+        assert_eq!(account.available, dec!(-80.00));
+        assert_eq!(account.total, dec!(-80.00));
+        assert_eq!(account.credit_used(), dec!(80.00));
+        assert!(account.ledger.is_reversed(1).unwrap());
+
+        // Only 20.00 of the line is left
+        let result =
+            account.process_transaction(Transaction::withdrawal(1, 3, dec!(20.01)).unwrap());
+        assert_eq!(result.unwrap_err().to_string(), "Insufficient funds");
+
+        account
+            .process_transaction(Transaction::deposit(1, 4, dec!(30.00)).unwrap())
+            .unwrap();
+        assert_eq!(account.credit_used(), dec!(50.00));
+
+        let rebuilt = rebuild::replay(account.client, &account.ledger, &account.dispute_policy);
+        assert!(rebuilt.unwrap().compare(&account).is_empty());
+    }
+
+    #[test]
+    fn test_reversal_of_disputed_transaction_rejected() {
+        use crate::transaction::{ClientTransaction, MoneyTransaction, Transaction};
+
+        let mut account = Account::new(1);
+
+        let deposit = Transaction::Deposit(MoneyTransaction::new(1, 1, dec!(100.00)).unwrap());
+        account.process_transaction(deposit).unwrap();
+        let dispute = Transaction::Dispute(ClientTransaction::new(1, 1));
+        account.process_transaction(dispute).unwrap();
+
+        let reversal = Transaction::Reversal(ClientTransaction::new(1, 1));
+        let result = account.process_transaction(reversal);
+        assert!(result.is_err());
+        assert_eq!(account.held, dec!(100.00));
+        assert!(account.ledger.is_disputed(1).unwrap());
+
+        let missing = Transaction::Reversal(ClientTransaction::new(1, 99));
+        let result = account.process_transaction(missing);
+        assert_eq!(result.unwrap_err().to_string(), "Transaction not found");
+    }
+
+    #[test]
+    fn test_max_disputes_per_transaction() {
+        use crate::transaction::{ClientTransaction, MoneyTransaction, Transaction};
+
+        let mut account = Account::new(1);
+        account.dispute_policy = DisputePolicy {
+            max_disputes: Some(2),
+            ..DisputePolicy::default()
+        };
+
+        let deposit = Transaction::Deposit(MoneyTransaction::new(1, 1, dec!(100.00)).unwrap());
+        account.process_transaction(deposit).unwrap();
+
+        for _ in 0..2 {
+            let dispute = Transaction::Dispute(ClientTransaction::new(1, 1));
+            account.process_transaction(dispute).unwrap();
+            let resolve = Transaction::Resolve(ClientTransaction::new(1, 1));
+            account.process_transaction(resolve).unwrap();
+        }
+
+        // Third dispute exceeds the policy
+        let dispute = Transaction::Dispute(ClientTransaction::new(1, 1));
+        let result = account.process_transaction(dispute);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Transaction has reached the maximum of 2 disputes"
+        );
+        assert_eq!(account.available, dec!(100.00));
+        assert_eq!(account.held, dec!(0.00));
+
+        if let Some(Transaction::Deposit(money_tx)) =
+            account.ledger.get_transaction(1).unwrap().as_deref()
+        {
+            assert!(money_tx.is_resolved());
+            assert_eq!(money_tx.dispute_count, 2);
+            assert_eq!(money_tx.dispute_history.len(), 4);
+        } else {
+            panic!("Expected Deposit transaction");
+        }
+    }
+
+    #[test]
+    fn test_deposit_over_limit_rejected() {
+        use crate::transaction::{MoneyTransaction, Transaction};
+
+        let limits = Limits {
+            max_deposit: Some(dec!(100.00)),
+            ..Limits::default()
+        };
+        let mut account = Account::with_limits(1, limits);
+
+        let deposit = Transaction::Deposit(MoneyTransaction::new(1, 1, dec!(150.00)).unwrap());
+        let result = account.process_transaction(deposit);
+
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Deposit of 150.00 exceeds the maximum of 100.00"
+        );
+        assert_eq!(account.available, Decimal::ZERO);
+        assert!(account.ledger.get_transaction(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_daily_withdrawal_cap_rejected() {
+        use crate::transaction::{MoneyTransaction, Transaction};
+
+        let limits = Limits {
+            daily_withdrawal_cap: Some(dec!(100.00)),
+            ..Limits::default()
+        };
+        let mut account = Account::with_limits(1, limits);
+
+        let deposit = Transaction::Deposit(MoneyTransaction::new(1, 1, dec!(500.00)).unwrap());
+        account.process_transaction(deposit).unwrap();
+
+        let withdrawal1 =
+            Transaction::Withdrawal(MoneyTransaction::new(1, 2, dec!(80.00)).unwrap());
+        account.process_transaction(withdrawal1).unwrap();
+
+        // Second withdrawal would push the day's total over the cap
+        let withdrawal2 =
+            Transaction::Withdrawal(MoneyTransaction::new(1, 3, dec!(30.00)).unwrap());
+        let result = account.process_transaction(withdrawal2);
+
+        assert!(result.is_err());
+        assert_eq!(account.available, dec!(420.00));
+        assert!(account.ledger.get_transaction(3).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_insufficient_funds_does_not_count_toward_limits() {
+        use crate::transaction::{MoneyTransaction, Transaction};
+
+        let limits = Limits {
+            max_transactions: Some(2),
+            ..Limits::default()
+        };
+        let mut account = Account::with_limits(1, limits);
+
+        let withdrawal = Transaction::Withdrawal(MoneyTransaction::new(1, 1, dec!(10.00)).unwrap());
+        assert!(account.process_transaction(withdrawal).is_err());
+
+        let deposit1 = Transaction::Deposit(MoneyTransaction::new(1, 2, dec!(10.00)).unwrap());
+        account.process_transaction(deposit1).unwrap();
+        let deposit2 = Transaction::Deposit(MoneyTransaction::new(1, 3, dec!(10.00)).unwrap());
+        account.process_transaction(deposit2).unwrap();
+
+        let deposit3 = Transaction::Deposit(MoneyTransaction::new(1, 4, dec!(10.00)).unwrap());
+        let result = account.process_transaction(deposit3);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Transaction limit of 2 per 86400s exceeded"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_account_manager_applies_client_limits() {
+        use crate::transaction::{MoneyTransaction, Transaction};
+
+        let mut config = LimitsConfig::default();
+        config.overrides.insert(
+            ClientId::new(2),
+            Limits {
+                max_deposit: Some(dec!(10.00)),
+                ..Limits::default()
+            },
+        );
+        let manager = AccountManager::with_limits(config);
+
+        let deposit1 = Transaction::Deposit(MoneyTransaction::new(1, 1, dec!(100.00)).unwrap());
+        assert!(manager.process_transaction(deposit1).await.is_ok());
+
+        let deposit2 = Transaction::Deposit(MoneyTransaction::new(2, 2, dec!(100.00)).unwrap());
+        assert!(manager.process_transaction(deposit2).await.is_err());
     }
 
     #[test]
@@ -776,14 +1869,14 @@ mod tests {
         account.process_transaction(dispute1).unwrap();
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(100.00));
-        assert!(account.ledger.is_disputed(1));
+        assert!(account.ledger.is_disputed(1).unwrap());
 
         // Resolve the dispute
         let resolve = Transaction::Resolve(ClientTransaction::new(1, 1));
         account.process_transaction(resolve).unwrap();
         assert_eq!(account.available, dec!(100.00));
         assert_eq!(account.held, dec!(0.00));
-        assert!(!account.ledger.is_disputed(1));
+        assert!(!account.ledger.is_disputed(1).unwrap());
 
         // Dispute again - should be allowed after resolution
         let dispute2 = Transaction::Dispute(ClientTransaction::new(1, 1));
@@ -795,6 +1888,6 @@ mod tests {
 
         assert_eq!(account.available, dec!(0.00));
         assert_eq!(account.held, dec!(100.00));
-        assert!(account.ledger.is_disputed(1));
+        assert!(account.ledger.is_disputed(1).unwrap());
     }
 }
@@ -0,0 +1,533 @@
+use crate::csv::{DEFAULT_BATCH_SIZE, DEFAULT_CHUNK_SIZE, ErrorPolicy, ParallelConfig};
+use crate::engine::{DEFAULT_CHANNEL_CAPACITY, EngineBuilder};
+use crate::limits::LimitsConfig;
+use crate::transaction::{AmountPolicy, DisputePolicy};
+use flexi_logger::LogSpecification;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Environment variable naming the config file when no `--config` flag is given
+pub const CONFIG_ENV: &str = "TRANSACTIONS_CONFIG";
+
+/// Prefix of environment variables that override settings, e.g.
+/// `TRANSACTIONS_CONCURRENCY_BATCH_SIZE` for `concurrency.batch_size`
+pub const ENV_PREFIX: &str = "TRANSACTIONS_";
+
+/// Every dotted key accepted by [`Config::set`]
+const KEYS: &[&str] = &[
+    "logging.level",
+    "concurrency.channel_capacity",
+    "concurrency.batch_size",
+    "concurrency.parse_workers",
+    "concurrency.chunk_size",
+    "concurrency.ack_buffer",
+    "disputes.max_disputes",
+    "disputes.lock_on_chargeback",
+    "amounts.min_amount",
+    "amounts.max_decimal_places",
+    "errors.malformed_rows",
+    "limits.file",
+    "io.delimiter",
+    "io.disputes_report",
+    "io.export_sqlite",
+];
+
+/// Largest number of decimal places a `Decimal` can hold
+const MAX_DECIMAL_PLACES: u32 = 28;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownKey(String),
+    /// A setting that has the wrong type or is out of range
+    Invalid {
+        key: String,
+        message: String,
+    },
+}
+
+impl ConfigError {
+    fn invalid(key: &str, message: impl fmt::Display) -> Self {
+        ConfigError::Invalid {
+            key: key.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => {
+                write!(f, "Error reading config file {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "Error parsing config file {}: {}", path.display(), e)
+            }
+            ConfigError::UnknownKey(key) => write!(f, "Unknown config key: {}", key),
+            ConfigError::Invalid { key, message } => write!(f, "Invalid {}: {}", key, message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// A flexi_logger spec such as `info` or `warn, transactions::engine=debug`
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    /// Batches the channel between the CSV reader and the processor holds
+    pub channel_capacity: usize,
+    /// Transactions per batch
+    pub batch_size: usize,
+    /// Threads parsing one CSV file, `None` to parse on the reader task
+    pub parse_workers: Option<usize>,
+    /// Bytes per chunk when `parse_workers` is set
+    pub chunk_size: usize,
+    /// Acks the gRPC and line-protocol servers buffer per connection
+    pub ack_buffer: usize,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            batch_size: DEFAULT_BATCH_SIZE,
+            parse_workers: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            ack_buffer: DEFAULT_CHANNEL_CAPACITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputesConfig {
+    pub max_disputes: Option<u32>,
+    pub lock_on_chargeback: bool,
+}
+
+impl Default for DisputesConfig {
+    fn default() -> Self {
+        let policy = DisputePolicy::default();
+        Self {
+            max_disputes: policy.max_disputes,
+            lock_on_chargeback: policy.lock_on_chargeback,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountsConfig {
+    pub min_amount: Option<Decimal>,
+    pub max_decimal_places: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorsConfig {
+    pub malformed_rows: ErrorPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsFileConfig {
+    /// CSV file read by `LimitsConfig::from_path`
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IoConfig {
+    /// Field separator of CSV input
+    pub delimiter: char,
+    pub disputes_report: Option<PathBuf>,
+    pub export_sqlite: Option<PathBuf>,
+}
+
+impl Default for IoConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            disputes_report: None,
+            export_sqlite: None,
+        }
+    }
+}
+
+/// Settings read from a TOML file, then overridden by environment variables and
+/// command-line flags.
+///
+/// Every section and key is optional. Unknown keys are rejected so a typo does
+/// not silently fall back to a default.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub disputes: DisputesConfig,
+    pub amounts: AmountsConfig,
+    pub errors: ErrorsConfig,
+    pub limits: LimitsFileConfig,
+    pub io: IoConfig,
+}
+
+impl Config {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents =
+            fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Set one setting by its dotted key, such as `concurrency.batch_size`,
+    /// parsing `value` the way the matching flag or environment variable would
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "logging.level" => self.logging.level = value.to_string(),
+            "concurrency.channel_capacity" => {
+                self.concurrency.channel_capacity = parse(key, value)?
+            }
+            "concurrency.batch_size" => self.concurrency.batch_size = parse(key, value)?,
+            "concurrency.parse_workers" => {
+                self.concurrency.parse_workers = Some(parse(key, value)?)
+            }
+            "concurrency.chunk_size" => self.concurrency.chunk_size = parse(key, value)?,
+            "concurrency.ack_buffer" => self.concurrency.ack_buffer = parse(key, value)?,
+            "disputes.max_disputes" => self.disputes.max_disputes = Some(parse(key, value)?),
+            "disputes.lock_on_chargeback" => self.disputes.lock_on_chargeback = parse(key, value)?,
+            "amounts.min_amount" => self.amounts.min_amount = Some(parse(key, value)?),
+            "amounts.max_decimal_places" => {
+                self.amounts.max_decimal_places = Some(parse(key, value)?)
+            }
+            "errors.malformed_rows" => self.errors.malformed_rows = parse(key, value)?,
+            "limits.file" => self.limits.file = Some(value.into()),
+            "io.delimiter" => self.io.delimiter = parse(key, value)?,
+            "io.disputes_report" => self.io.disputes_report = Some(value.into()),
+            "io.export_sqlite" => self.io.export_sqlite = Some(value.into()),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Apply `TRANSACTIONS_<SECTION>_<KEY>` variables from `vars`, usually
+    /// `std::env::vars()`. Other `TRANSACTIONS_*` variables may belong to
+    /// something else in the environment, so they are left alone and their
+    /// names returned for the caller to warn about. `TRANSACTIONS_CONFIG`
+    /// itself is not a setting.
+    pub fn apply_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Vec<String>, ConfigError> {
+        let mut ignored = Vec::new();
+        for (name, value) in vars {
+            if name == CONFIG_ENV || !name.starts_with(ENV_PREFIX) {
+                continue;
+            }
+            match KEYS.iter().find(|key| env_name(key) == name) {
+                Some(key) => self.set(key, &value)?,
+                None => ignored.push(name),
+            }
+        }
+        Ok(ignored)
+    }
+
+    /// Check settings that parse but cannot be used, such as a zero batch size or
+    /// a limits file with a negative cap
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Err(e) = LogSpecification::parse(&self.logging.level) {
+            return Err(ConfigError::invalid("logging.level", e));
+        }
+
+        for (key, value) in [
+            (
+                "concurrency.channel_capacity",
+                self.concurrency.channel_capacity,
+            ),
+            ("concurrency.batch_size", self.concurrency.batch_size),
+            ("concurrency.chunk_size", self.concurrency.chunk_size),
+            ("concurrency.ack_buffer", self.concurrency.ack_buffer),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::invalid(key, "must be at least 1"));
+            }
+        }
+        if self.concurrency.parse_workers == Some(0) {
+            return Err(ConfigError::invalid(
+                "concurrency.parse_workers",
+                "must be at least 1",
+            ));
+        }
+
+        if let Some(min) = self.amounts.min_amount
+            && min <= Decimal::ZERO
+        {
+            return Err(ConfigError::invalid(
+                "amounts.min_amount",
+                format!("must be positive, got {}", min),
+            ));
+        }
+        if let Some(places) = self.amounts.max_decimal_places
+            && places > MAX_DECIMAL_PLACES
+        {
+            return Err(ConfigError::invalid(
+                "amounts.max_decimal_places",
+                format!("must be at most {}, got {}", MAX_DECIMAL_PLACES, places),
+            ));
+        }
+
+        let delimiter = self.io.delimiter;
+        if !delimiter.is_ascii() || matches!(delimiter, '"' | '\n' | '\r') {
+            return Err(ConfigError::invalid(
+                "io.delimiter",
+                format!("{:?} cannot separate CSV fields", delimiter),
+            ));
+        }
+
+        self.limits()?;
+        Ok(())
+    }
+
+    /// Load the limits file, or default limits if none is configured
+    pub fn limits(&self) -> Result<LimitsConfig, ConfigError> {
+        match &self.limits.file {
+            Some(path) => LimitsConfig::from_path(path).map_err(|e| {
+                ConfigError::invalid("limits.file", format!("{}: {}", path.display(), e))
+            }),
+            None => Ok(LimitsConfig::default()),
+        }
+    }
+
+    /// An engine builder with every policy and concurrency setting applied.
+    ///
+    /// `limits` are passed in rather than loaded here so the caller can inspect them,
+    /// e.g. to decide whether to report credit usage.
+    pub fn engine_builder(&self, limits: LimitsConfig) -> EngineBuilder {
+        let mut builder = EngineBuilder::default()
+            .limits(limits)
+            .dispute_policy(DisputePolicy {
+                max_disputes: self.disputes.max_disputes,
+                lock_on_chargeback: self.disputes.lock_on_chargeback,
+            })
+            .amount_policy(AmountPolicy {
+                min_amount: self.amounts.min_amount,
+                max_decimal_places: self.amounts.max_decimal_places,
+            })
+            .channel_capacity(self.concurrency.channel_capacity)
+            .batch_size(self.concurrency.batch_size)
+            .delimiter(self.io.delimiter as u8)
+            .on_malformed_row(self.errors.malformed_rows);
+        if let Some(workers) = self.concurrency.parse_workers {
+            builder = builder.parallel_parse(ParallelConfig {
+                workers,
+                chunk_size: self.concurrency.chunk_size,
+            });
+        }
+        builder
+    }
+}
+
+/// The environment variable overriding `key`, e.g. `TRANSACTIONS_IO_DELIMITER`
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| ConfigError::invalid(key, format!("{:?}: {}", value, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_file;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_config_file() {
+        let config: Config = toml::from_str(
+            r#"
+            [logging]
+            level = "warn"
+
+            [concurrency]
+            batch_size = 64
+            parse_workers = 4
+
+            [disputes]
+            max_disputes = 2
+            lock_on_chargeback = false
+
+            [amounts]
+            min_amount = "0.01"
+            max_decimal_places = 4
+
+            [errors]
+            malformed_rows = "skip"
+
+            [io]
+            delimiter = ";"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.logging.level, "warn");
+        assert_eq!(config.concurrency.batch_size, 64);
+        assert_eq!(config.concurrency.parse_workers, Some(4));
+        assert_eq!(
+            config.concurrency.channel_capacity,
+            DEFAULT_CHANNEL_CAPACITY
+        );
+        assert_eq!(config.disputes.max_disputes, Some(2));
+        assert!(!config.disputes.lock_on_chargeback);
+        assert_eq!(config.amounts.min_amount, Some(dec!(0.01)));
+        assert_eq!(config.errors.malformed_rows, ErrorPolicy::Skip);
+        assert_eq!(config.io.delimiter, ';');
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_empty_config_is_default() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config, Config::default());
+        assert!(config.validate().is_ok());
+        assert!(config.disputes.lock_on_chargeback);
+        assert_eq!(config.errors.malformed_rows, ErrorPolicy::Stop);
+    }
+
+    #[test]
+    fn test_example_config_lists_defaults() {
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        assert!(toml::from_str::<Config>("[concurrency]\nbatch = 1").is_err());
+        assert!(toml::from_str::<Config>("[network]\nport = 1").is_err());
+
+        let mut config = Config::default();
+        assert!(matches!(
+            config.set("concurrency.batch", "1"),
+            Err(ConfigError::UnknownKey(_))
+        ));
+        for key in KEYS {
+            assert!(
+                !matches!(config.set(key, "x"), Err(ConfigError::UnknownKey(_))),
+                "{}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut config = Config::default();
+        let ignored = config
+            .apply_env([
+                (
+                    "TRANSACTIONS_CONFIG".to_string(),
+                    "ignored.toml".to_string(),
+                ),
+                (
+                    "TRANSACTIONS_CONCURRENCY_BATCH_SIZE".to_string(),
+                    "32".to_string(),
+                ),
+                (
+                    "TRANSACTIONS_ERRORS_MALFORMED_ROWS".to_string(),
+                    "skip".to_string(),
+                ),
+                (
+                    "TRANSACTIONS_DB_URL".to_string(),
+                    "postgres://localhost".to_string(),
+                ),
+                (
+                    "TRANSACTIONS_CONCURRENCY_BATCH".to_string(),
+                    "1".to_string(),
+                ),
+                ("PATH".to_string(), "/usr/bin".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(config.concurrency.batch_size, 32);
+        assert_eq!(config.errors.malformed_rows, ErrorPolicy::Skip);
+        assert_eq!(
+            ignored,
+            ["TRANSACTIONS_DB_URL", "TRANSACTIONS_CONCURRENCY_BATCH"]
+        );
+
+        let error = config
+            .apply_env([(
+                "TRANSACTIONS_DISPUTES_MAX_DISPUTES".to_string(),
+                "-1".to_string(),
+            )])
+            .unwrap_err();
+        assert!(matches!(error, ConfigError::Invalid { .. }), "{}", error);
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid_key = |config: Config| match config.validate() {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("expected an invalid setting, got {:?}", other),
+        };
+
+        let mut config = Config::default();
+        config.concurrency.batch_size = 0;
+        assert_eq!(invalid_key(config), "concurrency.batch_size");
+
+        let mut config = Config::default();
+        config.concurrency.parse_workers = Some(0);
+        assert_eq!(invalid_key(config), "concurrency.parse_workers");
+
+        let mut config = Config::default();
+        config.amounts.min_amount = Some(Decimal::ZERO);
+        assert_eq!(invalid_key(config), "amounts.min_amount");
+
+        let mut config = Config::default();
+        config.amounts.max_decimal_places = Some(29);
+        assert_eq!(invalid_key(config), "amounts.max_decimal_places");
+
+        let mut config = Config::default();
+        config.io.delimiter = '"';
+        assert_eq!(invalid_key(config), "io.delimiter");
+
+        let mut config = Config::default();
+        config.logging.level = "info, transactions = loud".to_string();
+        assert_eq!(invalid_key(config), "logging.level");
+
+        let file = temp_file(
+            "client, max_deposit, max_withdrawal, daily_withdrawal_cap, max_transactions, period_secs\n\
+             7, , , , , -60\n",
+        );
+        let mut config = Config::default();
+        config.limits.file = Some(file.path().to_path_buf());
+        assert_eq!(invalid_key(config), "limits.file");
+    }
+}
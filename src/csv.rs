@@ -1,94 +1,657 @@
-use crate::transaction::{ClientTransaction, MoneyTransaction, Transaction};
+use crate::transaction::{
+    ClientId, ClientIdError, ClientTransaction, MoneyTransaction, Transaction,
+};
+use csv::ByteRecord;
+use log::warn;
 use rust_decimal::Decimal;
 use serde::Deserialize;
+use std::collections::VecDeque;
 use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
+use std::str::{self, FromStr};
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
-#[derive(Debug, Deserialize)]
-struct CsvRecord {
-    #[serde(rename = "type")]
-    tx_type: String,
-    client: u16,
-    tx: u32,
-    amount: Option<Decimal>,
+/// Number of transactions the reader sends over the channel at a time
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// What the file readers do with a row they cannot parse
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Stop reading at the row. Rows before it are still sent.
+    #[default]
+    Stop,
+    /// Report the row and carry on with the next one
+    Skip,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "stop" => Ok(ErrorPolicy::Stop),
+            "skip" => Ok(ErrorPolicy::Skip),
+            other => Err(format!(
+                "Unknown error policy: {} (expected stop or skip)",
+                other
+            )),
+        }
+    }
+}
+
+/// How `process_csv_with_channel` and `process_csv_parallel` read a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Transactions sent over the channel at a time
+    pub batch_size: usize,
+    /// Field separator
+    pub delimiter: u8,
+    pub on_error: ErrorPolicy,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            delimiter: b',',
+            on_error: ErrorPolicy::Stop,
+        }
+    }
+}
+
+/// The `type` column of an input row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Reversal,
+}
+
+impl TransactionType {
+    /// Parse a raw field without allocating, ignoring surrounding whitespace
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.trim_ascii() {
+            b"deposit" => Some(Self::Deposit),
+            b"withdrawal" => Some(Self::Withdrawal),
+            b"dispute" => Some(Self::Dispute),
+            b"resolve" => Some(Self::Resolve),
+            b"chargeback" => Some(Self::Chargeback),
+            b"reversal" => Some(Self::Reversal),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Reversal => "reversal",
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(s.as_bytes())
+            .ok_or_else(|| format!("Unknown transaction type: {}", s.trim()))
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One input row: `type, client, tx, amount`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvRecord {
+    pub tx_type: TransactionType,
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
 }
 
 impl CsvRecord {
-    fn into_transaction(self) -> Result<Transaction, String> {
-        let tx_type = self.tx_type.trim();
+    /// Parse the fields of `record` in place, without copying them into `String`s
+    fn from_byte_record(record: &ByteRecord, columns: &Columns) -> Result<Self, String> {
+        let field = |index: usize| record.get(index).unwrap_or_default();
+
+        let tx_type = field(columns.tx_type);
+        let tx_type = TransactionType::from_bytes(tx_type).ok_or_else(|| {
+            format!(
+                "Unknown transaction type: {}",
+                String::from_utf8_lossy(tx_type.trim_ascii())
+            )
+        })?;
+        let amount = match columns.amount.and_then(|index| record.get(index)) {
+            Some(bytes) if !bytes.trim_ascii().is_empty() => Some(parse_amount(bytes)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            tx_type,
+            client: parse_client(field(columns.client))?,
+            tx: parse_field(field(columns.tx), "tx")?,
+            amount,
+        })
+    }
 
-        match tx_type {
-            "deposit" => {
+    pub fn into_transaction(self) -> Result<Transaction, String> {
+        match self.tx_type {
+            TransactionType::Deposit => {
                 let amount = self.amount.ok_or("Deposit requires an amount")?;
                 let money_tx = MoneyTransaction::new(self.client, self.tx, amount)?;
                 Ok(Transaction::Deposit(money_tx))
             }
-            "withdrawal" => {
+            TransactionType::Withdrawal => {
                 let amount = self.amount.ok_or("Withdrawal requires an amount")?;
                 let money_tx = MoneyTransaction::new(self.client, self.tx, amount)?;
                 Ok(Transaction::Withdrawal(money_tx))
             }
-            "dispute" => Ok(Transaction::Dispute(ClientTransaction::new(
+            TransactionType::Dispute => Ok(Transaction::Dispute(ClientTransaction::new(
                 self.client,
                 self.tx,
             ))),
-            "resolve" => Ok(Transaction::Resolve(ClientTransaction::new(
+            TransactionType::Resolve => Ok(Transaction::Resolve(ClientTransaction::new(
                 self.client,
                 self.tx,
             ))),
-            "chargeback" => Ok(Transaction::Chargeback(ClientTransaction::new(
+            TransactionType::Chargeback => Ok(Transaction::Chargeback(ClientTransaction::new(
                 self.client,
                 self.tx,
             ))),
-            _ => Err(format!("Unknown transaction type: {}", tx_type)),
+            TransactionType::Reversal => Ok(Transaction::Reversal(ClientTransaction::new(
+                self.client,
+                self.tx,
+            ))),
+        }
+    }
+}
+
+fn parse_field<T: FromStr>(bytes: &[u8], name: &str) -> Result<T, String> {
+    str::from_utf8(bytes.trim_ascii())
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format!("Invalid {}: {:?}", name, String::from_utf8_lossy(bytes)))
+}
+
+fn parse_client(bytes: &[u8]) -> Result<ClientId, String> {
+    match str::from_utf8(bytes) {
+        Ok(s) => s.parse().map_err(|e: ClientIdError| e.to_string()),
+        Err(_) => {
+            Err(ClientIdError::Invalid(String::from_utf8_lossy(bytes).into_owned()).to_string())
+        }
+    }
+}
+
+fn parse_amount(bytes: &[u8]) -> Result<Decimal, String> {
+    str::from_utf8(bytes.trim_ascii())
+        .ok()
+        .and_then(|s| {
+            Decimal::from_str(s)
+                .or_else(|_| Decimal::from_scientific(s))
+                .ok()
+        })
+        .ok_or_else(|| format!("Invalid amount: {:?}", String::from_utf8_lossy(bytes)))
+}
+
+/// Positions of the `type, client, tx, amount` fields within a row
+#[derive(Debug, Clone, Copy)]
+struct Columns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            tx_type: 0,
+            client: 1,
+            tx: 2,
+            amount: Some(3),
+        }
+    }
+}
+
+impl Columns {
+    /// Look the fields up by name so the columns may come in any order
+    fn from_headers(headers: &ByteRecord) -> Result<Self, String> {
+        let find = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim_ascii() == name.as_bytes())
+        };
+        let required = |name: &str| find(name).ok_or_else(|| format!("Missing column: {}", name));
+
+        Ok(Self {
+            tx_type: required("type")?,
+            client: required("client")?,
+            tx: required("tx")?,
+            amount: find("amount"),
+        })
+    }
+}
+
+/// Parse transactions from any CSV source with a `type, client, tx, amount` header.
+///
+/// Rows are read into a single reused `ByteRecord`, so a valid row allocates
+/// nothing beyond the `Transaction` it produces.
+pub fn read_transactions<R: Read>(
+    reader: R,
+) -> impl Iterator<Item = Result<Transaction, Box<dyn Error + Send + Sync>>> {
+    TransactionReader::new(reader, b',')
+}
+
+struct TransactionReader<R> {
+    reader: csv::Reader<R>,
+    record: ByteRecord,
+    columns: Option<Columns>,
+    /// Field count every row must have, when the reader itself does not check it
+    fields: Option<usize>,
+    /// Lines of the file before the first line this reader sees
+    line_offset: u64,
+    done: bool,
+}
+
+impl<R: Read> TransactionReader<R> {
+    fn new(reader: R, delimiter: u8) -> Self {
+        Self {
+            reader: csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .trim(csv::Trim::All)
+                .from_reader(reader),
+            record: ByteRecord::new(),
+            columns: None,
+            fields: None,
+            line_offset: 0,
+            done: false,
         }
     }
+
+    /// `None` for an input without a header row
+    fn columns(&mut self) -> Result<Option<Columns>, Box<dyn Error + Send + Sync>> {
+        if self.columns.is_none() {
+            let headers = self.reader.byte_headers()?;
+            if headers.is_empty() {
+                return Ok(None);
+            }
+            self.columns = Some(Columns::from_headers(headers)?);
+        }
+        Ok(self.columns)
+    }
 }
 
+impl<R: Read> Iterator for TransactionReader<R> {
+    type Item = Result<Transaction, Box<dyn Error + Send + Sync>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let columns = match self.columns() {
+            Ok(Some(columns)) => columns,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => {
+                let result = match self.fields {
+                    Some(fields) if self.record.len() != fields => Err(format!(
+                        "found record with {} fields, but the header has {} fields",
+                        self.record.len(),
+                        fields
+                    )),
+                    _ => CsvRecord::from_byte_record(&self.record, &columns)
+                        .and_then(CsvRecord::into_transaction),
+                };
+                Some(result.map_err(|e| match self.record.position() {
+                    Some(position) => {
+                        format!("line {}: {}", self.line_offset + position.line(), e).into()
+                    }
+                    None => e.into(),
+                }))
+            }
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // The rows after a read failure cannot be trusted
+                self.done = e.is_io_error();
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+/// Parse a single headerless row such as `deposit, 1, 1, 1.5`.
+///
+/// The trailing amount column may be left off.
+pub fn parse_row(line: &str) -> Result<Transaction, Box<dyn Error + Send + Sync>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(line.as_bytes());
+
+    let mut record = ByteRecord::new();
+    if !reader.read_byte_record(&mut record)? {
+        return Err("Empty row".into());
+    }
+    Ok(CsvRecord::from_byte_record(&record, &Columns::default())?.into_transaction()?)
+}
+
+/// Outcome of checking a file with `validate_csv`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validation {
+    /// Rows that parse into a transaction
+    pub valid: usize,
+    /// Errors of the rows that do not, prefixed with their line numbers
+    pub errors: Vec<String>,
+}
+
+/// Parse every row of `path` without applying anything, collecting the errors of
+/// malformed rows. A missing file, a bad header or a failed read is returned as an
+/// error instead.
+pub fn validate_csv<P: AsRef<Path>>(
+    path: P,
+    delimiter: u8,
+) -> Result<Validation, Box<dyn Error + Send + Sync>> {
+    let mut reader = TransactionReader::new(File::open(path)?, delimiter);
+    let mut validation = Validation::default();
+
+    while let Some(result) = reader.next() {
+        match result {
+            Ok(_) => validation.valid += 1,
+            Err(e) if !reader.done => validation.errors.push(e.to_string()),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(validation)
+}
+
+/// Read `path` and send its transactions to `tx` in batches of up to `options.batch_size`.
+///
+/// Transactions read before a malformed row are still sent. Under `ErrorPolicy::Skip`
+/// malformed rows are passed over instead and their errors returned; a bad header
+/// or a failed read still stops the file.
 pub async fn process_csv_with_channel<P: AsRef<Path>>(
     path: P,
-    tx: mpsc::Sender<Transaction>,
+    tx: mpsc::Sender<Vec<Transaction>>,
+    options: ReadOptions,
     cancel_token: CancellationToken,
-) -> Result<(), Box<dyn Error>> {
-    let mut reader = csv::ReaderBuilder::new()
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let mut reader = TransactionReader::new(File::open(path)?, options.delimiter);
+    let batch_size = options.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut skipped = Vec::new();
+
+    while let Some(result) = reader.next() {
+        match result {
+            Ok(transaction) => batch.push(transaction),
+            Err(e) if options.on_error == ErrorPolicy::Skip && !reader.done => {
+                warn!("Skipping malformed row: {}", e);
+                skipped.push(e.to_string());
+                continue;
+            }
+            Err(e) => {
+                send_batch(&tx, batch, &cancel_token).await?;
+                return Err(e);
+            }
+        }
+
+        if batch.len() == batch_size {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            if !send_batch(&tx, full, &cancel_token).await? {
+                return Ok(skipped);
+            }
+        }
+    }
+
+    send_batch(&tx, batch, &cancel_token).await?;
+    Ok(skipped)
+}
+
+/// Returns `false` if processing was cancelled instead
+async fn send_batch(
+    tx: &mpsc::Sender<Vec<Transaction>>,
+    batch: Vec<Transaction>,
+    cancel_token: &CancellationToken,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    if cancel_token.is_cancelled() {
+        warn!("CSV processing cancelled");
+        return Ok(false);
+    }
+    if batch.is_empty() {
+        return Ok(true);
+    }
+
+    tokio::select! {
+        _ = cancel_token.cancelled() => {
+            warn!("CSV processing cancelled");
+            Ok(false)
+        }
+        result = tx.send(batch) => {
+            result?;
+            Ok(true)
+        }
+    }
+}
+
+/// Settings for parsing one file on several threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelConfig {
+    /// Chunks parsed at the same time
+    pub workers: usize,
+    /// Bytes per chunk, extended to the end of the line it stops in
+    pub chunk_size: usize,
+}
+
+/// Default `ParallelConfig::chunk_size`
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        Self {
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Transactions parsed from one chunk, in `batch_size` batches, the errors of
+/// rows skipped and the error that stopped parsing it, if any
+struct ParsedChunk {
+    batches: Vec<Vec<Transaction>>,
+    skipped: Vec<String>,
+    error: Option<Box<dyn Error + Send + Sync>>,
+}
+
+/// Like `process_csv_with_channel`, but splits the file into chunks at line boundaries
+/// and parses up to `config.workers` of them at once on Tokio's blocking thread pool.
+///
+/// Chunks are handed off in file order, so transactions reach `tx` in exactly the
+/// order of the file. Rows must not contain quoted line breaks.
+pub async fn process_csv_parallel<P: AsRef<Path>>(
+    path: P,
+    tx: mpsc::Sender<Vec<Transaction>>,
+    options: ReadOptions,
+    config: ParallelConfig,
+    cancel_token: CancellationToken,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let options = ReadOptions {
+        batch_size: options.batch_size.max(1),
+        ..options
+    };
+    let workers = config.workers.max(1);
+    let chunk_size = config.chunk_size.max(1);
+
+    let mut header = Vec::new();
+    let mut lines = 0;
+    while header.trim_ascii().is_empty() {
+        header.clear();
+        if file.read_until(b'\n', &mut header)? == 0 {
+            return Ok(Vec::new());
+        }
+        lines += 1;
+    }
+    let mut header_reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
         .trim(csv::Trim::All)
-        .from_path(path)?;
+        .from_reader(&header[..]);
+    let headers = header_reader.byte_headers()?;
+    let columns = Columns::from_headers(headers)?;
+    let fields = headers.len();
 
-    for result in reader.deserialize::<CsvRecord>() {
-        if cancel_token.is_cancelled() {
-            println!("CSV processing cancelled");
-            return Ok(());
+    let mut pending = VecDeque::with_capacity(workers);
+    let mut skipped = Vec::new();
+    loop {
+        let chunk = read_chunk(&mut file, chunk_size)?;
+        if chunk.is_empty() {
+            break;
         }
+        let line_offset = lines;
+        lines += chunk.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        pending.push_back(tokio::task::spawn_blocking(move || {
+            parse_chunk(&chunk, columns, fields, line_offset, options)
+        }));
 
-        let record = result?;
-        let transaction = record.into_transaction()?;
+        if pending.len() == workers {
+            let chunk = pending.pop_front().expect("pending is full").await?;
+            if !forward_chunk(&tx, chunk, &mut skipped, &cancel_token).await? {
+                return Ok(skipped);
+            }
+        }
+    }
 
-        tokio::select! {
-            _ = cancel_token.cancelled() => {
-                println!("CSV processing cancelled");
-                return Ok(());
+    while let Some(chunk) = pending.pop_front() {
+        if !forward_chunk(&tx, chunk.await?, &mut skipped, &cancel_token).await? {
+            return Ok(skipped);
+        }
+    }
+    Ok(skipped)
+}
+
+/// Read about `chunk_size` bytes, continuing to the end of the last line
+fn read_chunk<R: BufRead>(reader: &mut R, chunk_size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(chunk_size);
+    reader
+        .by_ref()
+        .take(chunk_size as u64)
+        .read_to_end(&mut chunk)?;
+    if chunk.last().is_some_and(|&byte| byte != b'\n') {
+        reader.read_until(b'\n', &mut chunk)?;
+    }
+    Ok(chunk)
+}
+
+fn parse_chunk(
+    chunk: &[u8],
+    columns: Columns,
+    fields: usize,
+    line_offset: u64,
+    options: ReadOptions,
+) -> ParsedChunk {
+    let mut reader = TransactionReader {
+        reader: csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(chunk),
+        record: ByteRecord::new(),
+        columns: Some(columns),
+        fields: Some(fields),
+        line_offset,
+        done: false,
+    };
+
+    let mut parsed = ParsedChunk {
+        batches: Vec::new(),
+        skipped: Vec::new(),
+        error: None,
+    };
+    let batch_size = options.batch_size;
+    let mut batch = Vec::with_capacity(batch_size);
+    while let Some(result) = reader.next() {
+        match result {
+            Ok(transaction) => batch.push(transaction),
+            Err(e) if options.on_error == ErrorPolicy::Skip && !reader.done => {
+                warn!("Skipping malformed row: {}", e);
+                parsed.skipped.push(e.to_string());
+                continue;
             }
-            result = tx.send(transaction) => {
-                result?;
+            Err(e) => {
+                parsed.error = Some(e);
+                break;
             }
         }
+        if batch.len() == batch_size {
+            parsed.batches.push(std::mem::replace(
+                &mut batch,
+                Vec::with_capacity(batch_size),
+            ));
+        }
     }
+    parsed.batches.push(batch);
+    parsed
+}
 
-    Ok(())
+/// Send a parsed chunk's batches and add its skipped rows to `skipped`, then return
+/// its error. Returns `false` if processing was cancelled.
+async fn forward_chunk(
+    tx: &mpsc::Sender<Vec<Transaction>>,
+    chunk: ParsedChunk,
+    skipped: &mut Vec<String>,
+    cancel_token: &CancellationToken,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    skipped.extend(chunk.skipped);
+    for batch in chunk.batches {
+        if !send_batch(tx, batch, cancel_token).await? {
+            return Ok(false);
+        }
+    }
+    match chunk.error {
+        Some(e) => Err(e),
+        None => Ok(true),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::temp_file;
     use rust_decimal_macros::dec;
 
     #[test]
     fn test_deposit_transaction() {
         let record = CsvRecord {
-            tx_type: "deposit".to_string(),
-            client: 1,
+            tx_type: TransactionType::Deposit,
+            client: ClientId::new(1),
             tx: 100,
             amount: Some(dec!(50.00)),
         };
@@ -110,8 +673,8 @@ mod tests {
     #[test]
     fn test_withdrawal_transaction() {
         let record = CsvRecord {
-            tx_type: "withdrawal".to_string(),
-            client: 2,
+            tx_type: TransactionType::Withdrawal,
+            client: ClientId::new(2),
             tx: 200,
             amount: Some(dec!(25.50)),
         };
@@ -133,8 +696,8 @@ mod tests {
     #[test]
     fn test_dispute_transaction() {
         let record = CsvRecord {
-            tx_type: "dispute".to_string(),
-            client: 3,
+            tx_type: TransactionType::Dispute,
+            client: ClientId::new(3),
             tx: 300,
             amount: None,
         };
@@ -154,8 +717,8 @@ mod tests {
     #[test]
     fn test_resolve_transaction() {
         let record = CsvRecord {
-            tx_type: "resolve".to_string(),
-            client: 4,
+            tx_type: TransactionType::Resolve,
+            client: ClientId::new(4),
             tx: 400,
             amount: None,
         };
@@ -175,8 +738,8 @@ mod tests {
     #[test]
     fn test_chargeback_transaction() {
         let record = CsvRecord {
-            tx_type: "chargeback".to_string(),
-            client: 5,
+            tx_type: TransactionType::Chargeback,
+            client: ClientId::new(5),
             tx: 500,
             amount: None,
         };
@@ -194,23 +757,40 @@ mod tests {
     }
 
     #[test]
-    fn test_transaction_type_with_whitespace() {
+    fn test_reversal_transaction() {
         let record = CsvRecord {
-            tx_type: "  deposit  ".to_string(),
-            client: 1,
-            tx: 100,
-            amount: Some(dec!(10.00)),
+            tx_type: TransactionType::Reversal,
+            client: ClientId::new(6),
+            tx: 600,
+            amount: None,
         };
 
         let result = record.into_transaction();
         assert!(result.is_ok());
+
+        let transaction = result.unwrap();
+        if let Transaction::Reversal(client_tx) = transaction {
+            assert_eq!(client_tx.client, 6);
+            assert_eq!(client_tx.tx, 600);
+        } else {
+            panic!("Expected Reversal transaction");
+        }
+    }
+
+    #[test]
+    fn test_transaction_type_with_whitespace() {
+        assert_eq!(
+            TransactionType::from_bytes(b"  deposit  "),
+            Some(TransactionType::Deposit)
+        );
+        assert_eq!("  chargeback ".parse(), Ok(TransactionType::Chargeback));
     }
 
     #[test]
     fn test_deposit_missing_amount() {
         let record = CsvRecord {
-            tx_type: "deposit".to_string(),
-            client: 1,
+            tx_type: TransactionType::Deposit,
+            client: ClientId::new(1),
             tx: 100,
             amount: None,
         };
@@ -223,8 +803,8 @@ mod tests {
     #[test]
     fn test_withdrawal_missing_amount() {
         let record = CsvRecord {
-            tx_type: "withdrawal".to_string(),
-            client: 1,
+            tx_type: TransactionType::Withdrawal,
+            client: ClientId::new(1),
             tx: 100,
             amount: None,
         };
@@ -234,17 +814,220 @@ mod tests {
         assert_eq!(result.unwrap_err(), "Withdrawal requires an amount");
     }
 
+    #[test]
+    fn test_read_transactions_from_reader() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.5\n\
+                     dispute, 1, 1,\n\
+                     bogus, 1, 2, 1.0\n";
+
+        let results: Vec<_> = read_transactions(input.as_bytes()).collect();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[0], Ok(Transaction::Deposit(_))));
+        assert!(matches!(results[1], Ok(Transaction::Dispute(_))));
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn test_parse_row() {
+        match parse_row("deposit, 1, 7, 2.5").unwrap() {
+            Transaction::Deposit(money_tx) => {
+                assert_eq!(money_tx.id, ClientTransaction::new(1, 7));
+                assert_eq!(money_tx.amount, dec!(2.5));
+            }
+            other => panic!("Expected Deposit transaction, got {:?}", other),
+        }
+
+        // The amount column may be left off entirely for dispute rows
+        let transaction = parse_row("dispute, 1, 7").unwrap();
+        assert_eq!(transaction, Transaction::dispute(1, 7));
+
+        assert!(parse_row("deposit, x, 7, 2.5").is_err());
+        assert!(parse_row("").is_err());
+    }
+
     #[test]
     fn test_unknown_transaction_type() {
-        let record = CsvRecord {
-            tx_type: "unknown".to_string(),
-            client: 1,
-            tx: 100,
-            amount: Some(dec!(10.00)),
+        let result = "unknown".parse::<TransactionType>();
+        assert_eq!(result, Err("Unknown transaction type: unknown".to_string()));
+
+        let error = parse_row("unknown, 1, 100, 10.00").unwrap_err();
+        assert!(error.to_string().contains("Unknown transaction type"));
+    }
+
+    #[tokio::test]
+    async fn test_process_csv_parallel_keeps_file_order() {
+        let mut input = String::from("\ntype, client, tx, amount\n");
+        for tx in 1..=200 {
+            input.push_str(&format!("deposit, {}, {}, {}.5\n", tx % 3, tx, tx));
+            if tx % 10 == 0 {
+                input.push_str(&format!("dispute, {}, {},\n", tx % 3, tx));
+            }
+        }
+        input.push_str("deposit, 1, 201\ndeposit, 1, 202, 1.0\n");
+        let file = temp_file(&input);
+        let path = file.path().to_path_buf();
+
+        let expected: Vec<_> = read_transactions(input.as_bytes()).collect();
+        let (tx, mut rx) = mpsc::channel(4);
+        let config = ParallelConfig {
+            workers: 4,
+            chunk_size: 64,
         };
+        let reader = tokio::spawn({
+            async move {
+                let options = ReadOptions {
+                    batch_size: 5,
+                    ..ReadOptions::default()
+                };
+                process_csv_parallel(&path, tx, options, config, CancellationToken::new())
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        });
 
-        let result = record.into_transaction();
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Unknown transaction type"));
+        let mut transactions = Vec::new();
+        while let Some(batch) = rx.recv().await {
+            assert!(batch.len() <= 5);
+            transactions.extend(batch);
+        }
+        let error = reader.await.unwrap().unwrap_err();
+
+        assert_eq!(transactions.len(), 220);
+        // Timestamps differ between the two parses, so compare kind and IDs
+        let key = |transaction: &Transaction| {
+            (
+                std::mem::discriminant(transaction),
+                transaction.client_id(),
+                transaction.transaction_id(),
+            )
+        };
+        for (transaction, expected) in transactions.iter().zip(&expected) {
+            assert_eq!(key(transaction), key(expected.as_ref().unwrap()));
+        }
+        assert_eq!(
+            error,
+            "line 223: found record with 3 fields, but the header has 4 fields"
+        );
+    }
+
+    #[test]
+    fn test_read_transactions_by_header_name() {
+        let input = "client, amount, tx, type\n\
+                     1, 1.5, 1, deposit\n\
+                     1, 1e1, 2, deposit\n\
+                     1, x, 3, deposit\n";
+
+        let results: Vec<_> = read_transactions(input.as_bytes()).collect();
+        assert_eq!(results.len(), 3);
+        match &results[1] {
+            Ok(Transaction::Deposit(money_tx)) => assert_eq!(money_tx.amount, dec!(10)),
+            other => panic!("Expected Deposit transaction, got {:?}", other),
+        }
+        let error = results[2].as_ref().unwrap_err().to_string();
+        assert_eq!(error, "line 4: Invalid amount: \"x\"");
+
+        let results: Vec<_> = read_transactions("type, client\n".as_bytes()).collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].as_ref().unwrap_err().to_string(),
+            "Missing column: tx"
+        );
+    }
+
+    #[test]
+    fn test_read_transactions_wide_client_ids() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 70000, 1, 1.0\n\
+                     deposit, 99999999999999999999, 2, 1.0\n\
+                     deposit, -1, 3, 1.0\n";
+
+        let results: Vec<_> = read_transactions(input.as_bytes()).collect();
+        assert_eq!(results[0].as_ref().unwrap().client_id(), 70000);
+        let errors: Vec<_> = results[1..]
+            .iter()
+            .map(|result| result.as_ref().unwrap_err().to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                format!(
+                    "line 3: Client ID 99999999999999999999 is out of range (0 to {})",
+                    ClientId::MAX
+                ),
+                format!(
+                    "line 4: Client ID -1 is out of range (0 to {})",
+                    ClientId::MAX
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skip_policy_passes_over_malformed_rows() {
+        let file = temp_file(
+            "type;client;tx;amount\n\
+             deposit;1;1;1.0\n\
+             deposit;1;2;abc\n\
+             refund;1;3;1.0\n\
+             withdrawal;1;4;0.5\n",
+        );
+        let options = ReadOptions {
+            delimiter: b';',
+            on_error: ErrorPolicy::Skip,
+            ..ReadOptions::default()
+        };
+        let parallel = ParallelConfig {
+            workers: 2,
+            chunk_size: 16,
+        };
+
+        for parallel in [None, Some(parallel)] {
+            let (tx, mut rx) = mpsc::channel(4);
+            let cancel_token = CancellationToken::new();
+            let skipped = match parallel {
+                Some(config) => {
+                    process_csv_parallel(file.path(), tx, options, config, cancel_token).await
+                }
+                None => process_csv_with_channel(file.path(), tx, options, cancel_token).await,
+            }
+            .unwrap();
+
+            let mut ids = Vec::new();
+            while let Some(batch) = rx.recv().await {
+                ids.extend(batch.iter().map(Transaction::transaction_id));
+            }
+            assert_eq!(ids, vec![1, 4]);
+            assert_eq!(
+                skipped,
+                vec![
+                    "line 3: Invalid amount: \"abc\"",
+                    "line 4: Unknown transaction type: refund",
+                ]
+            );
+        }
+
+        assert_eq!("skip".parse(), Ok(ErrorPolicy::Skip));
+        assert!("ignore".parse::<ErrorPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_validate_csv() {
+        let file = temp_file(
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 1, 2, -1.0\n\
+             withdrawal, x, 3, 1.0\n\
+             dispute, 1, 1,\n",
+        );
+        let validation = validate_csv(file.path(), b',').unwrap();
+        assert_eq!(validation.valid, 2);
+        assert_eq!(validation.errors.len(), 2);
+        assert!(validation.errors[0].starts_with("line 3: "));
+        assert!(validation.errors[1].starts_with("line 4: Invalid client ID"));
+
+        let file = temp_file("kind, client, tx, amount\ndeposit, 1, 1, 1.0\n");
+        let error = validate_csv(file.path(), b',').unwrap_err();
+        assert_eq!(error.to_string(), "Missing column: type");
     }
 }
@@ -0,0 +1,678 @@
+use crate::account::{Account, AccountBalance, AccountError, AccountManager, ProcessOutcome};
+use crate::csv::{self, ErrorPolicy, ParallelConfig, ReadOptions};
+use crate::invariants::InvariantViolation;
+use crate::limits::{LimitError, LimitsConfig};
+use crate::rebuild::RebuildReport;
+use crate::spill::SpillConfig;
+use crate::store::AccountStore;
+use crate::transaction::{AmountPolicy, ClientId, DisputePolicy, Transaction, TransactionError};
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Default capacity, in batches, of the channel between the CSV reader and the processor
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 100;
+
+/// Machine-readable code for an error returned by the engine.
+///
+/// Failures to read or save account state are reported as `internal`. Other
+/// errors that are not one of the engine's typed errors, such as malformed
+/// input, are reported as `invalid_input`.
+pub fn error_code(error: &(dyn Error + 'static)) -> &'static str {
+    if let Some(e) = error.downcast_ref::<AccountError>() {
+        e.code()
+    } else if let Some(e) = error.downcast_ref::<TransactionError>() {
+        e.code()
+    } else if let Some(e) = error.downcast_ref::<LimitError>() {
+        e.code()
+    } else if error.is::<io::Error>() {
+        "internal"
+    } else {
+        "invalid_input"
+    }
+}
+
+/// A transaction the engine refused, with the reason it gave
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub client: ClientId,
+    pub tx: u32,
+    pub code: &'static str,
+    pub reason: String,
+}
+
+/// Outcome of running one input through the engine
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessSummary {
+    /// Hex SHA-256 of the input file's contents
+    pub fingerprint: Option<String>,
+    /// Set when a file with the same fingerprint was already ingested; nothing was applied
+    pub skipped: bool,
+    pub processed: usize,
+    /// Dispute, resolve and chargeback rows that had already been applied
+    pub replayed: usize,
+    pub rejected: Vec<Rejection>,
+    /// Errors of malformed rows passed over under `ErrorPolicy::Skip`
+    pub malformed: Vec<String>,
+    /// Set when reading the input stopped early, e.g. on a malformed row
+    pub read_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct EngineBuilder {
+    limits: LimitsConfig,
+    dispute_policy: DisputePolicy,
+    amount_policy: AmountPolicy,
+    channel_capacity: usize,
+    read_options: ReadOptions,
+    parallel_parse: Option<ParallelConfig>,
+    check_invariants: bool,
+    ledger_spill: Option<SpillConfig>,
+    account_store: Option<Box<dyn AccountStore>>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            limits: LimitsConfig::default(),
+            dispute_policy: DisputePolicy::default(),
+            amount_policy: AmountPolicy::default(),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            read_options: ReadOptions::default(),
+            parallel_parse: None,
+            check_invariants: false,
+            ledger_spill: None,
+            account_store: None,
+        }
+    }
+}
+
+impl EngineBuilder {
+    pub fn limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    /// Minimum and precision rules for deposit and withdrawal amounts
+    pub fn amount_policy(mut self, amount_policy: AmountPolicy) -> Self {
+        self.amount_policy = amount_policy;
+        self
+    }
+
+    /// Number of batches the channel between the CSV reader and the processor holds
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity.max(1);
+        self
+    }
+
+    /// Number of transactions the CSV reader sends, and the processor applies
+    /// under one lock, at a time
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.read_options.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Field separator of CSV input, `b','` by default
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.read_options.delimiter = delimiter;
+        self
+    }
+
+    /// Whether a malformed CSV row stops the file or is skipped and reported
+    pub fn on_malformed_row(mut self, policy: ErrorPolicy) -> Self {
+        self.read_options.on_error = policy;
+        self
+    }
+
+    /// Parse CSV files in chunks on several threads instead of on the reader task
+    pub fn parallel_parse(mut self, config: ParallelConfig) -> Self {
+        self.parallel_parse = Some(config);
+        self
+    }
+
+    /// Check balance invariants after every transaction (debug mode)
+    pub fn check_invariants(mut self, check_invariants: bool) -> Self {
+        self.check_invariants = check_invariants;
+        self
+    }
+
+    /// Bound the transactions each ledger keeps in memory, moving older ones to disk
+    pub fn ledger_spill(mut self, config: SpillConfig) -> Self {
+        self.ledger_spill = Some(config);
+        self
+    }
+
+    /// Keep accounts in `store` instead of memory, e.g. a `SqliteAccountStore` so
+    /// state persists across runs. Takes precedence over `ledger_spill`.
+    pub fn account_store(mut self, store: impl AccountStore + 'static) -> Self {
+        self.account_store = Some(Box::new(store));
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        let mut manager = AccountManager::with_limits(self.limits)
+            .with_dispute_policy(self.dispute_policy)
+            .with_amount_policy(self.amount_policy)
+            .with_invariant_checks(self.check_invariants);
+        if let Some(config) = self.ledger_spill {
+            manager = manager.with_ledger_spill(config);
+        }
+        if let Some(store) = self.account_store {
+            manager = manager.with_account_store(store);
+        }
+        Engine {
+            manager: Arc::new(manager),
+            channel_capacity: self.channel_capacity,
+            read_options: self.read_options,
+            parallel_parse: self.parallel_parse,
+            ingested: Arc::default(),
+        }
+    }
+}
+
+/// Embeddable transaction engine. Clones share the same accounts.
+#[derive(Debug, Clone)]
+pub struct Engine {
+    manager: Arc<AccountManager>,
+    channel_capacity: usize,
+    read_options: ReadOptions,
+    parallel_parse: Option<ParallelConfig>,
+    /// Fingerprints of files this engine read to the end, or is reading now.
+    /// Account stores that outlive the engine also record finished ones.
+    ingested: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    pub fn account_manager(&self) -> &Arc<AccountManager> {
+        &self.manager
+    }
+
+    pub async fn process(
+        &self,
+        transaction: Transaction,
+    ) -> Result<ProcessOutcome, Box<dyn Error>> {
+        self.manager.process_transaction(transaction).await
+    }
+
+    /// Whether a file with this fingerprint was ingested by this engine, or into
+    /// its account store by an earlier run
+    pub async fn is_ingested(&self, fingerprint: &str) -> io::Result<bool> {
+        Ok(self.ingested.lock().unwrap().contains(fingerprint)
+            || self.manager.is_ingested(fingerprint).await?)
+    }
+
+    /// Stream a CSV file through the reader → channel → processor pipeline.
+    ///
+    /// Rejected transactions do not stop processing; they are collected in the summary.
+    /// A file whose contents were already ingested is skipped. A file read to the
+    /// end is recorded as ingested in the account store's commit of its last
+    /// batch, so a store that persists accounts skips it on later runs too.
+    pub async fn process_csv<P: AsRef<Path>>(
+        &self,
+        path: P,
+        cancel_token: CancellationToken,
+    ) -> ProcessSummary {
+        let path = path.as_ref().to_path_buf();
+        let mut summary = ProcessSummary::default();
+
+        let fingerprint = match fingerprint_file(&path) {
+            Ok(fingerprint) => fingerprint,
+            Err(e) => {
+                summary.read_error = Some(e.to_string());
+                return summary;
+            }
+        };
+        summary.fingerprint = Some(fingerprint.clone());
+        let ingested = match self.manager.is_ingested(&fingerprint).await {
+            // Claiming the fingerprint also keeps concurrent reads of one file apart
+            Ok(stored) => stored || !self.ingested.lock().unwrap().insert(fingerprint.clone()),
+            Err(e) => {
+                summary.read_error = Some(e.to_string());
+                return summary;
+            }
+        };
+        if ingested {
+            info!(
+                "Skipping {}: already ingested ({})",
+                path.display(),
+                fingerprint
+            );
+            summary.skipped = true;
+            return summary;
+        }
+
+        let (tx, mut rx) = mpsc::channel::<Vec<Transaction>>(self.channel_capacity);
+        let reader_token = cancel_token.clone();
+        let (options, parallel_parse) = (self.read_options, self.parallel_parse);
+        let reader_handle = tokio::spawn(async move {
+            let result = match parallel_parse {
+                Some(config) => {
+                    csv::process_csv_parallel(&path, tx, options, config, reader_token).await
+                }
+                None => csv::process_csv_with_channel(&path, tx, options, reader_token).await,
+            };
+            result.map_err(|e| e.to_string())
+        });
+
+        // The latest batch waits until the next one arrives, so the last batch of
+        // a file read to the end can be committed with the file's fingerprint
+        let mut last = None;
+        while let Some(batch) = rx.recv().await {
+            if let Some(batch) = last.replace(batch) {
+                let ids = batch_ids(&batch);
+                let results = self.manager.process_batch(batch).await;
+                record_results(&mut summary, ids, results);
+            }
+        }
+
+        summary.read_error = match reader_handle.await {
+            Ok(Ok(malformed)) => {
+                summary.malformed = malformed;
+                None
+            }
+            Ok(Err(e)) => Some(e),
+            Err(e) => Some(e.to_string()),
+        };
+
+        let batch = last.unwrap_or_default();
+        let ids = batch_ids(&batch);
+        // Only a file that was read to the end counts as ingested
+        if summary.read_error.is_none() && !cancel_token.is_cancelled() {
+            match self.manager.process_last_batch(batch, &fingerprint).await {
+                Ok(results) => record_results(&mut summary, ids, results),
+                Err(e) => {
+                    let results = ids
+                        .iter()
+                        .map(|_| Err(io::Error::new(e.kind(), e.to_string()).into()))
+                        .collect();
+                    record_results(&mut summary, ids, results);
+                    summary.read_error = Some(format!("Error committing the last batch: {}", e));
+                }
+            }
+        } else if !batch.is_empty() {
+            let results = self.manager.process_batch(batch).await;
+            record_results(&mut summary, ids, results);
+        }
+        if summary.read_error.is_some() || cancel_token.is_cancelled() {
+            self.ingested.lock().unwrap().remove(&fingerprint);
+        }
+        summary
+    }
+
+    pub async fn account(&self, client: impl Into<ClientId>) -> io::Result<Option<Account>> {
+        self.manager.get_account(client).await
+    }
+
+    pub async fn balance(&self, client: impl Into<ClientId>) -> io::Result<Option<AccountBalance>> {
+        self.manager.get_balance(client).await
+    }
+
+    /// One transaction of `client`, without cloning the account
+    pub async fn transaction(
+        &self,
+        client: impl Into<ClientId>,
+        tx_id: u32,
+    ) -> io::Result<Option<Transaction>> {
+        self.manager.get_transaction(client, tx_id).await
+    }
+
+    /// Balances of every account sorted by client, without cloning any ledger
+    pub async fn balances(&self) -> io::Result<Vec<AccountBalance>> {
+        self.manager.balances().await
+    }
+
+    /// Run `f` over every account without cloning them, see `AccountManager::with_accounts`
+    pub async fn with_accounts<R>(
+        &self,
+        f: impl FnOnce(&mut dyn Iterator<Item = io::Result<Cow<'_, Account>>>) -> R,
+    ) -> R {
+        self.manager.with_accounts(f).await
+    }
+
+    pub async fn accounts(&self) -> io::Result<HashMap<ClientId, Account>> {
+        self.manager.accounts().await
+    }
+
+    /// Check every account's balance invariants now
+    pub async fn check_invariants(&self) -> io::Result<Vec<InvariantViolation>> {
+        self.manager.check_invariants().await
+    }
+
+    /// Rebuild all balances from ledger history and report any that disagree
+    pub async fn verify(&self) -> io::Result<RebuildReport> {
+        self.manager.verify().await
+    }
+
+    /// Rebuild all balances from ledger history and replace any that disagree
+    pub async fn rebuild(&self) -> io::Result<RebuildReport> {
+        self.manager.rebuild().await
+    }
+}
+
+fn batch_ids(batch: &[Transaction]) -> Vec<(ClientId, u32)> {
+    batch
+        .iter()
+        .map(|transaction| (transaction.client_id(), transaction.transaction_id()))
+        .collect()
+}
+
+/// Count `results` into `summary`, `ids` giving the client and tx of each
+fn record_results(
+    summary: &mut ProcessSummary,
+    ids: Vec<(ClientId, u32)>,
+    results: Vec<Result<ProcessOutcome, Box<dyn Error>>>,
+) {
+    for ((client, tx_id), result) in ids.into_iter().zip(results) {
+        match result {
+            Ok(ProcessOutcome::Applied) => summary.processed += 1,
+            Ok(ProcessOutcome::Replayed) => summary.replayed += 1,
+            Err(e) => {
+                error!("Error processing transaction {}: {}", tx_id, e);
+                summary.rejected.push(Rejection {
+                    client,
+                    tx: tx_id,
+                    code: error_code(e.as_ref()),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Hex SHA-256 of a file's contents
+pub fn fingerprint_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_file;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn test_engine_process_csv() {
+        let file = temp_file(
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             withdrawal, 1, 2, 20.0\n\
+             deposit, 2, 3, 5.0\n",
+        );
+
+        let engine = Engine::builder().channel_capacity(1).build();
+        let summary = engine
+            .process_csv(file.path(), CancellationToken::new())
+            .await;
+
+        assert_eq!(summary.processed, 2);
+        assert_eq!(
+            summary.rejected,
+            vec![Rejection {
+                client: ClientId::new(1),
+                tx: 2,
+                code: "insufficient_funds",
+                reason: "Insufficient funds".to_string()
+            }]
+        );
+        assert_eq!(summary.read_error, None);
+        assert_eq!(
+            engine.account(1).await.unwrap().unwrap().available,
+            dec!(10.0)
+        );
+        assert_eq!(engine.accounts().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_engine_policies() {
+        let file = temp_file(
+            "type|client|tx|amount\n\
+             deposit|1|1|10.0\n\
+             deposit|1|2|0.001\n\
+             deposit|1|3|1.25\n\
+             deposit|1|4|ten\n\
+             dispute|1|1|\n\
+             chargeback|1|1|\n\
+             withdrawal|1|5|0.5\n",
+        );
+
+        let engine = Engine::builder()
+            .dispute_policy(DisputePolicy {
+                lock_on_chargeback: false,
+                ..DisputePolicy::default()
+            })
+            .amount_policy(AmountPolicy {
+                min_amount: Some(dec!(0.01)),
+                max_decimal_places: Some(2),
+            })
+            .delimiter(b'|')
+            .on_malformed_row(ErrorPolicy::Skip)
+            .build();
+        let summary = engine
+            .process_csv(file.path(), CancellationToken::new())
+            .await;
+
+        assert_eq!(summary.read_error, None);
+        assert_eq!(summary.malformed, vec!["line 5: Invalid amount: \"ten\""]);
+        let codes: Vec<_> = summary.rejected.iter().map(|r| (r.tx, r.code)).collect();
+        assert_eq!(codes, vec![(2, "invalid_amount")]);
+        assert_eq!(summary.processed, 5);
+
+        let account = engine.account(1).await.unwrap().unwrap();
+        assert!(!account.locked);
+        assert_eq!(account.available, dec!(0.75));
+        assert!(engine.verify().await.unwrap().is_consistent());
+    }
+
+    #[tokio::test]
+    async fn test_engine_batch_size_does_not_change_results() {
+        let config = crate::generate::GeneratorConfig {
+            rows: 2_000,
+            ..Default::default()
+        };
+        let mut input = Vec::new();
+        crate::generate::write_csv(&config, &mut input).unwrap();
+        let file = temp_file(input);
+
+        let mut runs = Vec::new();
+        for batch_size in [1, 7, 10_000] {
+            let engine = Engine::builder()
+                .channel_capacity(2)
+                .batch_size(batch_size)
+                .build();
+            let summary = engine
+                .process_csv(file.path(), CancellationToken::new())
+                .await;
+            runs.push((summary, engine.balances().await.unwrap()));
+        }
+
+        assert!(runs[0].0.processed > 0);
+        assert!(!runs[0].0.rejected.is_empty());
+        assert_eq!(runs[0], runs[1]);
+        assert_eq!(runs[0], runs[2]);
+    }
+
+    #[tokio::test]
+    async fn test_engine_parallel_parse_matches_sequential() {
+        let config = crate::generate::GeneratorConfig {
+            rows: 2_000,
+            ..Default::default()
+        };
+        let mut input = Vec::new();
+        crate::generate::write_csv(&config, &mut input).unwrap();
+        // A malformed row stops reading at the same place in both modes
+        input.extend_from_slice(b"deposit, x, 99999, 1.0\ndeposit, 1, 100000, 1.0\n");
+        let file = temp_file(input);
+
+        let sequential = Engine::default();
+        let expected = sequential
+            .process_csv(file.path(), CancellationToken::new())
+            .await;
+
+        let parallel = Engine::builder()
+            .parallel_parse(ParallelConfig {
+                workers: 3,
+                chunk_size: 500,
+            })
+            .batch_size(16)
+            .build();
+        let summary = parallel
+            .process_csv(file.path(), CancellationToken::new())
+            .await;
+
+        assert!(expected.read_error.is_some());
+        assert_eq!(summary, expected);
+        assert_eq!(
+            parallel.balances().await.unwrap(),
+            sequential.balances().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_codes() {
+        let engine = Engine::default();
+
+        let error = engine
+            .process(Transaction::resolve(1, 1))
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(error.as_ref()), "not_disputed");
+
+        let error = engine
+            .process(Transaction::dispute(1, 1))
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(error.as_ref()), "transaction_not_found");
+
+        let error: Box<dyn Error> = "Unknown transaction type: bogus".into();
+        assert_eq!(error_code(error.as_ref()), "invalid_input");
+
+        let error: Box<dyn Error> = io::Error::other("disk I/O error").into();
+        assert_eq!(error_code(error.as_ref()), "internal");
+    }
+
+    #[tokio::test]
+    async fn test_engine_skips_already_ingested_file() {
+        let file = temp_file(
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             dispute, 1, 1,\n\
+             dispute, 1, 1,\n",
+        );
+
+        let engine = Engine::default();
+        let first = engine
+            .process_csv(file.path(), CancellationToken::new())
+            .await;
+        let second = engine
+            .process_csv(file.path(), CancellationToken::new())
+            .await;
+
+        assert_eq!(first.processed, 2);
+        assert_eq!(first.replayed, 1);
+        assert!(first.rejected.is_empty());
+        assert!(!first.skipped);
+
+        assert!(second.skipped);
+        assert_eq!(second.processed, 0);
+        assert_eq!(second.fingerprint, first.fingerprint);
+        assert!(
+            engine
+                .is_ingested(first.fingerprint.as_deref().unwrap())
+                .await
+                .unwrap()
+        );
+        assert_eq!(engine.account(1).await.unwrap().unwrap().held, dec!(10.0));
+    }
+
+    #[tokio::test]
+    async fn test_ingested_files_are_stored_with_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_db = dir.path().join("state.db");
+        let engine = || {
+            Engine::builder()
+                .batch_size(1)
+                .account_store(crate::sqlite::SqliteAccountStore::open(&state_db).unwrap())
+                .build()
+        };
+        let file = temp_file(
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             deposit, 1, 2, 5.0\n\
+             deposit, 2, 3, 1.0\n",
+        );
+        let stopped = temp_file(
+            "type, client, tx, amount\n\
+             deposit, 3, 4, 2.0\n\
+             deposit, x, 5, 1.0\n",
+        );
+
+        let first = engine();
+        assert_eq!(
+            first
+                .process_csv(file.path(), CancellationToken::new())
+                .await
+                .processed,
+            3
+        );
+        let summary = first
+            .process_csv(stopped.path(), CancellationToken::new())
+            .await;
+        assert!(summary.read_error.is_some());
+        drop(first);
+
+        // A later run skips the file and keeps its balances, but retries the one
+        // that was not read to the end
+        let second = engine();
+        let summary = second
+            .process_csv(file.path(), CancellationToken::new())
+            .await;
+        assert!(summary.skipped);
+        assert_eq!(second.balance(1).await.unwrap().unwrap().total, dec!(15.0));
+        let summary = second
+            .process_csv(stopped.path(), CancellationToken::new())
+            .await;
+        assert!(!summary.skipped);
+        assert_eq!(summary.rejected.len(), 1);
+        assert_eq!(summary.rejected[0].code, "duplicate_transaction");
+    }
+
+    #[tokio::test]
+    async fn test_engine_missing_file() {
+        let engine = Engine::default();
+        let summary = engine
+            .process_csv("does/not/exist.csv", CancellationToken::new())
+            .await;
+
+        assert_eq!(summary.processed, 0);
+        assert!(summary.read_error.is_some());
+    }
+}
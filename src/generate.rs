@@ -0,0 +1,287 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Settings for a synthetic transactions CSV
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneratorConfig {
+    pub rows: usize,
+    pub clients: u32,
+    /// Probability that a row disputes one of the client's earlier deposits
+    pub dispute_rate: f64,
+    /// Probability that a dispute ends in a chargeback rather than a resolve
+    pub chargeback_rate: f64,
+    /// Probability that a row is one the engine will reject, such as an
+    /// overdraft, a duplicate ID or a dispute of an unknown transaction
+    pub error_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            rows: 10_000,
+            clients: 100,
+            dispute_rate: 0.01,
+            chargeback_rate: 0.1,
+            error_rate: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+impl GeneratorConfig {
+    /// Check that every rate is a probability and there is at least one client
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, rate) in [
+            ("dispute rate", self.dispute_rate),
+            ("chargeback rate", self.chargeback_rate),
+            ("error rate", self.error_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{} must be between 0 and 1, got {}", name, rate));
+            }
+        }
+        if self.clients == 0 {
+            return Err("client count must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What the generator knows about one client while writing rows
+#[derive(Debug, Default)]
+struct ClientState {
+    available: Decimal,
+    /// Deposits that may still be disputed
+    deposits: Vec<(u32, Decimal)>,
+    /// Open disputes waiting for a resolve or chargeback
+    disputes: Vec<(u32, Decimal)>,
+}
+
+/// Writes rows in the same `type, client, tx, amount` format the reader accepts.
+///
+/// Every row parses; rows counted by `error_rate` are valid CSV that the engine
+/// rejects. The same config always produces the same file.
+struct Generator<W: Write> {
+    config: GeneratorConfig,
+    rng: StdRng,
+    clients: HashMap<u32, ClientState>,
+    /// Clients that have not been charged back yet
+    unlocked: Vec<u32>,
+    next_tx: u32,
+    writer: W,
+}
+
+impl<W: Write> Generator<W> {
+    fn row(
+        &mut self,
+        tx_type: &str,
+        client: u32,
+        tx: u32,
+        amount: Option<Decimal>,
+    ) -> io::Result<()> {
+        match amount {
+            Some(amount) => writeln!(self.writer, "{}, {}, {}, {}", tx_type, client, tx, amount),
+            None => writeln!(self.writer, "{}, {}, {},", tx_type, client, tx),
+        }
+    }
+
+    fn amount(&mut self) -> Decimal {
+        // Mostly small amounts with up to four decimal places
+        let cents = if self.rng.random_bool(0.9) {
+            self.rng.random_range(100..50_000)
+        } else {
+            self.rng.random_range(50_000..5_000_000)
+        };
+        Decimal::new(cents * 100 + self.rng.random_range(0..100), 4).normalize()
+    }
+
+    fn next_tx(&mut self) -> u32 {
+        self.next_tx += 1;
+        self.next_tx
+    }
+
+    fn error_row(&mut self, client: u32) -> io::Result<()> {
+        match self.rng.random_range(0..3) {
+            0 => {
+                let tx = self.next_tx();
+                let available = self.clients.entry(client).or_default().available;
+                let amount = available.max(Decimal::ZERO) + self.amount();
+                self.row("withdrawal", client, tx, Some(amount))
+            }
+            1 if !self.clients.entry(client).or_default().deposits.is_empty() => {
+                // Transaction IDs only need to be unique per client
+                let deposits = &self.clients[&client].deposits;
+                let (tx, _) = deposits[self.rng.random_range(0..deposits.len())];
+                let amount = self.amount();
+                self.row("deposit", client, tx, Some(amount))
+            }
+            _ => {
+                // IDs from the top of the range are never handed out by `next_tx`
+                let tx = u32::MAX - self.rng.random_range(0..1_000);
+                self.row("dispute", client, tx, None)
+            }
+        }
+    }
+
+    fn valid_row(&mut self, client: u32) -> io::Result<()> {
+        let config = self.config;
+        let state = self.clients.entry(client).or_default();
+
+        if !state.disputes.is_empty() && self.rng.random_bool(0.5) {
+            let index = self.rng.random_range(0..state.disputes.len());
+            let (tx, amount) = state.disputes.swap_remove(index);
+            if self.rng.random_bool(config.chargeback_rate) {
+                self.unlocked.retain(|&unlocked| unlocked != client);
+                return self.row("chargeback", client, tx, None);
+            }
+            state.available += amount;
+            state.deposits.push((tx, amount));
+            return self.row("resolve", client, tx, None);
+        }
+
+        if !state.deposits.is_empty() && self.rng.random_bool(config.dispute_rate) {
+            let index = self.rng.random_range(0..state.deposits.len());
+            let (tx, amount) = state.deposits.swap_remove(index);
+            state.available -= amount;
+            state.disputes.push((tx, amount));
+            return self.row("dispute", client, tx, None);
+        }
+
+        let available = state.available;
+        let amount = self.amount();
+        let tx = self.next_tx();
+        let state = self.clients.entry(client).or_default();
+        if available > Decimal::ZERO && self.rng.random_bool(0.4) {
+            let amount = amount.min(available);
+            state.available -= amount;
+            self.row("withdrawal", client, tx, Some(amount))
+        } else {
+            state.available += amount;
+            state.deposits.push((tx, amount));
+            self.row("deposit", client, tx, Some(amount))
+        }
+    }
+
+    fn pick_client(&mut self) -> u32 {
+        // Locked clients only receive rows that will be rejected, so avoid them
+        if self.unlocked.is_empty() {
+            return self.rng.random_range(1..=self.config.clients);
+        }
+        self.unlocked[self.rng.random_range(0..self.unlocked.len())]
+    }
+}
+
+/// Write a synthetic transactions CSV, header included. Returns the number of rows written.
+pub fn write_csv<W: Write>(config: &GeneratorConfig, writer: W) -> io::Result<usize> {
+    config
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut generator = Generator {
+        config: *config,
+        rng: StdRng::seed_from_u64(config.seed),
+        clients: HashMap::new(),
+        unlocked: (1..=config.clients).collect(),
+        next_tx: 0,
+        writer,
+    };
+
+    writeln!(generator.writer, "type, client, tx, amount")?;
+    for _ in 0..config.rows {
+        let client = generator.pick_client();
+        if generator.rng.random_bool(config.error_rate) {
+            generator.error_row(client)?;
+        } else {
+            generator.valid_row(client)?;
+        }
+    }
+    generator.writer.flush()?;
+    Ok(config.rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::Account;
+    use crate::csv::read_transactions;
+    use crate::transaction::ClientId;
+
+    fn generate(config: &GeneratorConfig) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_csv(config, &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_generated_csv_is_deterministic_and_parses() {
+        let config = GeneratorConfig {
+            rows: 2_000,
+            clients: 20,
+            ..GeneratorConfig::default()
+        };
+        let output = generate(&config);
+        assert_eq!(output, generate(&config));
+        assert_ne!(output, generate(&GeneratorConfig { seed: 1, ..config }));
+
+        let transactions: Vec<_> = read_transactions(&output[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(transactions.len(), 2_000);
+        assert!(
+            transactions
+                .iter()
+                .all(|t| (1..=20).contains(&t.client_id().get()))
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_rejected() {
+        let config = GeneratorConfig {
+            dispute_rate: 1.5,
+            ..GeneratorConfig::default()
+        };
+        assert!(write_csv(&config, Vec::new()).is_err());
+        assert!(
+            GeneratorConfig {
+                clients: 0,
+                ..GeneratorConfig::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_error_rate_controls_rejections() {
+        let rejections = |error_rate| {
+            let config = GeneratorConfig {
+                rows: 2_000,
+                clients: 10,
+                dispute_rate: 0.05,
+                chargeback_rate: 0.0,
+                error_rate,
+                seed: 7,
+            };
+            let mut accounts: HashMap<ClientId, Account> = HashMap::new();
+            read_transactions(&generate(&config)[..])
+                .map(Result::unwrap)
+                .filter(|transaction| {
+                    let client = transaction.client_id();
+                    accounts
+                        .entry(client)
+                        .or_insert_with(|| Account::new(client))
+                        .process_transaction(transaction.clone())
+                        .is_err()
+                })
+                .count()
+        };
+
+        assert_eq!(rejections(0.0), 0);
+        let rejected = rejections(0.1);
+        assert!((100..=300).contains(&rejected), "{}", rejected);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::account::AccountBalance;
+use crate::engine::{Engine, error_code};
+use crate::transaction::{ClientId, Transaction};
+use proto::transaction_service_server::{TransactionService, TransactionServiceServer};
+use proto::{
+    Ack, ErrorReason, GetAccountRequest, ListAccountsRequest, ListAccountsResponse, TransactionType,
+};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status, Streaming};
+
+/// Types generated from `proto/transactions.proto`
+pub mod proto {
+    tonic::include_proto!("transactions.v1");
+}
+
+/// Map an engine error code such as `insufficient_funds` to its protobuf reason
+pub fn error_reason(code: &str) -> ErrorReason {
+    ErrorReason::from_str_name(&format!("ERROR_REASON_{}", code.to_uppercase()))
+        .unwrap_or(ErrorReason::Unspecified)
+}
+
+fn into_transaction(message: proto::Transaction) -> Result<Transaction, String> {
+    let client = ClientId::from_u64(message.client).map_err(|e| e.to_string())?;
+    let amount = || {
+        Decimal::from_str(message.amount.trim())
+            .map_err(|e| format!("Invalid amount '{}': {}", message.amount, e))
+    };
+
+    match TransactionType::try_from(message.r#type) {
+        Ok(TransactionType::Deposit) => Transaction::deposit(client, message.tx, amount()?),
+        Ok(TransactionType::Withdrawal) => Transaction::withdrawal(client, message.tx, amount()?),
+        Ok(TransactionType::Dispute) => Ok(Transaction::dispute(client, message.tx)),
+        Ok(TransactionType::Resolve) => Ok(Transaction::resolve(client, message.tx)),
+        Ok(TransactionType::Chargeback) => Ok(Transaction::chargeback(client, message.tx)),
+        Ok(TransactionType::Reversal) => Ok(Transaction::reversal(client, message.tx)),
+        Ok(TransactionType::Unspecified) | Err(_) => {
+            Err(format!("Unknown transaction type: {}", message.r#type))
+        }
+    }
+}
+
+fn account_message(balance: AccountBalance) -> proto::Account {
+    proto::Account {
+        client: balance.client.as_u64(),
+        available: balance.available.to_string(),
+        held: balance.held.to_string(),
+        total: balance.total.to_string(),
+        locked: balance.locked,
+    }
+}
+
+async fn process_message(engine: &Engine, message: proto::Transaction) -> Ack {
+    let mut ack = Ack {
+        client: message.client,
+        tx: message.tx,
+        accepted: true,
+        ..Ack::default()
+    };
+
+    let result = match into_transaction(message) {
+        Ok(transaction) => engine
+            .process(transaction)
+            .await
+            .map_err(|e| (error_code(e.as_ref()), e.to_string())),
+        Err(message) => Err(("invalid_input", message)),
+    };
+
+    if let Err((code, message)) = result {
+        ack.accepted = false;
+        ack.reason = error_reason(code).into();
+        ack.message = message;
+    }
+    ack
+}
+
+/// gRPC front end for an `Engine`
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    engine: Engine,
+    ack_buffer: usize,
+}
+
+impl GrpcService {
+    /// `ack_buffer` bounds how many acks may be waiting for the client before
+    /// the server stops reading from the request stream.
+    pub fn new(engine: Engine, ack_buffer: usize) -> Self {
+        Self {
+            engine,
+            ack_buffer: ack_buffer.max(1),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl TransactionService for GrpcService {
+    type StreamTransactionsStream = ReceiverStream<Result<Ack, Status>>;
+
+    async fn submit(&self, request: Request<proto::Transaction>) -> Result<Response<Ack>, Status> {
+        Ok(Response::new(
+            process_message(&self.engine, request.into_inner()).await,
+        ))
+    }
+
+    async fn stream_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<Self::StreamTransactionsStream>, Status> {
+        let mut inbound = request.into_inner();
+        let (tx, rx) = mpsc::channel(self.ack_buffer);
+        let engine = self.engine.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let ack = match inbound.message().await {
+                    Ok(Some(message)) => Ok(process_message(&engine, message).await),
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = ack.is_err();
+                // Waiting here is what pushes back on a client that is not reading acks
+                if tx.send(ack).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client = request.into_inner().client;
+        let account = match ClientId::from_u64(client) {
+            Ok(client) => self
+                .engine
+                .balance(client)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?,
+            Err(_) => None,
+        };
+
+        match account {
+            Some(balance) => Ok(Response::new(account_message(balance))),
+            None => Err(Status::not_found(format!("Client {} not found", client))),
+        }
+    }
+
+    async fn list_accounts(
+        &self,
+        _request: Request<ListAccountsRequest>,
+    ) -> Result<Response<ListAccountsResponse>, Status> {
+        let balances = self
+            .engine
+            .balances()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let accounts = balances.into_iter().map(account_message).collect();
+        Ok(Response::new(ListAccountsResponse { accounts }))
+    }
+}
+
+/// Serve the gRPC API on `listener` until `cancel_token` is cancelled
+pub async fn serve(
+    service: GrpcService,
+    listener: TcpListener,
+    cancel_token: CancellationToken,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(TransactionServiceServer::new(service))
+        .serve_with_incoming_shutdown(
+            TcpListenerStream::new(listener),
+            cancel_token.cancelled_owned(),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_reason_covers_engine_codes() {
+        for code in [
+            "invalid_input",
+            "account_locked",
+            "duplicate_transaction",
+            "insufficient_funds",
+            "transaction_not_found",
+            "not_money_transaction",
+            "transaction_reversed",
+            "already_disputed",
+            "not_disputed",
+            "already_chargedback",
+            "already_reversed",
+            "cannot_reverse_disputed",
+            "dispute_limit_reached",
+            "invalid_amount",
+            "deposit_too_large",
+            "withdrawal_too_large",
+            "daily_withdrawal_cap_exceeded",
+            "too_many_transactions",
+        ] {
+            assert_ne!(error_reason(code), ErrorReason::Unspecified, "{}", code);
+        }
+        assert_eq!(error_reason("something_else"), ErrorReason::Unspecified);
+    }
+
+    #[test]
+    fn test_into_transaction() {
+        let message = proto::Transaction {
+            r#type: TransactionType::Deposit.into(),
+            client: 1,
+            tx: 2,
+            amount: "1.5".to_string(),
+        };
+        assert!(matches!(
+            into_transaction(message),
+            Ok(Transaction::Deposit(_))
+        ));
+
+        let message = proto::Transaction {
+            r#type: TransactionType::Dispute.into(),
+            client: 70_000,
+            tx: 2,
+            amount: String::new(),
+        };
+        assert_eq!(into_transaction(message), Ok(Transaction::dispute(70_000, 2)));
+
+        // Only reachable when client IDs are narrower than the wire type
+        if let Some(client) = ClientId::MAX.as_u64().checked_add(1) {
+            let message = proto::Transaction {
+                r#type: TransactionType::Dispute.into(),
+                client,
+                tx: 2,
+                amount: String::new(),
+            };
+            let error = into_transaction(message).unwrap_err();
+            assert!(error.contains("out of range"), "{}", error);
+        }
+
+        let message = proto::Transaction {
+            r#type: TransactionType::Withdrawal.into(),
+            client: 1,
+            tx: 3,
+            amount: "abc".to_string(),
+        };
+        assert!(into_transaction(message).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::account::Account;
+use crate::transaction::{ClientId, Transaction};
+use rust_decimal::Decimal;
+use std::fmt;
+use std::io;
+
+/// A balance invariant that does not hold for an account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// `total != available + held`
+    TotalMismatch {
+        client: ClientId,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+    },
+    NegativeHeld {
+        client: ClientId,
+        held: Decimal,
+    },
+    /// `held` differs from the sum of the amounts currently under dispute
+    HeldMismatch {
+        client: ClientId,
+        held: Decimal,
+        disputed: Decimal,
+    },
+    /// The ledger's store failed to read a transaction, so `held` was not checked
+    Unreadable {
+        client: ClientId,
+        error: String,
+    },
+}
+
+impl InvariantViolation {
+    pub fn client(&self) -> ClientId {
+        match self {
+            InvariantViolation::TotalMismatch { client, .. }
+            | InvariantViolation::NegativeHeld { client, .. }
+            | InvariantViolation::HeldMismatch { client, .. }
+            | InvariantViolation::Unreadable { client, .. } => *client,
+        }
+    }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::TotalMismatch {
+                client,
+                available,
+                held,
+                total,
+            } => write!(
+                f,
+                "Client {}: total {} != available {} + held {}",
+                client, total, available, held
+            ),
+            InvariantViolation::NegativeHeld { client, held } => {
+                write!(f, "Client {}: held {} is negative", client, held)
+            }
+            InvariantViolation::HeldMismatch {
+                client,
+                held,
+                disputed,
+            } => write!(
+                f,
+                "Client {}: held {} != {} currently under dispute",
+                client, held, disputed
+            ),
+            InvariantViolation::Unreadable { client, error } => {
+                write!(f, "Client {}: ledger could not be read: {}", client, error)
+            }
+        }
+    }
+}
+
+/// Sum of the amounts of every transaction currently under dispute
+pub fn disputed_amount(account: &Account) -> io::Result<Decimal> {
+    let mut disputed = Decimal::ZERO;
+    for transaction in account.ledger.transactions() {
+        if let Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx) =
+            transaction?.as_ref()
+            && money_tx.is_disputed()
+        {
+            disputed += money_tx.amount;
+        }
+    }
+    Ok(disputed)
+}
+
+/// Check every balance invariant of `account`
+pub fn check_account(account: &Account) -> Vec<InvariantViolation> {
+    let mut violations = Vec::new();
+
+    if account.total != account.available + account.held {
+        violations.push(InvariantViolation::TotalMismatch {
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+        });
+    }
+    if account.held < Decimal::ZERO {
+        violations.push(InvariantViolation::NegativeHeld {
+            client: account.client,
+            held: account.held,
+        });
+    }
+    match disputed_amount(account) {
+        Ok(disputed) if account.held != disputed => {
+            violations.push(InvariantViolation::HeldMismatch {
+                client: account.client,
+                held: account.held,
+                disputed,
+            });
+        }
+        Ok(_) => {}
+        Err(e) => violations.push(InvariantViolation::Unreadable {
+            client: account.client,
+            error: e.to_string(),
+        }),
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountManager;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_consistent_account_has_no_violations() {
+        let mut account = Account::new(1);
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(10.0)).unwrap(),
+            Transaction::withdrawal(1, 2, dec!(4.0)).unwrap(),
+            Transaction::dispute(1, 1),
+            Transaction::dispute(1, 2),
+            Transaction::resolve(1, 2),
+        ] {
+            account.process_transaction(transaction).unwrap();
+        }
+
+        assert_eq!(disputed_amount(&account).unwrap(), dec!(10.0));
+        assert!(check_account(&account).is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let mut account = Account::new(3);
+        account
+            .process_transaction(Transaction::deposit(3, 1, dec!(10.0)).unwrap())
+            .unwrap();
+        account.held = dec!(-1.0);
+
+        assert_eq!(
+            check_account(&account),
+            vec![
+                InvariantViolation::TotalMismatch {
+                    client: ClientId::new(3),
+                    available: dec!(10.0),
+                    held: dec!(-1.0),
+                    total: dec!(10.0),
+                },
+                InvariantViolation::NegativeHeld {
+                    client: ClientId::new(3),
+                    held: dec!(-1.0),
+                },
+                InvariantViolation::HeldMismatch {
+                    client: ClientId::new(3),
+                    held: dec!(-1.0),
+                    disputed: dec!(0),
+                },
+            ]
+        );
+        assert_eq!(
+            check_account(&account)[0].to_string(),
+            "Client 3: total 10.0 != available 10.0 + held -1.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manager_checks_each_transaction() {
+        let manager = AccountManager::new().with_invariant_checks(true);
+        manager
+            .process_transaction(Transaction::deposit(1, 1, dec!(5.0)).unwrap())
+            .await
+            .unwrap();
+        manager
+            .process_transaction(Transaction::dispute(1, 1))
+            .await
+            .unwrap();
+
+        assert!(manager.invariant_violations().is_empty());
+        assert!(manager.check_invariants().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+//! Transaction processing engine for deposits, withdrawals, disputes,
+//! resolves, chargebacks and reversals.
+//!
+//! ```no_run
+//! use transactions::{Engine, Transaction};
+//! use rust_decimal_macros::dec;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let engine = Engine::builder().build();
+//! engine.process(Transaction::deposit(1, 1, dec!(10.0))?).await?;
+//! engine.process(Transaction::dispute(1, 1)).await?;
+//!
+//! let account = engine.account(1).await?.unwrap();
+//! assert_eq!(account.held(), dec!(10.0));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The engine and the types it works with are re-exported here. The public modules
+//! hold the CSV, report, sqlite and network front ends built on top of them.
+
+mod account;
+pub mod config;
+pub mod csv;
+mod engine;
+pub mod generate;
+pub mod grpc;
+pub mod invariants;
+mod limits;
+pub mod rebuild;
+pub mod report;
+pub mod server;
+mod spill;
+pub mod sqlite;
+mod store;
+pub mod tcp;
+#[cfg(test)]
+mod test_util;
+mod transaction;
+pub mod watch;
+
+pub use account::{
+    Account, AccountBalance, AccountError, AccountManager, Ledger, LedgerEvent, ProcessOutcome,
+};
+pub use engine::{
+    DEFAULT_CHANNEL_CAPACITY, Engine, EngineBuilder, ProcessSummary, Rejection, error_code,
+    fingerprint_file,
+};
+pub use limits::{DEFAULT_PERIOD_SECS, LimitError, Limits, LimitsConfig};
+pub use spill::{DEFAULT_MAX_IN_MEMORY, SpillConfig};
+pub use store::{AccountStore, ConfigureAccount, LedgerStore};
+pub use transaction::{
+    AmountPolicy, ClientId, ClientIdError, ClientIdRepr, ClientTransaction, DisputeAction,
+    DisputeEvent, DisputePolicy, MoneyTransaction, Transaction, TransactionError, TransactionState,
+};
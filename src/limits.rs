@@ -0,0 +1,454 @@
+use crate::transaction::ClientId;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Default length of the rate-limit window used by `max_transactions`
+pub const DEFAULT_PERIOD_SECS: i64 = 86_400;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    DepositTooLarge { amount: Decimal, limit: Decimal },
+    WithdrawalTooLarge { amount: Decimal, limit: Decimal },
+    DailyWithdrawalCapExceeded { attempted: Decimal, limit: Decimal },
+    TooManyTransactions { limit: u32, period_secs: i64 },
+}
+
+impl LimitError {
+    /// Stable machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            LimitError::DepositTooLarge { .. } => "deposit_too_large",
+            LimitError::WithdrawalTooLarge { .. } => "withdrawal_too_large",
+            LimitError::DailyWithdrawalCapExceeded { .. } => "daily_withdrawal_cap_exceeded",
+            LimitError::TooManyTransactions { .. } => "too_many_transactions",
+        }
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::DepositTooLarge { amount, limit } => {
+                write!(f, "Deposit of {} exceeds the maximum of {}", amount, limit)
+            }
+            LimitError::WithdrawalTooLarge { amount, limit } => {
+                write!(
+                    f,
+                    "Withdrawal of {} exceeds the maximum of {}",
+                    amount, limit
+                )
+            }
+            LimitError::DailyWithdrawalCapExceeded { attempted, limit } => write!(
+                f,
+                "Daily withdrawal cap of {} exceeded (would reach {})",
+                limit, attempted
+            ),
+            LimitError::TooManyTransactions { limit, period_secs } => write!(
+                f,
+                "Transaction limit of {} per {}s exceeded",
+                limit, period_secs
+            ),
+        }
+    }
+}
+
+impl Error for LimitError {}
+
+/// Risk limits applied to a single client. `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_deposit: Option<Decimal>,
+    pub max_withdrawal: Option<Decimal>,
+    pub daily_withdrawal_cap: Option<Decimal>,
+    pub max_transactions: Option<u32>,
+    pub period_secs: i64,
+    /// How far below zero `available` may go through withdrawals
+    pub credit_limit: Option<Decimal>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_deposit: None,
+            max_withdrawal: None,
+            daily_withdrawal_cap: None,
+            max_transactions: None,
+            period_secs: DEFAULT_PERIOD_SECS,
+            credit_limit: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn check_deposit(&self, amount: Decimal) -> Result<(), LimitError> {
+        match self.max_deposit {
+            Some(limit) if amount > limit => Err(LimitError::DepositTooLarge { amount, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_withdrawal(&self, amount: Decimal) -> Result<(), LimitError> {
+        match self.max_withdrawal {
+            Some(limit) if amount > limit => Err(LimitError::WithdrawalTooLarge { amount, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Running usage counters an `Account` keeps to enforce its `Limits`
+#[derive(Debug, Clone, Default)]
+pub struct LimitUsage {
+    pub(crate) withdrawal_day: Option<NaiveDate>,
+    pub(crate) withdrawn_today: Decimal,
+    /// Times of accepted transactions still inside the rate window. Only kept
+    /// when `max_transactions` is set.
+    pub(crate) recent: VecDeque<DateTime<Utc>>,
+}
+
+impl LimitUsage {
+    /// Check the period-based limits for a transaction at `at` without recording it
+    pub fn check(
+        &mut self,
+        limits: &Limits,
+        at: DateTime<Utc>,
+        withdrawal: Option<Decimal>,
+    ) -> Result<(), LimitError> {
+        if let Some(limit) = limits.max_transactions {
+            let window_start = at - Duration::seconds(limits.period_secs);
+            while self.recent.front().is_some_and(|t| *t <= window_start) {
+                self.recent.pop_front();
+            }
+            if self.recent.len() >= limit as usize {
+                return Err(LimitError::TooManyTransactions {
+                    limit,
+                    period_secs: limits.period_secs,
+                });
+            }
+        }
+
+        if let (Some(amount), Some(limit)) = (withdrawal, limits.daily_withdrawal_cap) {
+            let attempted = self.withdrawn_on(at.date_naive()) + amount;
+            if attempted > limit {
+                return Err(LimitError::DailyWithdrawalCapExceeded { attempted, limit });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record an accepted transaction at `at`
+    pub fn record(&mut self, limits: &Limits, at: DateTime<Utc>, withdrawal: Option<Decimal>) {
+        if limits.max_transactions.is_some() {
+            self.recent.push_back(at);
+        }
+
+        if let Some(amount) = withdrawal {
+            let day = at.date_naive();
+            self.withdrawn_today = self.withdrawn_on(day) + amount;
+            self.withdrawal_day = Some(day);
+        }
+    }
+
+    fn withdrawn_on(&self, day: NaiveDate) -> Decimal {
+        if self.withdrawal_day == Some(day) {
+            self.withdrawn_today
+        } else {
+            Decimal::ZERO
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LimitsRecord {
+    client: String,
+    max_deposit: Option<Decimal>,
+    max_withdrawal: Option<Decimal>,
+    daily_withdrawal_cap: Option<Decimal>,
+    max_transactions: Option<u32>,
+    period_secs: Option<i64>,
+    #[serde(default)]
+    credit_limit: Option<Decimal>,
+}
+
+/// Default limits plus per-client overrides
+#[derive(Debug, Clone, Default)]
+pub struct LimitsConfig {
+    pub default: Limits,
+    pub overrides: HashMap<ClientId, Limits>,
+}
+
+impl LimitsConfig {
+    pub fn for_client(&self, client: ClientId) -> Limits {
+        self.overrides.get(&client).copied().unwrap_or(self.default)
+    }
+
+    /// Whether any client has been granted a credit line
+    pub fn has_credit_lines(&self) -> bool {
+        std::iter::once(&self.default)
+            .chain(self.overrides.values())
+            .any(|limits| limits.credit_limit.is_some_and(|c| c > Decimal::ZERO))
+    }
+
+    /// Load limits from a CSV file with the columns
+    /// `client, max_deposit, max_withdrawal, daily_withdrawal_cap, max_transactions, period_secs`
+    /// and an optional trailing `credit_limit` column.
+    ///
+    /// A row with client `*` sets the defaults. Empty fields in a client row
+    /// fall back to the defaults.
+    ///
+    /// Amounts and `credit_limit` must not be negative, `period_secs` must be
+    /// positive and each client may have only one row. Errors name the line.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers = reader.headers()?.clone();
+
+        let mut default = None;
+        let mut clients = Vec::new();
+        let mut first_lines = HashMap::new();
+        for row in reader.records() {
+            let row = row?;
+            let line = row.position().map_or(0, |position| position.line());
+            let record: LimitsRecord = row.deserialize(Some(&headers))?;
+            record
+                .validate()
+                .map_err(|e| format!("line {}, client {}: {}", line, record.client, e))?;
+
+            let client = match record.client.as_str() {
+                "*" => None,
+                id => Some(id.parse::<ClientId>().map_err(|e| {
+                    format!(
+                        "line {}: invalid client '{}' in limits file: {}",
+                        line, id, e
+                    )
+                })?),
+            };
+            if let Some(first) = first_lines.insert(client, line) {
+                let client = client.map_or("*".to_string(), |client| client.to_string());
+                return Err(format!(
+                    "line {}: client {} already has limits on line {}",
+                    line, client, first
+                )
+                .into());
+            }
+            match client {
+                Some(client) => clients.push((client, record)),
+                None => default = Some(record),
+            }
+        }
+
+        let mut config = LimitsConfig::default();
+        if let Some(record) = default {
+            config.default = record.apply_to(Limits::default());
+        }
+        for (client, record) in clients {
+            config
+                .overrides
+                .insert(client, record.apply_to(config.default));
+        }
+
+        Ok(config)
+    }
+}
+
+impl LimitsRecord {
+    /// Reject limits that can never be met, naming the offending column
+    fn validate(&self) -> Result<(), String> {
+        for (column, value) in [
+            ("max_deposit", self.max_deposit),
+            ("max_withdrawal", self.max_withdrawal),
+            ("daily_withdrawal_cap", self.daily_withdrawal_cap),
+            ("credit_limit", self.credit_limit),
+        ] {
+            if let Some(value) = value
+                && value < Decimal::ZERO
+            {
+                return Err(format!("{} must not be negative, got {}", column, value));
+            }
+        }
+        if let Some(period_secs) = self.period_secs
+            && period_secs <= 0
+        {
+            return Err(format!("period_secs must be positive, got {}", period_secs));
+        }
+        Ok(())
+    }
+
+    fn apply_to(&self, base: Limits) -> Limits {
+        Limits {
+            max_deposit: self.max_deposit.or(base.max_deposit),
+            max_withdrawal: self.max_withdrawal.or(base.max_withdrawal),
+            daily_withdrawal_cap: self.daily_withdrawal_cap.or(base.daily_withdrawal_cap),
+            max_transactions: self.max_transactions.or(base.max_transactions),
+            period_secs: self.period_secs.unwrap_or(base.period_secs),
+            credit_limit: self.credit_limit.or(base.credit_limit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_file;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_single_transaction_limits() {
+        let limits = Limits {
+            max_deposit: Some(dec!(100.00)),
+            max_withdrawal: Some(dec!(50.00)),
+            ..Limits::default()
+        };
+
+        assert!(limits.check_deposit(dec!(100.00)).is_ok());
+        assert_eq!(
+            limits.check_deposit(dec!(100.01)),
+            Err(LimitError::DepositTooLarge {
+                amount: dec!(100.01),
+                limit: dec!(100.00)
+            })
+        );
+        assert!(limits.check_withdrawal(dec!(50.00)).is_ok());
+        assert!(limits.check_withdrawal(dec!(50.01)).is_err());
+    }
+
+    #[test]
+    fn test_daily_withdrawal_cap_resets_next_day() {
+        let limits = Limits {
+            daily_withdrawal_cap: Some(dec!(100.00)),
+            ..Limits::default()
+        };
+        let mut usage = LimitUsage::default();
+        let day1 = Utc::now();
+
+        usage.check(&limits, day1, Some(dec!(60.00))).unwrap();
+        usage.record(&limits, day1, Some(dec!(60.00)));
+
+        assert_eq!(
+            usage.check(&limits, day1, Some(dec!(50.00))),
+            Err(LimitError::DailyWithdrawalCapExceeded {
+                attempted: dec!(110.00),
+                limit: dec!(100.00)
+            })
+        );
+
+        let day2 = day1 + Duration::days(1);
+        assert!(usage.check(&limits, day2, Some(dec!(50.00))).is_ok());
+    }
+
+    #[test]
+    fn test_transactions_per_period() {
+        let limits = Limits {
+            max_transactions: Some(2),
+            period_secs: 60,
+            ..Limits::default()
+        };
+        let mut usage = LimitUsage::default();
+        let start = Utc::now();
+
+        for i in 0..2 {
+            let at = start + Duration::seconds(i);
+            usage.check(&limits, at, None).unwrap();
+            usage.record(&limits, at, None);
+        }
+
+        assert_eq!(
+            usage.check(&limits, start + Duration::seconds(10), None),
+            Err(LimitError::TooManyTransactions {
+                limit: 2,
+                period_secs: 60
+            })
+        );
+
+        // The first transaction falls out of the window
+        assert!(
+            usage
+                .check(&limits, start + Duration::seconds(60), None)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_limits_file_overrides() {
+        let file = temp_file(
+            "client, max_deposit, max_withdrawal, daily_withdrawal_cap, max_transactions, period_secs\n\
+             *, 1000, 500, 2000, 10, 3600\n\
+             7, 5000, , , , \n",
+        );
+
+        let config = LimitsConfig::from_path(file.path()).unwrap();
+
+        let default = config.for_client(ClientId::new(1));
+        assert_eq!(default.max_deposit, Some(dec!(1000)));
+        assert_eq!(default.max_transactions, Some(10));
+        assert_eq!(default.period_secs, 3600);
+
+        let client7 = config.for_client(ClientId::new(7));
+        assert_eq!(client7.max_deposit, Some(dec!(5000)));
+        assert_eq!(client7.max_withdrawal, Some(dec!(500)));
+        assert_eq!(client7.daily_withdrawal_cap, Some(dec!(2000)));
+        assert_eq!(client7.credit_limit, None);
+        assert!(!config.has_credit_lines());
+    }
+
+    #[test]
+    fn test_limits_file_credit_limit_column() {
+        let file = temp_file(
+            "client, max_deposit, max_withdrawal, daily_withdrawal_cap, max_transactions, period_secs, credit_limit\n\
+             3, , , , , , 250\n",
+        );
+
+        let config = LimitsConfig::from_path(file.path()).unwrap();
+
+        assert_eq!(
+            config.for_client(ClientId::new(3)).credit_limit,
+            Some(dec!(250))
+        );
+        assert_eq!(config.for_client(ClientId::new(4)).credit_limit, None);
+        assert!(config.has_credit_lines());
+    }
+
+    #[test]
+    fn test_limits_file_rejects_unusable_limits() {
+        let error = |rows: &str| {
+            let file = temp_file(format!(
+                "client, max_deposit, max_withdrawal, daily_withdrawal_cap, max_transactions, period_secs, credit_limit\n{}",
+                rows
+            ));
+            LimitsConfig::from_path(file.path())
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error("*, 100, , , , , \n7, -5, , , , , \n"),
+            "line 3, client 7: max_deposit must not be negative, got -5"
+        );
+        assert_eq!(
+            error("7, , , -1, , , \n"),
+            "line 2, client 7: daily_withdrawal_cap must not be negative, got -1"
+        );
+        assert_eq!(
+            error("*, , , , 10, 0, \n"),
+            "line 2, client *: period_secs must be positive, got 0"
+        );
+        assert_eq!(
+            error("3, , , , , , -250\n"),
+            "line 2, client 3: credit_limit must not be negative, got -250"
+        );
+        assert_eq!(
+            error("7, 100, , , , , \n8, , , , , , \n07, 200, , , , , \n"),
+            "line 4: client 7 already has limits on line 2"
+        );
+        assert_eq!(
+            error("*, 100, , , , , \n*, 200, , , , , \n"),
+            "line 3: client * already has limits on line 2"
+        );
+    }
+}
@@ -1,35 +1,424 @@
-mod account;
-mod csv;
-mod transaction;
-
-use crate::account::AccountManager;
-use crate::transaction::Transaction;
+use chrono::Utc;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use flexi_logger::{Logger, WriteMode};
 use log::{error, info};
 use rust_decimal::Decimal;
 use std::env;
-use std::sync::Arc;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::net::TcpListener;
 use tokio::signal;
-use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
+use transactions::config::{CONFIG_ENV, Config, ConfigError};
+use transactions::csv::{self, ErrorPolicy};
+use transactions::generate::{self, GeneratorConfig};
+use transactions::sqlite::{self, SqliteAccountStore};
+use transactions::watch::{self, DirectoryWatcher};
+use transactions::{
+    AccountBalance, DEFAULT_MAX_IN_MEMORY, Engine, ProcessSummary, SpillConfig, grpc, report,
+    server, tcp,
+};
+
+/// Address `serve` listens on when `--addr` is not given
+const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:8080";
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  Partial failure: some rows were rejected or malformed, or a check found violations
+  2  Fatal error: bad usage or configuration, unreadable input, or processing stopped early";
+
+/// How a command ended, reported as the process exit code. Ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    Success,
+    PartialFailure,
+    /// Also the code clap exits with on a usage error
+    Fatal,
+}
+
+impl From<Outcome> for ExitCode {
+    fn from(outcome: Outcome) -> Self {
+        ExitCode::from(match outcome {
+            Outcome::Success => 0,
+            Outcome::PartialFailure => 1,
+            Outcome::Fatal => 2,
+        })
+    }
+}
+
+type CommandResult = Result<Outcome, Box<dyn Error>>;
+
+/// Apply deposits, withdrawals, disputes, resolves, chargebacks and reversals to
+/// client accounts
+#[derive(Debug, Parser)]
+#[command(name = "transactions", version, after_help = EXIT_CODES)]
+struct Cli {
+    #[command(flatten)]
+    settings: Settings,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Apply a CSV file and write the final balances to stdout
+    Process(ProcessArgs),
+    /// Parse a CSV file without applying it and report every malformed row
+    Validate {
+        /// Input CSV file
+        file: PathBuf,
+        /// Field separator of CSV input [io.delimiter]
+        #[arg(long, value_name = "CHAR", help_heading = "Input")]
+        delimiter: Option<char>,
+    },
+    /// Serve the engine over HTTP, and optionally gRPC and the TCP line protocol
+    Serve(ServeArgs),
+    /// Apply CSV files dropped into a directory until Ctrl-C
+    Watch(WatchArgs),
+    /// Rebuild every account in a state database from its ledger history and write
+    /// the rebuilt balances, reporting any that disagree with the stored ones
+    Replay {
+        /// SQLite database written with --state-db or --export-sqlite
+        state_db: PathBuf,
+        /// Save the rebuilt balances over any stored ones that disagree
+        #[arg(long)]
+        restore: bool,
+        #[command(flatten)]
+        limits: LimitsArgs,
+        #[command(flatten)]
+        disputes: DisputeArgs,
+    },
+    /// Write a synthetic input CSV
+    Generate(GenerateArgs),
+    /// Write balances or the disputes report from a state database
+    Report(ReportArgs),
+}
+
+impl Command {
+    /// Apply the flags this command was given over the settings they override
+    fn apply_settings(&self, config: &mut Config) {
+        match self {
+            Command::Process(args) => {
+                args.read.apply(config);
+                args.policies.apply(config);
+                args.output.apply(config);
+            }
+            Command::Validate { delimiter, .. } => set(&mut config.io.delimiter, delimiter),
+            Command::Serve(args) => {
+                set(&mut config.concurrency.ack_buffer, &args.ack_buffer);
+                args.policies.apply(config);
+            }
+            Command::Watch(args) => {
+                args.read.apply(config);
+                args.policies.apply(config);
+            }
+            Command::Replay {
+                limits, disputes, ..
+            } => {
+                limits.apply(config);
+                disputes.apply(config);
+            }
+            Command::Generate(_) => {}
+            Command::Report(args) => args.limits.apply(config),
+        }
+    }
+}
 
-/// Format decimal with at least 1 decimal place, up to 4 decimal places
-fn format_decimal(value: Decimal) -> String {
-    let s = value.to_string();
+/// Settings every command accepts. Each command also takes the flags overriding
+/// the config file settings it uses; the setting each flag overrides is in
+/// brackets. `TRANSACTIONS_<SECTION>_<KEY>` environment variables override the
+/// same settings, and the flags win over both.
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Settings")]
+struct Settings {
+    /// TOML config file
+    #[arg(long, global = true, value_name = "FILE", env = CONFIG_ENV)]
+    config: Option<PathBuf>,
+    /// Log spec for session.log [logging.level]
+    #[arg(long, global = true, value_name = "SPEC")]
+    log_level: Option<String>,
+}
+
+impl Settings {
+    fn apply(&self, config: &mut Config) {
+        set(&mut config.logging.level, &self.log_level);
+    }
+}
+
+/// How input CSV files are read
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Input")]
+struct ReadArgs {
+    /// Field separator of CSV input [io.delimiter]
+    #[arg(long, value_name = "CHAR")]
+    delimiter: Option<char>,
+    /// `stop` at a malformed row or `skip` it [errors.malformed_rows]
+    #[arg(long, value_name = "POLICY")]
+    on_malformed_row: Option<ErrorPolicy>,
+    /// Batches queued between the CSV reader and the processor [concurrency.channel_capacity]
+    #[arg(long, value_name = "N")]
+    channel_capacity: Option<usize>,
+    /// Transactions per batch [concurrency.batch_size]
+    #[arg(long, value_name = "N")]
+    batch_size: Option<usize>,
+    /// Parse each file on N threads [concurrency.parse_workers]
+    #[arg(long, value_name = "N")]
+    parse_workers: Option<usize>,
+    /// Bytes per chunk with --parse-workers [concurrency.chunk_size]
+    #[arg(long, value_name = "BYTES")]
+    chunk_size: Option<usize>,
+}
+
+impl ReadArgs {
+    fn apply(&self, config: &mut Config) {
+        set(&mut config.io.delimiter, &self.delimiter);
+        set(&mut config.errors.malformed_rows, &self.on_malformed_row);
+        set(
+            &mut config.concurrency.channel_capacity,
+            &self.channel_capacity,
+        );
+        set(&mut config.concurrency.batch_size, &self.batch_size);
+        set_some(&mut config.concurrency.parse_workers, &self.parse_workers);
+        set(&mut config.concurrency.chunk_size, &self.chunk_size);
+    }
+}
+
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Policies")]
+struct LimitsArgs {
+    /// Per-client risk limits CSV [limits.file]
+    #[arg(long, value_name = "FILE")]
+    limits: Option<PathBuf>,
+}
 
-    // If it already has a decimal point, return as-is
-    if s.contains('.') {
-        return s;
+impl LimitsArgs {
+    fn apply(&self, config: &mut Config) {
+        set_some(&mut config.limits.file, &self.limits);
     }
+}
+
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Policies")]
+struct DisputeArgs {
+    /// Maximum disputes per transaction [disputes.max_disputes]
+    #[arg(long, value_name = "N")]
+    max_disputes: Option<u32>,
+    /// Whether a chargeback locks the account [disputes.lock_on_chargeback]
+    #[arg(long, value_name = "BOOL")]
+    lock_on_chargeback: Option<bool>,
+}
+
+impl DisputeArgs {
+    fn apply(&self, config: &mut Config) {
+        set_some(&mut config.disputes.max_disputes, &self.max_disputes);
+        set(
+            &mut config.disputes.lock_on_chargeback,
+            &self.lock_on_chargeback,
+        );
+    }
+}
+
+/// Every policy applied to incoming transactions
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Policies")]
+struct PolicyArgs {
+    #[command(flatten)]
+    limits: LimitsArgs,
+    #[command(flatten)]
+    disputes: DisputeArgs,
+    /// Smallest deposit or withdrawal accepted [amounts.min_amount]
+    #[arg(long, value_name = "AMOUNT")]
+    min_amount: Option<Decimal>,
+    /// Most decimal places an amount may have [amounts.max_decimal_places]
+    #[arg(long, value_name = "N")]
+    max_decimal_places: Option<u32>,
+}
+
+impl PolicyArgs {
+    fn apply(&self, config: &mut Config) {
+        self.limits.apply(config);
+        self.disputes.apply(config);
+        set_some(&mut config.amounts.min_amount, &self.min_amount);
+        set_some(
+            &mut config.amounts.max_decimal_places,
+            &self.max_decimal_places,
+        );
+    }
+}
 
-    // Otherwise, add .0
-    format!("{}.0", s)
+/// Files written after a run
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Output")]
+struct OutputArgs {
+    /// Write the dispute lifecycle report to PATH [io.disputes_report]
+    #[arg(long, value_name = "PATH")]
+    disputes_report: Option<PathBuf>,
+    /// Write the final state to a new SQLite database [io.export_sqlite]
+    #[arg(long, value_name = "PATH")]
+    export_sqlite: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    fn apply(&self, config: &mut Config) {
+        set_some(&mut config.io.disputes_report, &self.disputes_report);
+        set_some(&mut config.io.export_sqlite, &self.export_sqlite);
+    }
+}
+
+/// Replace `setting` with the value of its flag, if the flag was given
+fn set<T: Clone>(setting: &mut T, flag: &Option<T>) {
+    if let Some(value) = flag {
+        *setting = value.clone();
+    }
+}
+
+/// `set` for settings that are off unless configured
+fn set_some<T: Clone>(setting: &mut Option<T>, flag: &Option<T>) {
+    if flag.is_some() {
+        *setting = flag.clone();
+    }
+}
+
+/// How the engine keeps its state
+#[derive(Debug, Default, Args)]
+struct EngineArgs {
+    /// Check balance invariants after every transaction (slow, for debugging)
+    #[arg(long)]
+    check_invariants: bool,
+    /// Move older ledger transactions and ledger history to a scratch file in DIR
+    #[arg(long, value_name = "DIR")]
+    spill_dir: Option<PathBuf>,
+    /// Transactions kept in memory across all ledgers with --spill-dir [default: 100000]
+    #[arg(long, value_name = "N", requires = "spill_dir")]
+    max_in_memory: Option<usize>,
+    /// Keep accounts in a SQLite database so later runs continue from this one
+    #[arg(long, value_name = "PATH")]
+    state_db: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ProcessArgs {
+    /// Input CSV file
+    file: PathBuf,
+    /// Rebuild balances from ledger history afterwards and report any differences
+    #[arg(long)]
+    verify: bool,
+    /// Check balance invariants of every account once the file is applied
+    #[arg(long)]
+    check_invariants_at_end: bool,
+    #[command(flatten)]
+    engine: EngineArgs,
+    #[command(flatten)]
+    read: ReadArgs,
+    #[command(flatten)]
+    policies: PolicyArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+struct ServeArgs {
+    /// HTTP listen address
+    #[arg(long, value_name = "HOST:PORT", default_value = DEFAULT_SERVE_ADDR)]
+    addr: String,
+    /// Also serve gRPC on this address
+    #[arg(long, value_name = "HOST:PORT")]
+    grpc_addr: Option<String>,
+    /// Also accept the TCP line protocol on this address
+    #[arg(long, value_name = "HOST:PORT")]
+    tcp_addr: Option<String>,
+    /// Acks buffered per gRPC stream or TCP connection [concurrency.ack_buffer]
+    #[arg(long, value_name = "N")]
+    ack_buffer: Option<usize>,
+    #[command(flatten)]
+    engine: EngineArgs,
+    #[command(flatten)]
+    policies: PolicyArgs,
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    /// Landing directory to scan for CSV files
+    dir: PathBuf,
+    /// Seconds between scans
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = watch::DEFAULT_POLL_INTERVAL.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    poll_interval: u64,
+    #[command(flatten)]
+    engine: EngineArgs,
+    #[command(flatten)]
+    read: ReadArgs,
+    #[command(flatten)]
+    policies: PolicyArgs,
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    /// Output file, or `-` for stdout
+    output: String,
+    /// Number of rows after the header
+    #[arg(long, default_value_t = GeneratorConfig::default().rows)]
+    rows: usize,
+    /// Client IDs are drawn from 1..=N
+    #[arg(long, value_name = "N", default_value_t = GeneratorConfig::default().clients)]
+    clients: u32,
+    /// Probability that a row disputes one of the client's earlier deposits
+    #[arg(long, value_name = "P", default_value_t = GeneratorConfig::default().dispute_rate)]
+    dispute_rate: f64,
+    /// Probability that a dispute ends in a chargeback rather than a resolve
+    #[arg(long, value_name = "P", default_value_t = GeneratorConfig::default().chargeback_rate)]
+    chargeback_rate: f64,
+    /// Probability that a row is one the engine rejects
+    #[arg(long, value_name = "P", default_value_t = GeneratorConfig::default().error_rate)]
+    error_rate: f64,
+    /// The same options and seed always produce the same file
+    #[arg(long, default_value_t = GeneratorConfig::default().seed)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct ReportArgs {
+    /// SQLite database written with --state-db or --export-sqlite
+    state_db: PathBuf,
+    /// Which report to write to stdout
+    #[arg(long, value_enum, default_value_t = ReportKind::Accounts)]
+    kind: ReportKind,
+    #[command(flatten)]
+    limits: LimitsArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ReportKind {
+    /// Balances per client, as written by `process`
+    Accounts,
+    /// Every transaction that has been disputed
+    Disputes,
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    let cli = Cli::parse_from(with_default_command(env::args_os().collect()));
+    let config = match load_config(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Outcome::Fatal.into();
+        }
+    };
+
     // Initialize flexi_logger with BufferAndFlush for better performance
-    let _logger_handle = Logger::try_with_str("info")
+    let _logger_handle = Logger::try_with_str(&config.logging.level)
         .expect("Failed to create logger")
         .log_to_file(
             flexi_logger::FileSpec::default()
@@ -44,83 +433,458 @@ async fn main() {
 
     info!("Starting transaction processor");
 
-    let args: Vec<String> = env::args().collect();
+    let outcome = match run(cli.command, &config).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("{}", e);
+            Outcome::Fatal
+        }
+    };
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <csv_file>", args[0]);
-        std::process::exit(1);
+    // Flush logs before exiting
+    _logger_handle.flush();
+    outcome.into()
+}
+
+/// `transactions <csv_file> ...` predates subcommands and still means `process`.
+/// `process` flags may come before the file, so look past them and their values,
+/// and put `process` in front of them all.
+fn with_default_command(mut args: Vec<OsString>) -> Vec<OsString> {
+    let command = Cli::command();
+    let process = command
+        .find_subcommand("process")
+        .expect("process is a subcommand");
+    let takes_value = |flag: &str| {
+        command
+            .get_arguments()
+            .chain(process.get_arguments())
+            .any(|arg| arg.get_long() == Some(flag) && arg.get_action().takes_values())
+    };
+
+    let mut index = 1;
+    while let Some(arg) = args.get(index).and_then(|arg| arg.to_str()) {
+        if arg == "--" {
+            return args;
+        }
+        match arg.strip_prefix("--") {
+            Some(flag) if !flag.contains('=') && takes_value(flag) => index += 2,
+            _ if arg.starts_with('-') => index += 1,
+            _ => {
+                if arg != "help" && command.find_subcommand(arg).is_none() {
+                    args.insert(1, "process".into());
+                }
+                return args;
+            }
+        }
+    }
+    args
+}
+
+/// Read the config file named by `--config` or `TRANSACTIONS_CONFIG`, apply
+/// `TRANSACTIONS_*` environment variables and then flags, and validate the result
+fn load_config(cli: &Cli) -> Result<Config, ConfigError> {
+    let mut config = match &cli.settings.config {
+        Some(path) => Config::from_path(path)?,
+        None => Config::default(),
+    };
+
+    let ignored =
+        config.apply_env(env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        }))?;
+    for name in ignored {
+        eprintln!("Ignoring {}: not a config setting", name);
     }
+    cli.settings.apply(&mut config);
+    cli.command.apply_settings(&mut config);
+    config.validate()?;
+    Ok(config)
+}
 
-    let filename = args[1].clone();
-    info!("Processing file: {}", filename);
+async fn run(command: Command, config: &Config) -> CommandResult {
+    match command {
+        Command::Process(args) => process(args, config).await,
+        Command::Validate { file, .. } => validate(&file, config),
+        Command::Serve(args) => serve(args, config).await,
+        Command::Watch(args) => watch(args, config).await,
+        Command::Replay {
+            state_db, restore, ..
+        } => replay(&state_db, restore, config).await,
+        Command::Generate(args) => generate(args),
+        Command::Report(args) => write_report(args, config).await,
+    }
+}
 
+/// Build the engine from `config` and `args`. Also returns whether any client has a
+/// credit line.
+fn build_engine(config: &Config, args: &EngineArgs) -> Result<(Engine, bool), Box<dyn Error>> {
+    let limits = config.limits()?;
+    let show_credit = limits.has_credit_lines();
+    let mut builder = config
+        .engine_builder(limits)
+        .check_invariants(args.check_invariants);
+    if let Some(dir) = &args.spill_dir {
+        builder = builder.ledger_spill(SpillConfig {
+            dir: dir.clone(),
+            max_in_memory: args.max_in_memory.unwrap_or(DEFAULT_MAX_IN_MEMORY),
+        });
+    }
+    if let Some(path) = &args.state_db {
+        let store = SqliteAccountStore::open(path)
+            .map_err(|e| format!("Error opening state database {}: {}", path.display(), e))?;
+        builder = builder.account_store(store);
+    }
+    Ok((builder.build(), show_credit))
+}
+
+/// Build an engine on an existing state database, refusing to create a new one.
+/// `read_only` opens it without writing anything, not even the schema.
+fn open_state(
+    path: &Path,
+    config: &Config,
+    read_only: bool,
+) -> Result<(Engine, bool), Box<dyn Error>> {
+    if !path.is_file() {
+        return Err(format!("State database {} does not exist", path.display()).into());
+    }
+    let store = if read_only {
+        SqliteAccountStore::open_read_only(path)
+    } else {
+        SqliteAccountStore::open(path)
+    }
+    .map_err(|e| format!("Error opening state database {}: {}", path.display(), e))?;
+    let limits = config.limits()?;
+    let show_credit = limits.has_credit_lines();
+    let engine = config.engine_builder(limits).account_store(store).build();
+    Ok((engine, show_credit))
+}
+
+/// Fatal if the input was not read to the end, a partial failure if any row was
+/// rejected or skipped as malformed
+fn summary_outcome(summary: &ProcessSummary) -> Outcome {
+    if summary.read_error.is_some() {
+        Outcome::Fatal
+    } else if !summary.rejected.is_empty() || !summary.malformed.is_empty() {
+        Outcome::PartialFailure
+    } else {
+        Outcome::Success
+    }
+}
+
+fn write_balances(balances: &[AccountBalance], show_credit: bool) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    report::write_accounts(balances, show_credit, &mut stdout)
+        .map_err(|e| io::Error::new(e.kind(), format!("Error writing output: {}", e)))
+}
+
+async fn process(args: ProcessArgs, config: &Config) -> CommandResult {
+    info!("Processing file: {}", args.file.display());
+
+    let (engine, show_credit) = build_engine(config, &args.engine)?;
     let cancel_token = CancellationToken::new();
-    let account_manager = Arc::new(AccountManager::new());
 
-    let (tx, mut rx) = mpsc::channel::<Transaction>(100);
+    let summary = tokio::select! {
+        _ = signal::ctrl_c() => {
+            eprintln!("\nReceived Ctrl-C, shutting down gracefully...");
+            cancel_token.cancel();
+            return Ok(Outcome::Fatal);
+        }
+        summary = engine.process_csv(&args.file, cancel_token.clone()) => summary,
+    };
+
+    if summary.skipped {
+        eprintln!(
+            "Skipped {}: already ingested into the state database",
+            args.file.display()
+        );
+    }
+    for rejection in &summary.rejected {
+        eprintln!("Error processing transaction: {}", rejection.reason);
+    }
+    for error in &summary.malformed {
+        eprintln!("Skipped malformed row: {}", error);
+    }
+    if summary.replayed > 0 {
+        eprintln!("Ignored {} replayed dispute rows", summary.replayed);
+    }
+    if let Some(e) = &summary.read_error {
+        eprintln!("Error processing CSV: {}", e);
+    }
+    eprintln!("Finished reading CSV");
+    let mut outcome = summary_outcome(&summary);
+
+    let mut violations = engine.account_manager().invariant_violations();
+    if args.check_invariants_at_end {
+        violations.extend(engine.check_invariants().await?);
+    }
+    for violation in &violations {
+        error!("Invariant violation: {}", violation);
+        eprintln!("Invariant violation: {}", violation);
+    }
+    if !violations.is_empty() {
+        outcome = outcome.max(Outcome::PartialFailure);
+    }
+
+    if args.verify {
+        let report = engine.verify().await?;
+        for violation in &report.violations {
+            error!("Consistency violation: {}", violation);
+            eprintln!("Consistency violation: {}", violation);
+        }
+        if report.is_consistent() {
+            eprintln!(
+                "Rebuilt {} accounts from ledger history, no violations",
+                report.accounts.len()
+            );
+        } else {
+            outcome = outcome.max(Outcome::PartialFailure);
+        }
+    }
+
+    // Output CSV to stdout
+    write_balances(&engine.balances().await?, show_credit)?;
+
+    if let Some(path) = &config.io.disputes_report {
+        let result = engine
+            .with_accounts(|accounts| {
+                let mut writer = BufWriter::new(File::create(path)?);
+                report::write_disputes_report(accounts, Utc::now(), &mut writer)?;
+                writer.flush()
+            })
+            .await;
+        if let Err(e) = result {
+            error!("Error writing disputes report {}: {}", path.display(), e);
+            eprintln!("Error writing disputes report {}: {}", path.display(), e);
+            outcome = Outcome::Fatal;
+        }
+    }
+
+    if let Some(path) = &config.io.export_sqlite
+        && let Err(e) = engine
+            .with_accounts(|accounts| sqlite::export(path, accounts, &summary.rejected))
+            .await
+    {
+        error!("Error exporting to {}: {}", path.display(), e);
+        eprintln!("Error exporting to {}: {}", path.display(), e);
+        outcome = Outcome::Fatal;
+    }
+
+    eprintln!("Processing complete");
+    Ok(outcome)
+}
+
+fn validate(file: &Path, config: &Config) -> CommandResult {
+    let validation = csv::validate_csv(file, config.io.delimiter as u8)
+        .map_err(|e| format!("Error reading {}: {}", file.display(), e))?;
+
+    for error in &validation.errors {
+        eprintln!("{}", error);
+    }
+    eprintln!(
+        "{} valid rows, {} malformed",
+        validation.valid,
+        validation.errors.len()
+    );
+    Ok(if validation.errors.is_empty() {
+        Outcome::Success
+    } else {
+        Outcome::PartialFailure
+    })
+}
+
+async fn bind(addr: &str) -> Result<TcpListener, Box<dyn Error>> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Error binding {}: {}", addr, e).into())
+}
+
+async fn serve(args: ServeArgs, config: &Config) -> CommandResult {
+    let ack_buffer = config.concurrency.ack_buffer;
+    let (engine, _) = build_engine(config, &args.engine)?;
 
-    let sender_cancel_token = cancel_token.clone();
-    let sender_handle: tokio::task::JoinHandle<()> = tokio::spawn(async move {
-        match csv::process_csv_with_channel(&filename, tx, sender_cancel_token).await {
-            Ok(_) => eprintln!("Finished reading CSV"),
-            Err(e) => eprintln!("Error processing CSV: {}", e),
+    let listener = bind(&args.addr).await?;
+    info!("Serving HTTP API on {}", args.addr);
+    eprintln!("Serving HTTP API on {}", args.addr);
+
+    let cancel_token = CancellationToken::new();
+    let shutdown_token = cancel_token.clone();
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            eprintln!("\nReceived Ctrl-C, shutting down gracefully...");
+            shutdown_token.cancel();
         }
     });
 
-    let manager_clone = Arc::clone(&account_manager);
-    let receiver_handle = tokio::spawn(async move {
-        while let Some(transaction) = rx.recv().await {
-            info!("Received transaction: {:?}", transaction);
-            match manager_clone.process_transaction(transaction).await {
-                Ok(_) => {
-                    info!("Transaction processed successfully");
+    // Each optional server reports whether it stopped cleanly
+    let grpc_handle = match &args.grpc_addr {
+        Some(grpc_addr) => {
+            let grpc_listener = bind(grpc_addr).await?;
+            info!("Serving gRPC API on {}", grpc_addr);
+            eprintln!("Serving gRPC API on {}", grpc_addr);
+
+            let service = grpc::GrpcService::new(engine.clone(), ack_buffer);
+            let grpc_token = cancel_token.clone();
+            Some(tokio::spawn(async move {
+                if let Err(e) = grpc::serve(service, grpc_listener, grpc_token.clone()).await {
+                    error!("gRPC server error: {}", e);
+                    eprintln!("gRPC server error: {}", e);
+                    grpc_token.cancel();
+                    return false;
                 }
-                Err(e) => {
-                    error!("Error processing transaction: {}", e);
-                    eprintln!("Error processing transaction: {}", e);
+                true
+            }))
+        }
+        None => None,
+    };
+
+    let tcp_handle = match &args.tcp_addr {
+        Some(tcp_addr) => {
+            let tcp_listener = bind(tcp_addr).await?;
+            info!("Serving line protocol on {}", tcp_addr);
+            eprintln!("Serving line protocol on {}", tcp_addr);
+
+            let tcp_engine = engine.clone();
+            let tcp_token = cancel_token.clone();
+            Some(tokio::spawn(async move {
+                let result =
+                    tcp::serve(tcp_engine, tcp_listener, ack_buffer, tcp_token.clone()).await;
+                if let Err(e) = result {
+                    error!("Line-protocol server error: {}", e);
+                    eprintln!("Line-protocol server error: {}", e);
+                    tcp_token.cancel();
+                    return false;
                 }
-            }
+                true
+            }))
         }
-    });
+        None => None,
+    };
 
-    tokio::select! {
-        _ = signal::ctrl_c() => {
-            eprintln!("\nReceived Ctrl-C, shutting down gracefully...");
-            cancel_token.cancel();
+    let mut outcome = Outcome::Success;
+    if let Err(e) = server::serve(engine, listener, cancel_token.clone()).await {
+        error!("HTTP server error: {}", e);
+        eprintln!("HTTP server error: {}", e);
+        cancel_token.cancel();
+        outcome = Outcome::Fatal;
+    }
+
+    for handle in [grpc_handle, tcp_handle].into_iter().flatten() {
+        if !handle.await.unwrap_or(false) {
+            outcome = Outcome::Fatal;
         }
-        result = async {
-            tokio::try_join!(sender_handle, receiver_handle)
-        } => {
-            match result {
-                Ok(_) => {
-                    eprintln!("Finished reading CSV");
-
-                    // Output CSV to stdout
-                    println!("client, available, held, total, locked");
-
-                    let accounts = account_manager.accounts().await;
-                    let mut clients: Vec<_> = accounts.keys().collect();
-                    clients.sort();
-
-                    for client_id in clients {
-                        if let Some(account) = accounts.get(client_id) {
-                            println!("{}, {}, {}, {}, {}",
-                                account.client,
-                                format_decimal(account.available),
-                                format_decimal(account.held),
-                                format_decimal(account.total),
-                                account.locked
-                            );
-                        }
-                    }
-
-                    eprintln!("Processing complete");
-                },
-                Err(e) => eprintln!("Error: {:?}", e),
-            }
+    }
+    Ok(outcome)
+}
+
+async fn watch(args: WatchArgs, config: &Config) -> CommandResult {
+    let (engine, show_credit) = build_engine(config, &args.engine)?;
+    let dir = args.dir.display();
+
+    info!("Watching directory: {}", dir);
+    eprintln!("Watching {} for CSV files", dir);
+
+    let cancel_token = CancellationToken::new();
+    let shutdown_token = cancel_token.clone();
+    tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            eprintln!("\nReceived Ctrl-C, shutting down gracefully...");
+            shutdown_token.cancel();
         }
+    });
+
+    let mut watcher = DirectoryWatcher::new(engine, &args.dir)
+        .poll_interval(Duration::from_secs(args.poll_interval));
+    let mut outcome = Outcome::Success;
+    if let Err(e) = watcher.run(cancel_token).await {
+        error!("Error watching {}: {}", dir, e);
+        eprintln!("Error watching {}: {}", dir, e);
+        outcome = Outcome::Fatal;
     }
 
-    // Flush logs before exiting
-    _logger_handle.flush();
+    // Balances accumulated across every file seen this session
+    write_balances(&watcher.engine().balances().await?, show_credit)?;
+    Ok(outcome)
+}
+
+async fn replay(state_db: &Path, restore: bool, config: &Config) -> CommandResult {
+    let (engine, show_credit) = open_state(state_db, config, !restore)?;
+    let report = if restore {
+        engine.rebuild().await?
+    } else {
+        engine.verify().await?
+    };
+
+    for violation in &report.violations {
+        error!("Consistency violation: {}", violation);
+        eprintln!("Consistency violation: {}", violation);
+    }
+    eprintln!(
+        "Rebuilt {} accounts from ledger history, {} violations",
+        report.accounts.len(),
+        report.violations.len()
+    );
+    if restore && !report.is_consistent() {
+        eprintln!("Saved the rebuilt balances over the stored ones");
+    }
+
+    let mut balances: Vec<_> = report
+        .accounts
+        .iter()
+        .map(|(&client, rebuilt)| AccountBalance {
+            client,
+            available: rebuilt.available,
+            held: rebuilt.held,
+            total: rebuilt.total,
+            locked: rebuilt.locked,
+            credit_used: rebuilt.credit_used,
+        })
+        .collect();
+    balances.sort_by_key(|balance| balance.client);
+    write_balances(&balances, show_credit)?;
+
+    Ok(if report.is_consistent() {
+        Outcome::Success
+    } else {
+        Outcome::PartialFailure
+    })
+}
+
+fn generate(args: GenerateArgs) -> CommandResult {
+    let config = GeneratorConfig {
+        rows: args.rows,
+        clients: args.clients,
+        dispute_rate: args.dispute_rate,
+        chargeback_rate: args.chargeback_rate,
+        error_rate: args.error_rate,
+        seed: args.seed,
+    };
+    config.validate()?;
+
+    let output = args.output;
+    info!("Generating {} rows into {}", config.rows, output);
+    let result = if output == "-" {
+        generate::write_csv(&config, BufWriter::new(io::stdout().lock()))
+    } else {
+        File::create(&output).and_then(|file| generate::write_csv(&config, BufWriter::new(file)))
+    };
+    let rows = result.map_err(|e| format!("Error generating {}: {}", output, e))?;
+    eprintln!("Generated {} rows", rows);
+    Ok(Outcome::Success)
+}
+
+async fn write_report(args: ReportArgs, config: &Config) -> CommandResult {
+    let (engine, show_credit) = open_state(&args.state_db, config, true)?;
+    match args.kind {
+        ReportKind::Accounts => write_balances(&engine.balances().await?, show_credit)?,
+        ReportKind::Disputes => {
+            engine
+                .with_accounts(|accounts| {
+                    let mut stdout = io::stdout().lock();
+                    report::write_disputes_report(accounts, Utc::now(), &mut stdout)
+                })
+                .await?
+        }
+    }
+    Ok(Outcome::Success)
 }
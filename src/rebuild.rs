@@ -0,0 +1,348 @@
+use crate::account::{Account, Ledger, LedgerEvent};
+use crate::transaction::{ClientId, DisputePolicy, Transaction};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+/// Balances of one account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// See `Account::credit_used`
+    pub credit_used: Decimal,
+}
+
+impl Balances {
+    pub fn of(account: &Account) -> Self {
+        Self {
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            credit_used: account.credit_used(),
+        }
+    }
+
+    /// Replace `account`'s balances with these rebuilt ones
+    pub fn restore(&self, account: &mut Account) {
+        account.available = self.available;
+        account.held = self.held;
+        account.total = self.total;
+        account.locked = self.locked;
+        account.credit_drawn = self.credit_used;
+    }
+
+    /// Differences between these rebuilt balances and `account`'s current ones
+    pub fn compare(&self, account: &Account) -> Vec<ConsistencyViolation> {
+        let current = Self::of(account);
+        let mut violations = Vec::new();
+
+        for (field, current, rebuilt) in [
+            ("available", current.available, self.available),
+            ("held", current.held, self.held),
+            ("total", current.total, self.total),
+            ("credit_used", current.credit_used, self.credit_used),
+        ] {
+            if current != rebuilt {
+                violations.push(ConsistencyViolation::BalanceMismatch {
+                    client: account.client,
+                    field,
+                    current,
+                    rebuilt,
+                });
+            }
+        }
+        if current.locked != self.locked {
+            violations.push(ConsistencyViolation::LockedMismatch {
+                client: account.client,
+                current: current.locked,
+                rebuilt: self.locked,
+            });
+        }
+        violations
+    }
+}
+
+/// A way in which current account state disagrees with its ledger history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsistencyViolation {
+    BalanceMismatch {
+        client: ClientId,
+        field: &'static str,
+        current: Decimal,
+        rebuilt: Decimal,
+    },
+    LockedMismatch {
+        client: ClientId,
+        current: bool,
+        rebuilt: bool,
+    },
+    /// The history refers to a transaction the ledger does not hold
+    UnknownTransaction { client: ClientId, tx: u32 },
+    /// The ledger's store failed to read its history or a transaction
+    Unreadable { client: ClientId, error: String },
+}
+
+impl ConsistencyViolation {
+    pub fn client(&self) -> ClientId {
+        match self {
+            ConsistencyViolation::BalanceMismatch { client, .. }
+            | ConsistencyViolation::LockedMismatch { client, .. }
+            | ConsistencyViolation::UnknownTransaction { client, .. }
+            | ConsistencyViolation::Unreadable { client, .. } => *client,
+        }
+    }
+}
+
+impl fmt::Display for ConsistencyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsistencyViolation::BalanceMismatch {
+                client,
+                field,
+                current,
+                rebuilt,
+            } => write!(
+                f,
+                "Client {}: {} is {} but the ledger history gives {}",
+                client, field, current, rebuilt
+            ),
+            ConsistencyViolation::LockedMismatch {
+                client,
+                current,
+                rebuilt,
+            } => write!(
+                f,
+                "Client {}: locked is {} but the ledger history gives {}",
+                client, current, rebuilt
+            ),
+            ConsistencyViolation::UnknownTransaction { client, tx } => write!(
+                f,
+                "Client {}: history refers to unknown transaction {}",
+                client, tx
+            ),
+            ConsistencyViolation::Unreadable { client, error } => {
+                write!(f, "Client {}: ledger could not be read: {}", client, error)
+            }
+        }
+    }
+}
+
+/// Result of `AccountManager::rebuild`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RebuildReport {
+    /// Rebuilt balances of every account whose history could be replayed
+    pub accounts: HashMap<ClientId, Balances>,
+    pub violations: Vec<ConsistencyViolation>,
+}
+
+impl RebuildReport {
+    pub fn is_consistent(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Replay a ledger's history from zero balances, locking on chargebacks if `policy` does.
+///
+/// The arithmetic is deliberately independent of `Account`'s balance helpers, so
+/// a helper that skips or misapplies an update shows up as a difference.
+pub fn replay(
+    client: ClientId,
+    ledger: &Ledger,
+    policy: &DisputePolicy,
+) -> Result<Balances, ConsistencyViolation> {
+    let mut balances = Balances::default();
+
+    let unreadable = |e: io::Error| ConsistencyViolation::Unreadable {
+        client,
+        error: e.to_string(),
+    };
+    for event in ledger.history() {
+        let event = event.map_err(unreadable)?;
+        let tx = tx_id(&event);
+        let transaction = ledger.get_transaction(tx).map_err(unreadable)?;
+        let (is_deposit, amount) = match transaction.as_deref() {
+            Some(Transaction::Deposit(money_tx)) => (true, money_tx.amount),
+            Some(Transaction::Withdrawal(money_tx)) => (false, money_tx.amount),
+            _ => return Err(ConsistencyViolation::UnknownTransaction { client, tx }),
+        };
+
+        match event {
+            LedgerEvent::Deposit(_) => {
+                balances.available += amount;
+                balances.total += amount;
+            }
+            LedgerEvent::Withdrawal(_) => {
+                let covered = amount.min(balances.available.max(Decimal::ZERO));
+                balances.credit_used += amount - covered;
+                balances.available -= amount;
+                balances.total -= amount;
+            }
+            LedgerEvent::Dispute(_) => {
+                balances.available -= amount;
+                balances.held += amount;
+            }
+            LedgerEvent::Resolve(_) => {
+                balances.held -= amount;
+                balances.available += amount;
+            }
+            LedgerEvent::Chargeback(_) => {
+                balances.held -= amount;
+                balances.total -= amount;
+                balances.locked |= policy.lock_on_chargeback;
+            }
+            LedgerEvent::Reversal(_) if is_deposit => {
+                // Taken back like a withdrawal, drawing on credit for funds already spent
+                let covered = amount.min(balances.available.max(Decimal::ZERO));
+                balances.credit_used += amount - covered;
+                balances.available -= amount;
+                balances.total -= amount;
+            }
+            LedgerEvent::Reversal(_) => {
+                balances.available += amount;
+                balances.total += amount;
+            }
+        }
+        // Credit is paid off before anything else once `available` rises again
+        let below_zero = (-balances.available).max(Decimal::ZERO);
+        balances.credit_used = balances.credit_used.min(below_zero);
+    }
+
+    Ok(balances)
+}
+
+fn tx_id(event: &LedgerEvent) -> u32 {
+    match *event {
+        LedgerEvent::Deposit(tx)
+        | LedgerEvent::Withdrawal(tx)
+        | LedgerEvent::Dispute(tx)
+        | LedgerEvent::Resolve(tx)
+        | LedgerEvent::Chargeback(tx)
+        | LedgerEvent::Reversal(tx) => tx,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountManager;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn test_rebuild_matches_processed_state() {
+        let manager = AccountManager::new();
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(10.0)).unwrap(),
+            Transaction::deposit(1, 2, dec!(5.0)).unwrap(),
+            Transaction::withdrawal(1, 3, dec!(3.0)).unwrap(),
+            Transaction::dispute(1, 1),
+            Transaction::resolve(1, 1),
+            Transaction::reversal(1, 3),
+            Transaction::dispute(1, 2),
+            Transaction::chargeback(1, 2),
+            Transaction::deposit(2, 4, dec!(1.0)).unwrap(),
+            Transaction::withdrawal(2, 5, dec!(2.0)).unwrap(),
+        ] {
+            // Rejected transactions never reach the history
+            manager.process_transaction(transaction).await.ok();
+        }
+
+        let report = manager.verify().await.unwrap();
+        assert!(report.is_consistent(), "{:?}", report.violations);
+        assert_eq!(
+            report.accounts[&ClientId::new(1)],
+            Balances {
+                available: dec!(10.0),
+                held: dec!(0.0),
+                total: dec!(10.0),
+                locked: true,
+                credit_used: Decimal::ZERO,
+            }
+        );
+        assert_eq!(report.accounts[&ClientId::new(2)].available, dec!(1.0));
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_restores_drifted_balances() {
+        let mut account = Account::new(7);
+        account
+            .process_transaction(Transaction::deposit(7, 1, dec!(10.0)).unwrap())
+            .unwrap();
+        account.available = dec!(4.0);
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(HashMap::from([(account.client, account)])));
+
+        assert!(!manager.verify().await.unwrap().is_consistent());
+        // Verifying leaves the drifted balance alone
+        assert_eq!(
+            manager.get_account(7).await.unwrap().unwrap().available,
+            dec!(4.0)
+        );
+
+        let report = manager.rebuild().await.unwrap();
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(
+            manager.get_account(7).await.unwrap().unwrap().available,
+            dec!(10.0)
+        );
+        assert!(manager.verify().await.unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_balance_drift_is_reported() {
+        let mut account = Account::new(7);
+        account
+            .process_transaction(Transaction::deposit(7, 1, dec!(10.0)).unwrap())
+            .unwrap();
+        account
+            .process_transaction(Transaction::dispute(7, 1))
+            .unwrap();
+
+        // A helper that skips its update while the account is locked
+        account.locked = true;
+        account.resolve(dec!(10.0));
+        account.ledger.record(LedgerEvent::Resolve(1));
+
+        let rebuilt = replay(ClientId::new(7), &account.ledger, &account.dispute_policy).unwrap();
+        let violations = rebuilt.compare(&account);
+        assert_eq!(
+            violations,
+            vec![
+                ConsistencyViolation::BalanceMismatch {
+                    client: ClientId::new(7),
+                    field: "available",
+                    current: dec!(0.0),
+                    rebuilt: dec!(10.0),
+                },
+                ConsistencyViolation::BalanceMismatch {
+                    client: ClientId::new(7),
+                    field: "held",
+                    current: dec!(10.0),
+                    rebuilt: dec!(0.0),
+                },
+                ConsistencyViolation::LockedMismatch {
+                    client: ClientId::new(7),
+                    current: true,
+                    rebuilt: false,
+                },
+            ]
+        );
+        assert_eq!(
+            violations[0].to_string(),
+            "Client 7: available is 0.0 but the ledger history gives 10.0"
+        );
+
+        account.ledger.record(LedgerEvent::Dispute(99));
+        assert_eq!(
+            replay(ClientId::new(7), &account.ledger, &account.dispute_policy),
+            Err(ConsistencyViolation::UnknownTransaction {
+                client: ClientId::new(7),
+                tx: 99
+            })
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use crate::account::{Account, AccountBalance};
+use crate::engine::ProcessSummary;
+use crate::transaction::Transaction;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/// Format decimal with at least 1 decimal place, up to 4 decimal places
+pub fn format_decimal(value: Decimal) -> String {
+    let s = value.to_string();
+
+    // If it already has a decimal point, return as-is
+    if s.contains('.') {
+        return s;
+    }
+
+    // Otherwise, add .0
+    format!("{}.0", s)
+}
+
+/// Write per-client balances in the given order, optionally with a `credit_used`
+/// column. `AccountManager::balances` returns them sorted by client.
+pub fn write_accounts<W: Write>(
+    balances: &[AccountBalance],
+    show_credit: bool,
+    writer: &mut W,
+) -> io::Result<()> {
+    if show_credit {
+        writeln!(
+            writer,
+            "client, available, held, total, locked, credit_used"
+        )?;
+    } else {
+        writeln!(writer, "client, available, held, total, locked")?;
+    }
+
+    for balance in balances {
+        write!(
+            writer,
+            "{}, {}, {}, {}, {}",
+            balance.client,
+            format_decimal(balance.available),
+            format_decimal(balance.held),
+            format_decimal(balance.total),
+            balance.locked
+        )?;
+        if show_credit {
+            write!(writer, ", {}", format_decimal(balance.credit_used))?;
+        }
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Write every transaction that has ever been disputed, sorted by client and tx.
+///
+/// `dispute_age_secs` is only filled in for disputes that are still open at `now`.
+pub fn write_disputes_report<'a, W: Write>(
+    accounts: impl IntoIterator<Item = io::Result<Cow<'a, Account>>>,
+    now: DateTime<Utc>,
+    writer: &mut W,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "client, tx, type, amount, state, disputes, dispute_age_secs"
+    )?;
+
+    // Only disputed transactions are collected, so accounts can be read one at a time
+    let mut disputed = Vec::new();
+    for account in accounts {
+        for transaction in account?.ledger.transactions() {
+            let transaction = transaction?;
+            let (tx_type, money_tx) = match transaction.as_ref() {
+                Transaction::Deposit(money_tx) => ("deposit", money_tx),
+                Transaction::Withdrawal(money_tx) => ("withdrawal", money_tx),
+                _ => continue,
+            };
+            if money_tx.dispute_count > 0 {
+                disputed.push((tx_type, money_tx.clone()));
+            }
+        }
+    }
+    disputed.sort_by_key(|(_, money_tx)| (money_tx.id.client, money_tx.id.tx));
+
+    for (tx_type, money_tx) in disputed {
+        let age = money_tx
+            .dispute_opened_at()
+            .map(|opened| (now - opened).num_seconds().to_string())
+            .unwrap_or_default();
+
+        writeln!(
+            writer,
+            "{}, {}, {}, {}, {}, {}, {}",
+            money_tx.id.client,
+            money_tx.id.tx,
+            tx_type,
+            format_decimal(money_tx.amount),
+            money_tx.state,
+            money_tx.dispute_count,
+            age
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the outcome of processing one input file, one `key: value` per line.
+///
+/// Each rejected transaction gets its own `rejection: client, tx, code, reason` line.
+/// Each malformed row skipped under `ErrorPolicy::Skip` gets a `malformed: error` line.
+pub fn write_process_summary<W: Write>(
+    file_name: &str,
+    summary: &ProcessSummary,
+    writer: &mut W,
+) -> io::Result<()> {
+    let status = if summary.skipped {
+        "skipped"
+    } else if summary.read_error.is_some() {
+        "failed"
+    } else {
+        "done"
+    };
+    writeln!(writer, "file: {}", file_name)?;
+    writeln!(
+        writer,
+        "fingerprint: {}",
+        summary.fingerprint.as_deref().unwrap_or_default()
+    )?;
+    writeln!(writer, "status: {}", status)?;
+    writeln!(writer, "processed: {}", summary.processed)?;
+    writeln!(writer, "replayed: {}", summary.replayed)?;
+    writeln!(writer, "rejected: {}", summary.rejected.len())?;
+    writeln!(
+        writer,
+        "read_error: {}",
+        summary.read_error.as_deref().unwrap_or_default()
+    )?;
+    for rejection in &summary.rejected {
+        writeln!(
+            writer,
+            "rejection: {}, {}, {}, {}",
+            rejection.client, rejection.tx, rejection.code, rejection.reason
+        )?;
+    }
+    for error in &summary.malformed {
+        writeln!(writer, "malformed: {}", error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Rejection;
+    use crate::transaction::{ClientId, ClientTransaction, MoneyTransaction};
+    use chrono::Duration;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_format_decimal() {
+        assert_eq!(format_decimal(dec!(1)), "1.0");
+        assert_eq!(format_decimal(dec!(1.5)), "1.5");
+        assert_eq!(format_decimal(dec!(1.2345)), "1.2345");
+    }
+
+    #[test]
+    fn test_write_accounts() {
+        let mut account = Account::new(2);
+        account.deposit(dec!(3));
+        let balances = [
+            AccountBalance::from(&Account::new(1)),
+            AccountBalance::from(&account),
+        ];
+
+        let mut output = Vec::new();
+        write_accounts(&balances, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client, available, held, total, locked\n\
+             1, 0.0, 0.0, 0.0, false\n\
+             2, 3.0, 0.0, 3.0, false\n"
+        );
+    }
+
+    #[test]
+    fn test_disputes_report() {
+        let mut account = Account::new(1);
+        for tx in 1..=4 {
+            let deposit = Transaction::Deposit(MoneyTransaction::new(1, tx, dec!(10.00)).unwrap());
+            account.process_transaction(deposit).unwrap();
+        }
+
+        // tx 1 stays open, tx 2 is resolved, tx 3 is never disputed, tx 4 is charged back
+        for transaction in [
+            Transaction::Dispute(ClientTransaction::new(1, 1)),
+            Transaction::Dispute(ClientTransaction::new(1, 2)),
+            Transaction::Resolve(ClientTransaction::new(1, 2)),
+            Transaction::Dispute(ClientTransaction::new(1, 4)),
+            Transaction::Chargeback(ClientTransaction::new(1, 4)),
+        ] {
+            account.process_transaction(transaction).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let now = Utc::now() + Duration::seconds(90);
+        write_disputes_report([Ok(Cow::Borrowed(&account))], now, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "client, tx, type, amount, state, disputes, dispute_age_secs"
+        );
+        assert!(lines[1].starts_with("1, 1, deposit, 10.00, disputed, 1, "));
+        let age: i64 = lines[1].rsplit(", ").next().unwrap().parse().unwrap();
+        assert!((89..=91).contains(&age));
+        assert_eq!(lines[2], "1, 2, deposit, 10.00, resolved, 1, ");
+        assert_eq!(lines[3], "1, 4, deposit, 10.00, chargedback, 1, ");
+    }
+
+    #[test]
+    fn test_write_process_summary() {
+        let summary = ProcessSummary {
+            fingerprint: Some("ab12".to_string()),
+            processed: 3,
+            replayed: 1,
+            rejected: vec![Rejection {
+                client: ClientId::new(1),
+                tx: 2,
+                code: "insufficient_funds",
+                reason: "Insufficient funds".to_string(),
+            }],
+            ..ProcessSummary::default()
+        };
+
+        let mut output = Vec::new();
+        write_process_summary("partner.csv", &summary, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "file: partner.csv\n\
+             fingerprint: ab12\n\
+             status: done\n\
+             processed: 3\n\
+             replayed: 1\n\
+             rejected: 1\n\
+             read_error: \n\
+             rejection: 1, 2, insufficient_funds, Insufficient funds\n"
+        );
+    }
+}
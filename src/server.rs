@@ -0,0 +1,248 @@
+use crate::account::AccountBalance;
+use crate::csv::{CsvRecord, TransactionType};
+use crate::engine::{Engine, error_code};
+use crate::transaction::{ClientId, Transaction};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Body of `POST /transactions`, one `type, client, tx, amount` row as JSON.
+///
+/// `type` is kept as a string so an unknown type is reported as `invalid_input`
+/// rather than rejecting the whole request body.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransactionRequest {
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
+}
+
+impl TransactionRequest {
+    pub fn into_transaction(self) -> Result<Transaction, String> {
+        CsvRecord {
+            tx_type: self.tx_type.parse::<TransactionType>()?,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+        }
+        .into_transaction()
+    }
+}
+
+/// Typed reason for a rejected request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ErrorBody {
+    fn from_error(error: &(dyn Error + 'static)) -> Self {
+        Self {
+            code: error_code(error),
+            message: error.to_string(),
+        }
+    }
+
+    fn not_found(message: String) -> Self {
+        Self {
+            code: "not_found",
+            message,
+        }
+    }
+
+    fn internal(message: String) -> Self {
+        Self {
+            code: "internal",
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransactionResponse {
+    pub client: ClientId,
+    pub tx: u32,
+    pub accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountResponse {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl From<AccountBalance> for AccountResponse {
+    fn from(balance: AccountBalance) -> Self {
+        Self {
+            client: balance.client,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            locked: balance.locked,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransactionStateResponse {
+    pub client: ClientId,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub tx_type: &'static str,
+    pub amount: Decimal,
+    pub state: String,
+    pub disputes: u32,
+}
+
+/// Routes:
+/// - `POST /transactions` and `POST /transactions/batch` accept `TransactionRequest`s as JSON
+/// - `GET /accounts` and `GET /accounts/{client}` return balances
+/// - `GET /accounts/{client}/transactions/{tx}` returns a transaction's state
+pub fn router(engine: Engine) -> Router {
+    Router::new()
+        .route("/transactions", post(post_transaction))
+        .route("/transactions/batch", post(post_batch))
+        .route("/accounts", get(get_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .route(
+            "/accounts/{client}/transactions/{tx}",
+            get(get_transaction_state),
+        )
+        .with_state(engine)
+}
+
+/// Serve the HTTP API on `listener` until `cancel_token` is cancelled
+pub async fn serve(
+    engine: Engine,
+    listener: TcpListener,
+    cancel_token: CancellationToken,
+) -> io::Result<()> {
+    axum::serve(listener, router(engine))
+        .with_graceful_shutdown(cancel_token.cancelled_owned())
+        .await
+}
+
+async fn process_record(engine: &Engine, record: TransactionRequest) -> TransactionResponse {
+    let (client, tx) = (record.client, record.tx);
+
+    let error = match record.into_transaction() {
+        Ok(transaction) => engine
+            .process(transaction)
+            .await
+            .err()
+            .map(|e| ErrorBody::from_error(e.as_ref())),
+        Err(message) => Some(ErrorBody {
+            code: "invalid_input",
+            message,
+        }),
+    };
+
+    TransactionResponse {
+        client,
+        tx,
+        accepted: error.is_none(),
+        error,
+    }
+}
+
+async fn post_transaction(
+    State(engine): State<Engine>,
+    Json(record): Json<TransactionRequest>,
+) -> (StatusCode, Json<TransactionResponse>) {
+    let response = process_record(&engine, record).await;
+    let status = match &response.error {
+        None => StatusCode::OK,
+        Some(error) if error.code == "internal" => StatusCode::INTERNAL_SERVER_ERROR,
+        Some(_) => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    (status, Json(response))
+}
+
+async fn post_batch(
+    State(engine): State<Engine>,
+    Json(records): Json<Vec<TransactionRequest>>,
+) -> Json<Vec<TransactionResponse>> {
+    let mut responses = Vec::with_capacity(records.len());
+    for record in records {
+        responses.push(process_record(&engine, record).await);
+    }
+    Json(responses)
+}
+
+/// A failed read of stored accounts, as a 500 response
+fn read_error(e: io::Error) -> (StatusCode, Json<ErrorBody>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorBody::internal(format!(
+            "Accounts could not be read: {}",
+            e
+        ))),
+    )
+}
+
+async fn get_accounts(
+    State(engine): State<Engine>,
+) -> Result<Json<Vec<AccountResponse>>, (StatusCode, Json<ErrorBody>)> {
+    let balances = engine.balances().await.map_err(read_error)?;
+    Ok(Json(
+        balances.into_iter().map(AccountResponse::from).collect(),
+    ))
+}
+
+async fn get_account(
+    State(engine): State<Engine>,
+    Path(client): Path<ClientId>,
+) -> Result<Json<AccountResponse>, (StatusCode, Json<ErrorBody>)> {
+    match engine.balance(client).await.map_err(read_error)? {
+        Some(balance) => Ok(Json(AccountResponse::from(balance))),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorBody::not_found(format!("Client {} not found", client))),
+        )),
+    }
+}
+
+async fn get_transaction_state(
+    State(engine): State<Engine>,
+    Path((client, tx)): Path<(ClientId, u32)>,
+) -> Result<Json<TransactionStateResponse>, (StatusCode, Json<ErrorBody>)> {
+    let transaction = engine.transaction(client, tx).await.map_err(read_error)?;
+
+    let (tx_type, money_tx) = match &transaction {
+        Some(Transaction::Deposit(money_tx)) => ("deposit", money_tx),
+        Some(Transaction::Withdrawal(money_tx)) => ("withdrawal", money_tx),
+        _ => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ErrorBody::not_found(format!(
+                    "Transaction {} not found for client {}",
+                    tx, client
+                ))),
+            ));
+        }
+    };
+
+    Ok(Json(TransactionStateResponse {
+        client,
+        tx,
+        tx_type,
+        amount: money_tx.amount,
+        state: money_tx.state.to_string(),
+        disputes: money_tx.dispute_count,
+    }))
+}
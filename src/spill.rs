@@ -0,0 +1,987 @@
+use crate::account::LedgerEvent;
+use crate::sqlite::{event_row, parse_event, sql_error};
+use crate::store::LedgerStore;
+use crate::transaction::{
+    ClientId, ClientIdRepr, ClientTransaction, DisputeAction, DisputeEvent, MoneyTransaction,
+    Transaction, TransactionState,
+};
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use rust_decimal::Decimal;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Default number of transactions kept in memory across all ledgers
+pub const DEFAULT_MAX_IN_MEMORY: usize = 100_000;
+
+/// Rows read per query when iterating spilled transactions or history
+const PAGE_SIZE: usize = 1024;
+
+/// Where and when ledgers move transactions out of memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpillConfig {
+    /// Directory for the spill file, which is removed once no ledger refers to it
+    pub dir: PathBuf,
+    /// Transactions kept in memory across every ledger sharing a `SpillPool`
+    /// before the least recently used are moved to disk
+    pub max_in_memory: usize,
+}
+
+/// Distinguishes spill files created by one process
+static NEXT_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+const SPILL_SCHEMA: &str = "
+    CREATE TABLE transactions (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        record BLOB NOT NULL,
+        PRIMARY KEY (client, tx)
+    ) WITHOUT ROWID;
+    CREATE TABLE history (
+        client INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        tx INTEGER NOT NULL,
+        PRIMARY KEY (client, seq)
+    ) WITHOUT ROWID;
+";
+
+/// Removes the spill file when dropped
+#[derive(Debug)]
+struct SpillPath(PathBuf);
+
+impl Drop for SpillPath {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// A scratch SQLite database holding spilled transactions and the history of
+/// every ledger. Records are deleted when read back into memory, so their pages
+/// are reused by later spills instead of the file growing.
+#[derive(Debug)]
+struct SpillDb {
+    conn: Connection,
+    // Declared after `conn` so the connection is closed before the file is removed
+    _path: SpillPath,
+}
+
+impl SpillDb {
+    fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = SpillPath(dir.join(format!(
+            "ledger-{}-{}.spill",
+            std::process::id(),
+            NEXT_FILE_ID.fetch_add(1, Ordering::Relaxed)
+        )));
+        if path.0.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Spill file {} already exists", path.0.display()),
+            ));
+        }
+        let conn = Connection::open(&path.0).map_err(sql_error)?;
+        // Nothing here needs to survive a crash
+        conn.pragma_update(None, "journal_mode", "OFF")
+            .map_err(sql_error)?;
+        conn.pragma_update(None, "synchronous", "OFF")
+            .map_err(sql_error)?;
+        conn.execute_batch(SPILL_SCHEMA).map_err(sql_error)?;
+        Ok(Self { conn, _path: path })
+    }
+
+    fn put(&self, client: ClientId, tx_id: u32, transaction: &Transaction) -> io::Result<()> {
+        let record = encode(transaction)?;
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO transactions (client, tx, record) VALUES (?1, ?2, ?3)",
+            )
+            .and_then(|mut statement| statement.execute(params![client, tx_id, record]))
+            .map_err(sql_error)?;
+        Ok(())
+    }
+
+    fn get(&self, client: ClientId, tx_id: u32) -> io::Result<Option<Transaction>> {
+        let record = self
+            .conn
+            .prepare_cached("SELECT record FROM transactions WHERE client = ?1 AND tx = ?2")
+            .and_then(|mut statement| {
+                statement
+                    .query_row(params![client, tx_id], |row| row.get::<_, Vec<u8>>(0))
+                    .optional()
+            })
+            .map_err(sql_error)?;
+        record.map(|record| decode(&record)).transpose()
+    }
+
+    /// Read `tx_id` back and delete its record
+    fn take(&self, client: ClientId, tx_id: u32) -> io::Result<Option<Transaction>> {
+        let transaction = self.get(client, tx_id)?;
+        if transaction.is_some() {
+            self.conn
+                .prepare_cached("DELETE FROM transactions WHERE client = ?1 AND tx = ?2")
+                .and_then(|mut statement| statement.execute(params![client, tx_id]))
+                .map_err(sql_error)?;
+        }
+        Ok(transaction)
+    }
+
+    fn contains(&self, client: ClientId, tx_id: u32) -> io::Result<bool> {
+        self.conn
+            .prepare_cached("SELECT 1 FROM transactions WHERE client = ?1 AND tx = ?2")
+            .and_then(|mut statement| statement.exists(params![client, tx_id]))
+            .map_err(sql_error)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> io::Result<usize> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM transactions", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(|count| count as usize)
+            .map_err(sql_error)
+    }
+
+    /// Up to `PAGE_SIZE` of `client`'s spilled transactions with ids after `after`
+    fn page(&self, client: ClientId, after: Option<u32>) -> io::Result<Vec<(u32, Transaction)>> {
+        let after = after.map_or(-1, i64::from);
+        let rows = self
+            .conn
+            .prepare_cached(
+                "SELECT tx, record FROM transactions WHERE client = ?1 AND tx > ?2
+                 ORDER BY tx LIMIT ?3",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map(params![client, after, PAGE_SIZE as i64], |row| {
+                        Ok((row.get::<_, u32>(0)?, row.get::<_, Vec<u8>>(1)?))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(sql_error)?;
+        rows.into_iter()
+            .map(|(tx_id, record)| Ok((tx_id, decode(&record)?)))
+            .collect()
+    }
+
+    /// Append `events` to `client`'s history, numbering them from `start`, all or nothing
+    fn append_history(
+        &mut self,
+        client: ClientId,
+        start: usize,
+        events: &[LedgerEvent],
+    ) -> io::Result<()> {
+        let transaction = self.conn.transaction().map_err(sql_error)?;
+        {
+            let mut insert = transaction
+                .prepare_cached(
+                    "INSERT INTO history (client, seq, kind, tx) VALUES (?1, ?2, ?3, ?4)",
+                )
+                .map_err(sql_error)?;
+            for (offset, event) in events.iter().enumerate() {
+                let (kind, tx) = event_row(event);
+                insert
+                    .execute(params![client, (start + offset) as i64, kind, tx])
+                    .map_err(sql_error)?;
+            }
+        }
+        transaction.commit().map_err(sql_error)
+    }
+
+    /// Up to `PAGE_SIZE` of `client`'s history events from `seq` on
+    fn history_page(&self, client: ClientId, seq: usize) -> io::Result<Vec<LedgerEvent>> {
+        let rows = self
+            .conn
+            .prepare_cached(
+                "SELECT kind, tx FROM history WHERE client = ?1 AND seq >= ?2
+                 ORDER BY seq LIMIT ?3",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map(params![client, seq as i64, PAGE_SIZE as i64], |row| {
+                        Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(sql_error)?;
+        rows.iter()
+            .map(|(kind, tx)| parse_event(kind, *tx))
+            .collect()
+    }
+}
+
+/// A transaction held in memory by a `SpillPool`
+#[derive(Debug)]
+struct Cached {
+    transaction: Transaction,
+    /// Position in the pool's eviction order, or `None` while it is under
+    /// dispute and must stay in memory
+    seq: Option<u64>,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    config: SpillConfig,
+    /// Created on the first spill or history write
+    db: Option<SpillDb>,
+    cache: HashMap<ClientId, HashMap<u32, Cached>>,
+    /// Evictable cached transactions, least recently used first
+    lru: BTreeMap<u64, (ClientId, u32)>,
+    cached: usize,
+    next_seq: u64,
+}
+
+impl PoolState {
+    fn db(&mut self) -> io::Result<&mut SpillDb> {
+        if self.db.is_none() {
+            self.db = Some(SpillDb::create(&self.config.dir)?);
+        }
+        Ok(self.db.as_mut().expect("spill database was just created"))
+    }
+
+    fn cached(&self, client: ClientId, tx_id: u32) -> Option<&Transaction> {
+        self.cache
+            .get(&client)
+            .and_then(|transactions| transactions.get(&tx_id))
+            .map(|cached| &cached.transaction)
+    }
+
+    /// Copy of `tx_id` from memory or disk, leaving it where it is
+    fn read(&self, client: ClientId, tx_id: u32) -> io::Result<Option<Transaction>> {
+        if let Some(transaction) = self.cached(client, tx_id) {
+            return Ok(Some(transaction.clone()));
+        }
+        match &self.db {
+            Some(db) => db.get(client, tx_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Hold `transaction` in memory as the most recently used
+    fn check_in(&mut self, client: ClientId, tx_id: u32, transaction: Transaction) {
+        let seq = (!is_disputed(&transaction)).then(|| {
+            self.next_seq += 1;
+            self.lru.insert(self.next_seq, (client, tx_id));
+            self.next_seq
+        });
+        let previous = self
+            .cache
+            .entry(client)
+            .or_default()
+            .insert(tx_id, Cached { transaction, seq });
+        match previous {
+            Some(Cached { seq: Some(seq), .. }) => {
+                self.lru.remove(&seq);
+            }
+            Some(_) => {}
+            None => self.cached += 1,
+        }
+    }
+
+    /// Remove `tx_id` from memory or disk, wherever it is
+    fn check_out(&mut self, client: ClientId, tx_id: u32) -> io::Result<Option<Transaction>> {
+        if let Some(cached) = self
+            .cache
+            .get_mut(&client)
+            .and_then(|transactions| transactions.remove(&tx_id))
+        {
+            if let Some(seq) = cached.seq {
+                self.lru.remove(&seq);
+            }
+            self.cached -= 1;
+            return Ok(Some(cached.transaction));
+        }
+        match &self.db {
+            Some(db) => db.take(client, tx_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Move the least recently used transactions to disk until the budget holds
+    fn evict(&mut self) -> io::Result<()> {
+        while self.cached > self.config.max_in_memory {
+            let Some((&seq, &(client, tx_id))) = self.lru.first_key_value() else {
+                // Everything left is under dispute
+                return Ok(());
+            };
+            let transaction = &self.cache[&client][&tx_id].transaction;
+            // Cloning sidesteps borrowing the cache and the database at once
+            let transaction = transaction.clone();
+            self.db()?.put(client, tx_id, &transaction)?;
+            self.lru.remove(&seq);
+            if let Some(transactions) = self.cache.get_mut(&client) {
+                transactions.remove(&tx_id);
+            }
+            self.cached -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// Memory budget and spill file shared by the ledgers of one `AccountManager`.
+///
+/// Every ledger's transactions count against one `max_in_memory` budget; when it
+/// is exceeded the least recently used transactions of any ledger move to disk.
+/// Transactions under dispute are never moved, since a resolve or chargeback is
+/// expected to follow. Ledger histories are written to the same file.
+#[derive(Debug, Clone)]
+pub struct SpillPool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl SpillPool {
+    pub fn new(config: SpillConfig) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PoolState {
+                config,
+                db: None,
+                cache: HashMap::new(),
+                lru: BTreeMap::new(),
+                cached: 0,
+                next_seq: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Number of transactions held in memory across all ledgers, not counting
+    /// ones changed since their ledger's last flush
+    #[cfg(test)]
+    pub fn in_memory(&self) -> usize {
+        self.lock().cached
+    }
+
+    /// Number of transactions moved to disk across all ledgers
+    #[cfg(test)]
+    pub fn spilled(&self) -> io::Result<usize> {
+        match &self.lock().db {
+            Some(db) => db.len(),
+            None => Ok(0),
+        }
+    }
+}
+
+/// One ledger's view of a `SpillPool`.
+///
+/// Inserted transactions and transactions borrowed mutably stay with the ledger
+/// until `flush`, which hands them to the pool and writes new history events.
+///
+/// Clones read the same pool, so they see later changes made by the original,
+/// but never change it: a clone borrows a copy of a transaction mutably, and
+/// keeps what it inserts, changes and records in memory.
+#[derive(Debug)]
+pub struct SpillLedgerStore {
+    pool: SpillPool,
+    client: ClientId,
+    pending: HashMap<u32, Transaction>,
+    /// Number of history events already written for this client
+    history_len: usize,
+    /// Set on clones, whose changes stay in `pending`
+    copy_on_write: bool,
+}
+
+impl SpillLedgerStore {
+    pub fn new(pool: SpillPool, client: ClientId) -> Self {
+        Self {
+            pool,
+            client,
+            pending: HashMap::new(),
+            history_len: 0,
+            copy_on_write: false,
+        }
+    }
+}
+
+impl Clone for SpillLedgerStore {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            client: self.client,
+            pending: self.pending.clone(),
+            history_len: self.history_len,
+            copy_on_write: true,
+        }
+    }
+}
+
+impl LedgerStore for SpillLedgerStore {
+    fn get(&self, tx_id: u32) -> io::Result<Option<Cow<'_, Transaction>>> {
+        if let Some(transaction) = self.pending.get(&tx_id) {
+            return Ok(Some(Cow::Borrowed(transaction)));
+        }
+        Ok(self.pool.lock().read(self.client, tx_id)?.map(Cow::Owned))
+    }
+
+    fn get_mut(&mut self, tx_id: u32) -> io::Result<Option<&mut Transaction>> {
+        if !self.pending.contains_key(&tx_id) {
+            let mut state = self.pool.lock();
+            let transaction = if self.copy_on_write {
+                state.read(self.client, tx_id)?
+            } else {
+                state.check_out(self.client, tx_id)?
+            };
+            let Some(transaction) = transaction else {
+                return Ok(None);
+            };
+            self.pending.insert(tx_id, transaction);
+        }
+        Ok(self.pending.get_mut(&tx_id))
+    }
+
+    /// `tx_id` must not be stored already; `Account` checks `contains` first
+    fn insert(&mut self, tx_id: u32, transaction: Transaction) {
+        self.pending.insert(tx_id, transaction);
+    }
+
+    fn contains(&self, tx_id: u32) -> io::Result<bool> {
+        if self.pending.contains_key(&tx_id) {
+            return Ok(true);
+        }
+        let state = self.pool.lock();
+        if state.cached(self.client, tx_id).is_some() {
+            return Ok(true);
+        }
+        match &state.db {
+            Some(db) => db.contains(self.client, tx_id),
+            None => Ok(false),
+        }
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = io::Result<Cow<'_, Transaction>>> + '_> {
+        let cached: Vec<_> = self
+            .pool
+            .lock()
+            .cache
+            .get(&self.client)
+            .map(|transactions| {
+                transactions
+                    .iter()
+                    .filter(|(tx_id, _)| !self.pending.contains_key(tx_id))
+                    .map(|(_, cached)| cached.transaction.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let spilled = SpilledTransactions {
+            pool: &self.pool,
+            client: self.client,
+            after: None,
+            page: VecDeque::new(),
+            done: false,
+        };
+        // A clone's pending copies are also still in the pool
+        let spilled = spilled.filter(|transaction| {
+            !matches!(transaction, Ok(transaction)
+                if self.pending.contains_key(&transaction.transaction_id()))
+        });
+        Box::new(
+            self.pending
+                .values()
+                .map(|transaction| Ok(Cow::Borrowed(transaction)))
+                .chain(
+                    cached
+                        .into_iter()
+                        .map(|transaction| Ok(Cow::Owned(transaction))),
+                )
+                .chain(spilled.map(|transaction| transaction.map(Cow::Owned))),
+        )
+    }
+
+    /// Clones leave their history events with the ledger
+    fn keeps_history(&self) -> bool {
+        !self.copy_on_write
+    }
+
+    fn history(&self) -> Box<dyn Iterator<Item = io::Result<LedgerEvent>> + '_> {
+        Box::new(SpilledHistory {
+            pool: &self.pool,
+            client: self.client,
+            next: 0,
+            end: self.history_len,
+            page: VecDeque::new(),
+        })
+    }
+
+    /// Hand pending transactions to the pool, evict down to its budget, then
+    /// write `events`. If writing the events fails none of them are kept.
+    /// Does nothing for clones.
+    fn flush(&mut self, events: &[LedgerEvent]) -> io::Result<()> {
+        if self.copy_on_write {
+            return Ok(());
+        }
+        let mut state = self.pool.lock();
+        for (tx_id, transaction) in self.pending.drain() {
+            state.check_in(self.client, tx_id, transaction);
+        }
+        state.evict()?;
+        if !events.is_empty() {
+            state
+                .db()?
+                .append_history(self.client, self.history_len, events)?;
+            self.history_len += events.len();
+        }
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn LedgerStore> {
+        Box::new(self.clone())
+    }
+}
+
+/// Pages through one client's spilled transactions in id order
+struct SpilledTransactions<'a> {
+    pool: &'a SpillPool,
+    client: ClientId,
+    after: Option<u32>,
+    page: VecDeque<(u32, Transaction)>,
+    done: bool,
+}
+
+impl Iterator for SpilledTransactions<'_> {
+    type Item = io::Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            let page = match &self.pool.lock().db {
+                Some(db) => db.page(self.client, self.after),
+                None => Ok(Vec::new()),
+            };
+            match page {
+                Ok(page) => {
+                    self.done = page.len() < PAGE_SIZE;
+                    self.page = page.into();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let (tx_id, transaction) = self.page.pop_front()?;
+        self.after = Some(tx_id);
+        Some(Ok(transaction))
+    }
+}
+
+/// Pages through the history events one ledger has written
+struct SpilledHistory<'a> {
+    pool: &'a SpillPool,
+    client: ClientId,
+    next: usize,
+    end: usize,
+    page: VecDeque<LedgerEvent>,
+}
+
+impl Iterator for SpilledHistory<'_> {
+    type Item = io::Result<LedgerEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        if self.page.is_empty() {
+            let page = match &self.pool.lock().db {
+                Some(db) => db.history_page(self.client, self.next),
+                None => Ok(Vec::new()),
+            };
+            match page {
+                Ok(page) if page.is_empty() => {
+                    self.next = self.end;
+                    return Some(Err(invalid_data("Spilled history ends early")));
+                }
+                Ok(page) => self.page = page.into(),
+                Err(e) => {
+                    self.next = self.end;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.next += 1;
+        self.page.pop_front().map(Ok)
+    }
+}
+
+fn is_disputed(transaction: &Transaction) -> bool {
+    matches!(
+        transaction,
+        Transaction::Deposit(money_tx) | Transaction::Withdrawal(money_tx)
+            if money_tx.is_disputed()
+    )
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn state_code(state: TransactionState) -> u8 {
+    match state {
+        TransactionState::Normal => 0,
+        TransactionState::Disputed => 1,
+        TransactionState::Chargedback => 2,
+        TransactionState::Reversed => 3,
+        TransactionState::Resolved => 4,
+    }
+}
+
+fn action_code(action: DisputeAction) -> u8 {
+    match action {
+        DisputeAction::Dispute => 0,
+        DisputeAction::Resolve => 1,
+        DisputeAction::Chargeback => 2,
+    }
+}
+
+/// Fixed-width little-endian encoding of a deposit or withdrawal
+fn encode(transaction: &Transaction) -> io::Result<Vec<u8>> {
+    let (kind, money_tx) = match transaction {
+        Transaction::Deposit(money_tx) => (0u8, money_tx),
+        Transaction::Withdrawal(money_tx) => (1u8, money_tx),
+        _ => return Err(invalid_data("Only money transactions can be spilled")),
+    };
+
+    let mut record = Vec::with_capacity(48 + money_tx.dispute_history.len() * 9);
+    record.push(kind);
+    record.extend_from_slice(&money_tx.id.client.get().to_le_bytes());
+    record.extend_from_slice(&money_tx.id.tx.to_le_bytes());
+    record.extend_from_slice(&money_tx.amount.serialize());
+    record.extend_from_slice(&money_tx.timestamp.timestamp_micros().to_le_bytes());
+    record.push(state_code(money_tx.state));
+    record.extend_from_slice(&money_tx.dispute_count.to_le_bytes());
+    record.extend_from_slice(&(money_tx.dispute_history.len() as u32).to_le_bytes());
+    for event in &money_tx.dispute_history {
+        record.push(action_code(event.action));
+        record.extend_from_slice(&event.timestamp.timestamp_micros().to_le_bytes());
+    }
+    Ok(record)
+}
+
+/// Reads fixed-width fields off the front of a record
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        if self.0.len() < N {
+            return Err(invalid_data("Truncated spill record"));
+        }
+        let (field, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(field.try_into().unwrap())
+    }
+
+    fn timestamp(&mut self) -> io::Result<DateTime<Utc>> {
+        DateTime::from_timestamp_micros(i64::from_le_bytes(self.take()?))
+            .ok_or_else(|| invalid_data("Invalid timestamp in spill record"))
+    }
+}
+
+fn decode(record: &[u8]) -> io::Result<Transaction> {
+    let mut fields = Fields(record);
+    let [kind] = fields.take()?;
+    let client = ClientId::new(ClientIdRepr::from_le_bytes(fields.take()?));
+    let tx = u32::from_le_bytes(fields.take()?);
+    let amount = Decimal::deserialize(fields.take()?);
+    let timestamp = fields.timestamp()?;
+    let state = match fields.take::<1>()? {
+        [0] => TransactionState::Normal,
+        [1] => TransactionState::Disputed,
+        [2] => TransactionState::Chargedback,
+        [3] => TransactionState::Reversed,
+        [4] => TransactionState::Resolved,
+        _ => return Err(invalid_data("Invalid state in spill record")),
+    };
+    let dispute_count = u32::from_le_bytes(fields.take()?);
+
+    let history_len = u32::from_le_bytes(fields.take()?);
+    let mut dispute_history = Vec::new();
+    for _ in 0..history_len {
+        let action = match fields.take::<1>()? {
+            [0] => DisputeAction::Dispute,
+            [1] => DisputeAction::Resolve,
+            [2] => DisputeAction::Chargeback,
+            _ => return Err(invalid_data("Invalid dispute action in spill record")),
+        };
+        dispute_history.push(DisputeEvent {
+            action,
+            timestamp: fields.timestamp()?,
+        });
+    }
+
+    let money_tx = MoneyTransaction {
+        id: ClientTransaction::new(client, tx),
+        amount,
+        timestamp,
+        state,
+        dispute_count,
+        dispute_history,
+    };
+    match kind {
+        0 => Ok(Transaction::Deposit(money_tx)),
+        1 => Ok(Transaction::Withdrawal(money_tx)),
+        _ => Err(invalid_data("Invalid transaction kind in spill record")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::{Account, AccountManager, Ledger, ProcessOutcome};
+    use crate::{invariants, rebuild};
+    use rust_decimal_macros::dec;
+
+    fn spill_config(dir: &tempfile::TempDir) -> SpillConfig {
+        SpillConfig {
+            dir: dir.path().to_path_buf(),
+            max_in_memory: 2,
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut money_tx = MoneyTransaction::new(3, 42, dec!(12.3456)).unwrap();
+        money_tx.mark_disputed().unwrap();
+        money_tx.resolve_dispute().unwrap();
+        let transaction = Transaction::Withdrawal(money_tx);
+
+        let decoded = decode(&encode(&transaction).unwrap()).unwrap();
+        // Timestamps are stored to the microsecond
+        let (Transaction::Withdrawal(original), Transaction::Withdrawal(decoded)) =
+            (&transaction, &decoded)
+        else {
+            panic!("Expected withdrawals, got {:?}", decoded);
+        };
+        assert_eq!(decoded.id, original.id);
+        assert_eq!(decoded.amount, original.amount);
+        assert_eq!(decoded.state, TransactionState::Resolved);
+        assert_eq!(decoded.dispute_count, 1);
+        assert_eq!(decoded.dispute_history.len(), 2);
+        assert_eq!(
+            decoded.timestamp.timestamp_micros(),
+            original.timestamp.timestamp_micros()
+        );
+
+        assert!(decode(&encode(&transaction).unwrap()[..10]).is_err());
+        assert!(encode(&Transaction::dispute(3, 42)).is_err());
+    }
+
+    fn spilling_account(pool: &SpillPool, client: ClientId) -> Account {
+        let mut account = Account::new(client);
+        account.ledger = Ledger::with_store(SpillLedgerStore::new(pool.clone(), client));
+        account
+    }
+
+    fn deposit(account: &mut Account, tx: u32) {
+        let client = account.client.get();
+        account
+            .process_transaction(Transaction::deposit(client, tx, dec!(10.0)).unwrap())
+            .unwrap();
+        account.ledger.flush().unwrap();
+    }
+
+    #[test]
+    fn test_budget_is_shared_across_ledgers() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SpillPool::new(spill_config(&dir));
+        let mut first = spilling_account(&pool, ClientId::new(1));
+        let mut second = spilling_account(&pool, ClientId::new(2));
+        for tx in 1..=3 {
+            deposit(&mut first, tx);
+            deposit(&mut second, 100 + tx);
+        }
+        assert_eq!(pool.in_memory(), 2);
+        assert_eq!(pool.spilled().unwrap(), 4);
+        assert_eq!(first.ledger.transactions().count(), 3);
+        assert_eq!(second.ledger.transactions().count(), 3);
+        // Spilled ids are still known, so they count as duplicates
+        assert!(first.ledger.contains(1).unwrap());
+        assert!(!first.ledger.contains(101).unwrap());
+    }
+
+    #[test]
+    fn test_disputed_transactions_stay_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SpillPool::new(spill_config(&dir));
+        let mut account = spilling_account(&pool, ClientId::new(1));
+        deposit(&mut account, 1);
+        account
+            .process_transaction(Transaction::dispute(1, 1))
+            .unwrap();
+        account.ledger.flush().unwrap();
+        for tx in 2..=5 {
+            deposit(&mut account, tx);
+        }
+        assert!(pool.lock().cached(ClientId::new(1), 1).is_some());
+        assert_eq!(pool.in_memory(), 2);
+        assert_eq!(pool.spilled().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_records_read_back_are_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SpillPool::new(spill_config(&dir));
+        let mut account = spilling_account(&pool, ClientId::new(1));
+        for tx in 1..=4 {
+            deposit(&mut account, tx);
+        }
+        assert_eq!(pool.spilled().unwrap(), 2);
+
+        // Borrowing mutably takes the record off disk
+        assert!(account.ledger.get_transaction_mut(1).unwrap().is_some());
+        assert_eq!(pool.spilled().unwrap(), 1);
+        account.ledger.flush().unwrap();
+        assert_eq!(pool.spilled().unwrap(), 2);
+        assert_eq!(pool.in_memory(), 2);
+    }
+
+    #[test]
+    fn test_history_is_read_from_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SpillPool::new(spill_config(&dir));
+        let mut account = spilling_account(&pool, ClientId::new(1));
+        let count = PAGE_SIZE as u32 + 3;
+        for tx in 1..=count {
+            deposit(&mut account, tx);
+        }
+        account
+            .process_transaction(Transaction::dispute(1, 2))
+            .unwrap();
+
+        // The dispute is not flushed yet, so it is read from memory
+        let history = account.ledger.history().collect::<io::Result<Vec<_>>>();
+        let history = history.unwrap();
+        assert_eq!(history.len(), count as usize + 1);
+        assert_eq!(history[0], LedgerEvent::Deposit(1));
+        assert_eq!(history[count as usize], LedgerEvent::Dispute(2));
+
+        account.ledger.flush().unwrap();
+        assert_eq!(account.ledger.history().count(), count as usize + 1);
+    }
+
+    #[test]
+    fn test_corrupted_record_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SpillPool::new(spill_config(&dir));
+        let mut account = spilling_account(&pool, ClientId::new(1));
+        for tx in 1..=3 {
+            deposit(&mut account, tx);
+        }
+        pool.lock()
+            .db
+            .as_ref()
+            .unwrap()
+            .conn
+            .execute("UPDATE transactions SET record = x'00'", [])
+            .unwrap();
+
+        assert!(account.ledger.get_transaction(1).is_err());
+        assert!(
+            account
+                .ledger
+                .transactions()
+                .any(|transaction| transaction.is_err())
+        );
+        assert!(
+            account
+                .process_transaction(Transaction::dispute(1, 1))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_spill_file_is_removed_with_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SpillPool::new(spill_config(&dir));
+        let mut account = spilling_account(&pool, ClientId::new(1));
+        for tx in 1..=3 {
+            deposit(&mut account, tx);
+        }
+        let files = || fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files(), 1);
+        drop(pool);
+        assert_eq!(files(), 1);
+        drop(account);
+        assert_eq!(files(), 0);
+    }
+
+    #[test]
+    fn test_dispute_of_spilled_transaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = SpillPool::new(spill_config(&dir));
+        let mut account = spilling_account(&pool, ClientId::new(2));
+        let process = |account: &mut Account, transaction| {
+            let outcome = account.process_transaction(transaction).unwrap();
+            account.ledger.flush().unwrap();
+            outcome
+        };
+        for tx in 1..=4 {
+            process(
+                &mut account,
+                Transaction::deposit(2, tx, Decimal::from(tx)).unwrap(),
+            );
+        }
+        match account.ledger.get_transaction(1).unwrap() {
+            Some(Cow::Owned(Transaction::Deposit(money_tx))) => {
+                assert_eq!(money_tx.amount, dec!(1))
+            }
+            other => panic!("Expected deposit read from disk, got {:?}", other),
+        }
+
+        process(&mut account, Transaction::dispute(2, 1));
+        assert_eq!(account.held, dec!(1));
+        assert_eq!(
+            process(&mut account, Transaction::dispute(2, 1)),
+            ProcessOutcome::Replayed
+        );
+        process(&mut account, Transaction::chargeback(2, 1));
+        assert_eq!(account.total, dec!(9));
+        assert!(account.locked);
+        assert!(account.ledger.is_chargedback(1).unwrap());
+
+        let rebuilt =
+            rebuild::replay(ClientId::new(2), &account.ledger, &account.dispute_policy).unwrap();
+        assert!(rebuilt.compare(&account).is_empty());
+        assert!(invariants::check_account(&account).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_changing_a_cloned_account_leaves_the_pool_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = AccountManager::new().with_ledger_spill(spill_config(&dir));
+        for tx in 1..=4 {
+            manager
+                .process_transaction(Transaction::deposit(1, tx, dec!(10.0)).unwrap())
+                .await
+                .unwrap();
+        }
+
+        // Transaction 1 is on disk and 4 in memory
+        let mut copy = manager.get_account(1).await.unwrap().unwrap();
+        for tx in [1, 4] {
+            copy.process_transaction(Transaction::dispute(1, tx))
+                .unwrap();
+        }
+        copy.ledger.flush().unwrap();
+        assert_eq!(copy.held, dec!(20.0));
+        assert!(copy.ledger.is_disputed(1).unwrap());
+        assert_eq!(copy.ledger.transactions().count(), 4);
+        assert_eq!(copy.ledger.history().count(), 6);
+
+        let account = manager.get_account(1).await.unwrap().unwrap();
+        assert!(!account.ledger.is_disputed(1).unwrap());
+        assert_eq!(account.ledger.history().count(), 4);
+        for tx in [1, 4] {
+            manager
+                .process_transaction(Transaction::dispute(1, tx))
+                .await
+                .unwrap();
+        }
+        assert!(manager.verify().await.unwrap().is_consistent());
+    }
+}
@@ -0,0 +1,1454 @@
+use crate::account::{Account, Ledger, LedgerEvent};
+use crate::engine::Rejection;
+use crate::limits::LimitUsage;
+use crate::store::{AccountStore, ConfigureAccount, LedgerStore};
+use crate::transaction::{
+    ClientId, ClientTransaction, DisputeAction, DisputeEvent, MoneyTransaction, Transaction,
+    TransactionState,
+};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, ToSql, params};
+use rust_decimal::Decimal;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Amounts are stored as text so they keep their exact decimal value
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        credit_used TEXT NOT NULL,
+        withdrawal_day TEXT,
+        withdrawn_today TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS limit_window (
+        client INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        timestamp TEXT NOT NULL,
+        PRIMARY KEY (client, seq)
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        state TEXT NOT NULL,
+        dispute_count INTEGER NOT NULL,
+        PRIMARY KEY (client, tx)
+    );
+    CREATE TABLE IF NOT EXISTS dispute_events (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        action TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        PRIMARY KEY (client, tx, seq)
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        client INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        tx INTEGER NOT NULL,
+        PRIMARY KEY (client, seq)
+    );
+    CREATE TABLE IF NOT EXISTS ingested_files (
+        fingerprint TEXT PRIMARY KEY
+    );
+";
+
+/// Rows the engine rejected, in the order they were rejected. Only written by `export`.
+const REJECTIONS_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS rejections (
+        seq INTEGER PRIMARY KEY,
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        code TEXT NOT NULL,
+        reason TEXT NOT NULL
+    );
+";
+
+/// Rows read per query when iterating transactions, history or accounts
+const PAGE_SIZE: usize = 1024;
+
+const SELECT_ACCOUNT: &str = "SELECT client, available, held, total, locked, credit_used,
+    withdrawal_day, withdrawn_today FROM accounts";
+
+type SharedConnection = Arc<Mutex<Connection>>;
+
+/// Canonical paths of the databases opened by live `SqliteAccountStore`s, so
+/// `export` can refuse to replace one
+static OPEN_DATABASES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Keeps a path in `OPEN_DATABASES` until dropped
+#[derive(Debug)]
+struct OpenDatabase(PathBuf);
+
+impl OpenDatabase {
+    fn register(path: &Path) -> io::Result<Self> {
+        let path = path.canonicalize()?;
+        OPEN_DATABASES.lock().unwrap().push(path.clone());
+        Ok(Self(path))
+    }
+
+    fn is_open(path: &Path) -> io::Result<bool> {
+        if !path.exists() {
+            return Ok(false);
+        }
+        let path = path.canonicalize()?;
+        Ok(OPEN_DATABASES.lock().unwrap().contains(&path))
+    }
+}
+
+impl Drop for OpenDatabase {
+    fn drop(&mut self) {
+        let mut open = OPEN_DATABASES.lock().unwrap();
+        if let Some(index) = open.iter().position(|path| *path == self.0) {
+            open.swap_remove(index);
+        }
+    }
+}
+
+impl ToSql for ClientId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        i64::try_from(self.as_u64())
+            .map(ToSqlOutput::from)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+    }
+}
+
+impl FromSql for ClientId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let id = i64::column_result(value)?;
+        u64::try_from(id)
+            .ok()
+            .and_then(|id| ClientId::from_u64(id).ok())
+            .ok_or(FromSqlError::OutOfRange(id))
+    }
+}
+
+pub(crate) fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn parse_timestamp(value: &str) -> io::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|e| invalid_data(format!("Invalid timestamp {}: {}", value, e)))
+}
+
+fn parse_date(value: &str) -> io::Result<NaiveDate> {
+    value
+        .parse()
+        .map_err(|e| invalid_data(format!("Invalid date {}: {}", value, e)))
+}
+
+fn parse_decimal(value: &str) -> io::Result<Decimal> {
+    value
+        .parse()
+        .map_err(|e| invalid_data(format!("Invalid amount {}: {}", value, e)))
+}
+
+fn parse_state(value: &str) -> io::Result<TransactionState> {
+    match value {
+        "normal" => Ok(TransactionState::Normal),
+        "disputed" => Ok(TransactionState::Disputed),
+        "chargedback" => Ok(TransactionState::Chargedback),
+        "reversed" => Ok(TransactionState::Reversed),
+        "resolved" => Ok(TransactionState::Resolved),
+        _ => Err(invalid_data(format!("Invalid transaction state {}", value))),
+    }
+}
+
+fn action_name(action: DisputeAction) -> &'static str {
+    match action {
+        DisputeAction::Dispute => "dispute",
+        DisputeAction::Resolve => "resolve",
+        DisputeAction::Chargeback => "chargeback",
+    }
+}
+
+fn parse_action(value: &str) -> io::Result<DisputeAction> {
+    match value {
+        "dispute" => Ok(DisputeAction::Dispute),
+        "resolve" => Ok(DisputeAction::Resolve),
+        "chargeback" => Ok(DisputeAction::Chargeback),
+        _ => Err(invalid_data(format!("Invalid dispute action {}", value))),
+    }
+}
+
+pub(crate) fn event_row(event: &LedgerEvent) -> (&'static str, u32) {
+    match *event {
+        LedgerEvent::Deposit(tx) => ("deposit", tx),
+        LedgerEvent::Withdrawal(tx) => ("withdrawal", tx),
+        LedgerEvent::Dispute(tx) => ("dispute", tx),
+        LedgerEvent::Resolve(tx) => ("resolve", tx),
+        LedgerEvent::Chargeback(tx) => ("chargeback", tx),
+        LedgerEvent::Reversal(tx) => ("reversal", tx),
+    }
+}
+
+pub(crate) fn parse_event(kind: &str, tx: u32) -> io::Result<LedgerEvent> {
+    match kind {
+        "deposit" => Ok(LedgerEvent::Deposit(tx)),
+        "withdrawal" => Ok(LedgerEvent::Withdrawal(tx)),
+        "dispute" => Ok(LedgerEvent::Dispute(tx)),
+        "resolve" => Ok(LedgerEvent::Resolve(tx)),
+        "chargeback" => Ok(LedgerEvent::Chargeback(tx)),
+        "reversal" => Ok(LedgerEvent::Reversal(tx)),
+        _ => Err(invalid_data(format!("Invalid history event {}", kind))),
+    }
+}
+
+/// Bring a database written by an earlier schema up to date. `dispute_events`
+/// used to repeat each event's transaction id in a `row_tx` column.
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let has_row_tx = conn
+        .prepare("SELECT 1 FROM pragma_table_info('dispute_events') WHERE name = 'row_tx'")?
+        .exists([])?;
+    if has_row_tx {
+        conn.execute_batch("ALTER TABLE dispute_events DROP COLUMN row_tx")?;
+    }
+    Ok(())
+}
+
+/// Write one deposit or withdrawal and its dispute history, replacing any earlier version
+fn write_transaction(conn: &Connection, transaction: &Transaction) -> rusqlite::Result<()> {
+    let (tx_type, money_tx) = match transaction {
+        Transaction::Deposit(money_tx) => ("deposit", money_tx),
+        Transaction::Withdrawal(money_tx) => ("withdrawal", money_tx),
+        _ => return Ok(()),
+    };
+    let (client, tx) = (money_tx.id.client, money_tx.id.tx);
+
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO transactions
+         (client, tx, type, amount, timestamp, state, dispute_count)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?
+    .execute(params![
+        client,
+        tx,
+        tx_type,
+        money_tx.amount.to_string(),
+        format_timestamp(&money_tx.timestamp),
+        money_tx.state.to_string(),
+        money_tx.dispute_count,
+    ])?;
+
+    conn.prepare_cached("DELETE FROM dispute_events WHERE client = ?1 AND tx = ?2")?
+        .execute(params![client, tx])?;
+    let mut insert_event = conn.prepare_cached(
+        "INSERT INTO dispute_events (client, tx, seq, action, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for (seq, event) in money_tx.dispute_history.iter().enumerate() {
+        insert_event.execute(params![
+            client,
+            tx,
+            seq as i64,
+            action_name(event.action),
+            format_timestamp(&event.timestamp),
+        ])?;
+    }
+    Ok(())
+}
+
+/// Append `events` to `client`'s history, numbering them from `start`
+fn write_history(
+    conn: &Connection,
+    client: ClientId,
+    start: usize,
+    events: &[LedgerEvent],
+) -> rusqlite::Result<()> {
+    let mut insert_event =
+        conn.prepare_cached("INSERT INTO history (client, seq, kind, tx) VALUES (?1, ?2, ?3, ?4)")?;
+    for (offset, event) in events.iter().enumerate() {
+        let (kind, tx) = event_row(event);
+        insert_event.execute(params![client, (start + offset) as i64, kind, tx])?;
+    }
+    Ok(())
+}
+
+/// Write the account's balances and limit usage, replacing any earlier version
+fn write_account(conn: &Connection, account: &Account) -> rusqlite::Result<()> {
+    let usage = &account.limit_usage;
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO accounts
+         (client, available, held, total, locked, credit_used, withdrawal_day, withdrawn_today)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?
+    .execute(params![
+        account.client,
+        account.available.to_string(),
+        account.held.to_string(),
+        account.total.to_string(),
+        account.locked,
+        account.credit_used().to_string(),
+        usage.withdrawal_day.map(|day| day.to_string()),
+        usage.withdrawn_today.to_string(),
+    ])?;
+
+    conn.prepare_cached("DELETE FROM limit_window WHERE client = ?1")?
+        .execute(params![account.client])?;
+    let mut insert_time = conn
+        .prepare_cached("INSERT INTO limit_window (client, seq, timestamp) VALUES (?1, ?2, ?3)")?;
+    for (seq, timestamp) in usage.recent.iter().enumerate() {
+        insert_time.execute(params![
+            account.client,
+            seq as i64,
+            format_timestamp(timestamp)
+        ])?;
+    }
+    Ok(())
+}
+
+fn write_ingested(conn: &Connection, fingerprints: &[String]) -> rusqlite::Result<()> {
+    let mut insert =
+        conn.prepare_cached("INSERT OR IGNORE INTO ingested_files (fingerprint) VALUES (?1)")?;
+    for fingerprint in fingerprints {
+        insert.execute(params![fingerprint])?;
+    }
+    Ok(())
+}
+
+/// Columns of an `accounts` row, in `SELECT_ACCOUNT` order
+type AccountRow = (
+    ClientId,
+    String,
+    String,
+    String,
+    bool,
+    String,
+    Option<String>,
+    String,
+);
+
+fn read_account_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<AccountRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+    ))
+}
+
+/// Columns of a `transactions` row, in schema order after `client`
+type TransactionRow = (u32, String, String, String, String, u32);
+
+fn read_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<TransactionRow> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+    ))
+}
+
+fn build_transaction(
+    client: ClientId,
+    row: TransactionRow,
+    dispute_history: Vec<DisputeEvent>,
+) -> io::Result<Transaction> {
+    let (tx, tx_type, amount, timestamp, state, dispute_count) = row;
+    let money_tx = MoneyTransaction {
+        id: ClientTransaction::new(client, tx),
+        amount: parse_decimal(&amount)?,
+        timestamp: parse_timestamp(&timestamp)?,
+        state: parse_state(&state)?,
+        dispute_count,
+        dispute_history,
+    };
+    match tx_type.as_str() {
+        "deposit" => Ok(Transaction::Deposit(money_tx)),
+        "withdrawal" => Ok(Transaction::Withdrawal(money_tx)),
+        _ => Err(invalid_data(format!(
+            "Invalid transaction type {}",
+            tx_type
+        ))),
+    }
+}
+
+/// Iterates rows fetched `PAGE_SIZE` at a time. `fetch` is given the key of
+/// the last row returned and yields the rows after it in key order.
+struct Paged<T, F> {
+    fetch: F,
+    key: fn(&T) -> i64,
+    after: Option<i64>,
+    page: VecDeque<T>,
+    done: bool,
+}
+
+impl<T, F: FnMut(Option<i64>) -> io::Result<Vec<T>>> Paged<T, F> {
+    fn new(fetch: F, key: fn(&T) -> i64) -> Self {
+        Self {
+            fetch,
+            key,
+            after: None,
+            page: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl<T, F: FnMut(Option<i64>) -> io::Result<Vec<T>>> Iterator for Paged<T, F> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            match (self.fetch)(self.after) {
+                Ok(page) => {
+                    self.done = page.len() < PAGE_SIZE;
+                    self.page = page.into();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let row = self.page.pop_front()?;
+        self.after = Some((self.key)(&row));
+        Some(Ok(row))
+    }
+}
+
+/// Ledger transactions and history kept in a SQLite database.
+///
+/// Inserted and changed transactions are held in memory until `flush`; stored
+/// ones and the history are read a page at a time.
+#[derive(Debug, Clone)]
+pub struct SqliteLedgerStore {
+    conn: SharedConnection,
+    client: ClientId,
+    pending: HashMap<u32, Transaction>,
+    /// Number of history events already written for this client
+    history_len: usize,
+}
+
+impl SqliteLedgerStore {
+    fn new(conn: SharedConnection, client: ClientId, history_len: usize) -> Self {
+        Self {
+            conn,
+            client,
+            pending: HashMap::new(),
+            history_len,
+        }
+    }
+
+    fn load(&self, tx_id: u32) -> io::Result<Option<Transaction>> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .prepare_cached(
+                "SELECT tx, type, amount, timestamp, state, dispute_count
+                 FROM transactions WHERE client = ?1 AND tx = ?2",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_row(params![self.client, tx_id], read_row)
+                    .optional()
+            })
+            .map_err(sql_error)?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let mut statement = conn
+            .prepare_cached(
+                "SELECT action, timestamp FROM dispute_events
+                 WHERE client = ?1 AND tx = ?2 ORDER BY seq",
+            )
+            .map_err(sql_error)?;
+        let events = statement
+            .query_map(params![self.client, tx_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .and_then(Iterator::collect::<rusqlite::Result<Vec<_>>>)
+            .map_err(sql_error)?;
+        let dispute_history = events
+            .iter()
+            .map(|(action, timestamp)| {
+                Ok(DisputeEvent {
+                    action: parse_action(action)?,
+                    timestamp: parse_timestamp(timestamp)?,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        build_transaction(self.client, row, dispute_history).map(Some)
+    }
+
+    /// Up to `PAGE_SIZE` stored transactions of this client with ids after
+    /// `after`, ignoring pending changes
+    fn load_page(&self, after: Option<i64>) -> io::Result<Vec<Transaction>> {
+        let conn = self.conn.lock().unwrap();
+        let rows = conn
+            .prepare_cached(
+                "SELECT tx, type, amount, timestamp, state, dispute_count
+                 FROM transactions WHERE client = ?1 AND tx > ?2 ORDER BY tx LIMIT ?3",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map(
+                        params![self.client, after.unwrap_or(-1), PAGE_SIZE as i64],
+                        read_row,
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(sql_error)?;
+        let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+            return Ok(Vec::new());
+        };
+
+        let mut histories: HashMap<u32, Vec<DisputeEvent>> = HashMap::new();
+        let events = conn
+            .prepare_cached(
+                "SELECT tx, action, timestamp FROM dispute_events
+                 WHERE client = ?1 AND tx BETWEEN ?2 AND ?3 ORDER BY tx, seq",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map(params![self.client, first.0, last.0], |row| {
+                        Ok((
+                            row.get::<_, u32>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(sql_error)?;
+        for (tx, action, timestamp) in events {
+            histories.entry(tx).or_default().push(DisputeEvent {
+                action: parse_action(&action)?,
+                timestamp: parse_timestamp(&timestamp)?,
+            });
+        }
+
+        rows.into_iter()
+            .map(|row| {
+                let dispute_history = histories.remove(&row.0).unwrap_or_default();
+                build_transaction(self.client, row, dispute_history)
+            })
+            .collect()
+    }
+
+    /// Up to `PAGE_SIZE` written history events of this client after `after`,
+    /// with their sequence numbers
+    fn load_history(&self, after: Option<i64>) -> io::Result<Vec<(i64, LedgerEvent)>> {
+        let rows = self
+            .conn
+            .lock()
+            .unwrap()
+            .prepare_cached(
+                "SELECT seq, kind, tx FROM history WHERE client = ?1 AND seq > ?2
+                 ORDER BY seq LIMIT ?3",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map(
+                        params![self.client, after.unwrap_or(-1), PAGE_SIZE as i64],
+                        |row| {
+                            Ok((
+                                row.get::<_, i64>(0)?,
+                                row.get::<_, String>(1)?,
+                                row.get::<_, u32>(2)?,
+                            ))
+                        },
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(sql_error)?;
+        rows.into_iter()
+            .map(|(seq, kind, tx)| Ok((seq, parse_event(&kind, tx)?)))
+            .collect()
+    }
+}
+
+impl LedgerStore for SqliteLedgerStore {
+    fn get(&self, tx_id: u32) -> io::Result<Option<Cow<'_, Transaction>>> {
+        if let Some(transaction) = self.pending.get(&tx_id) {
+            return Ok(Some(Cow::Borrowed(transaction)));
+        }
+        Ok(self.load(tx_id)?.map(Cow::Owned))
+    }
+
+    fn get_mut(&mut self, tx_id: u32) -> io::Result<Option<&mut Transaction>> {
+        if !self.pending.contains_key(&tx_id) {
+            let Some(transaction) = self.load(tx_id)? else {
+                return Ok(None);
+            };
+            self.pending.insert(tx_id, transaction);
+        }
+        Ok(self.pending.get_mut(&tx_id))
+    }
+
+    fn insert(&mut self, tx_id: u32, transaction: Transaction) {
+        self.pending.insert(tx_id, transaction);
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = io::Result<Cow<'_, Transaction>>> + '_> {
+        let stored = Paged::new(
+            |after| self.load_page(after),
+            |transaction: &Transaction| i64::from(transaction.transaction_id()),
+        )
+        .filter(|transaction| {
+            !matches!(transaction, Ok(transaction)
+                if self.pending.contains_key(&transaction.transaction_id()))
+        });
+        Box::new(
+            self.pending
+                .values()
+                .map(|transaction| Ok(Cow::Borrowed(transaction)))
+                .chain(stored.map(|transaction| transaction.map(Cow::Owned))),
+        )
+    }
+
+    fn keeps_history(&self) -> bool {
+        true
+    }
+
+    fn history(&self) -> Box<dyn Iterator<Item = io::Result<LedgerEvent>> + '_> {
+        let events = Paged::new(|after| self.load_history(after), |(seq, _)| *seq);
+        Box::new(events.map(|event| event.map(|(_, event)| event)))
+    }
+
+    fn flush(&mut self, events: &[LedgerEvent]) -> io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        for transaction in self.pending.values() {
+            write_transaction(&conn, transaction).map_err(sql_error)?;
+        }
+
+        write_history(&conn, self.client, self.history_len, events).map_err(sql_error)?;
+
+        self.history_len += events.len();
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn LedgerStore> {
+        Box::new(self.clone())
+    }
+}
+
+/// Accounts kept in a SQLite database, so state survives across runs and can be
+/// queried with SQL.
+///
+/// Accounts are read from the database when first needed and kept in memory
+/// only until the next `commit`; ledger transactions and history are read as
+/// they are needed. Saves go into one database transaction per batch, which
+/// `commit` makes durable.
+pub struct SqliteAccountStore {
+    conn: SharedConnection,
+    /// Accounts read or changed since the last `commit`
+    accounts: HashMap<ClientId, Account>,
+    /// Fingerprints of files to record as ingested on the next `commit`
+    ingested: Vec<String>,
+    configure: Option<ConfigureAccount>,
+    /// `None` for in-memory databases
+    _file: Option<OpenDatabase>,
+}
+
+impl fmt::Debug for SqliteAccountStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteAccountStore")
+            .field("conn", &self.conn)
+            .field("accounts", &self.accounts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SqliteAccountStore {
+    /// Open or create the database at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).map_err(sql_error)?;
+        Self::from_connection(conn, Some(OpenDatabase::register(path)?))
+    }
+
+    /// Open the existing database at `path` without writing to it: no WAL switch and
+    /// no schema. Saving to the store fails.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(sql_error)?;
+        Ok(Self::with_connection(
+            conn,
+            Some(OpenDatabase::register(path)?),
+        ))
+    }
+
+    pub fn open_in_memory() -> io::Result<Self> {
+        Self::from_connection(Connection::open_in_memory().map_err(sql_error)?, None)
+    }
+
+    fn from_connection(conn: Connection, file: Option<OpenDatabase>) -> io::Result<Self> {
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))
+            .map_err(sql_error)?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .map_err(sql_error)?;
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        migrate(&conn).map_err(sql_error)?;
+        Ok(Self::with_connection(conn, file))
+    }
+
+    fn with_connection(conn: Connection, file: Option<OpenDatabase>) -> Self {
+        Self {
+            conn: Arc::new(Mutex::new(conn)),
+            accounts: HashMap::new(),
+            ingested: Vec::new(),
+            configure: None,
+            _file: file,
+        }
+    }
+
+    /// Read `client`'s account, or `None` if it was never saved
+    fn load(&self, client: ClientId) -> io::Result<Option<Account>> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .prepare_cached(&format!("{} WHERE client = ?1", SELECT_ACCOUNT))
+            .and_then(|mut statement| {
+                statement
+                    .query_row(params![client], read_account_row)
+                    .optional()
+            })
+            .map_err(sql_error)?;
+        row.map(|row| self.build_account(&conn, row)).transpose()
+    }
+
+    /// Up to `PAGE_SIZE` stored accounts with client ids after `after`
+    fn load_page(&self, after: Option<i64>) -> io::Result<Vec<Account>> {
+        let conn = self.conn.lock().unwrap();
+        let rows = conn
+            .prepare_cached(&format!(
+                "{} WHERE client > ?1 ORDER BY client LIMIT ?2",
+                SELECT_ACCOUNT
+            ))
+            .and_then(|mut statement| {
+                statement
+                    .query_map(
+                        params![after.unwrap_or(-1), PAGE_SIZE as i64],
+                        read_account_row,
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(sql_error)?;
+        rows.into_iter()
+            .map(|row| self.build_account(&conn, row))
+            .collect()
+    }
+
+    fn build_account(&self, conn: &Connection, row: AccountRow) -> io::Result<Account> {
+        let (client, available, held, total, locked, credit_used, withdrawal_day, withdrawn_today) =
+            row;
+        let recent = conn
+            .prepare_cached("SELECT timestamp FROM limit_window WHERE client = ?1 ORDER BY seq")
+            .and_then(|mut statement| {
+                statement
+                    .query_map(params![client], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(sql_error)?;
+        let history_len = conn
+            .prepare_cached("SELECT COALESCE(MAX(seq) + 1, 0) FROM history WHERE client = ?1")
+            .and_then(|mut statement| {
+                statement.query_row(params![client], |row| row.get::<_, i64>(0))
+            })
+            .map_err(sql_error)?;
+
+        let mut account = Account::new(client);
+        account.available = parse_decimal(&available)?;
+        account.held = parse_decimal(&held)?;
+        account.total = parse_decimal(&total)?;
+        account.locked = locked;
+        account.credit_drawn = parse_decimal(&credit_used)?;
+        account.limit_usage = LimitUsage {
+            withdrawal_day: withdrawal_day.as_deref().map(parse_date).transpose()?,
+            withdrawn_today: parse_decimal(&withdrawn_today)?,
+            recent: recent
+                .iter()
+                .map(|timestamp| parse_timestamp(timestamp))
+                .collect::<io::Result<_>>()?,
+        };
+        let store = SqliteLedgerStore::new(self.conn.clone(), client, history_len as usize);
+        account.ledger = Ledger::restore(Box::new(store), Vec::new());
+        if let Some(configure) = &self.configure {
+            configure(&mut account);
+        }
+        Ok(account)
+    }
+}
+
+impl AccountStore for SqliteAccountStore {
+    fn get(&self, client: ClientId) -> io::Result<Option<Cow<'_, Account>>> {
+        if let Some(account) = self.accounts.get(&client) {
+            return Ok(Some(Cow::Borrowed(account)));
+        }
+        Ok(self.load(client)?.map(Cow::Owned))
+    }
+
+    fn get_mut(&mut self, client: ClientId) -> io::Result<Option<&mut Account>> {
+        if !self.accounts.contains_key(&client) {
+            let Some(account) = self.load(client)? else {
+                return Ok(None);
+            };
+            self.accounts.insert(client, account);
+        }
+        Ok(self.accounts.get_mut(&client))
+    }
+
+    fn insert(&mut self, account: Account) {
+        self.accounts.insert(account.client, account);
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = io::Result<Cow<'_, Account>>> + '_> {
+        let stored = Paged::new(
+            |after| self.load_page(after),
+            |account: &Account| account.client.as_u64() as i64,
+        )
+        .filter(|account| {
+            !matches!(account, Ok(account) if self.accounts.contains_key(&account.client))
+        });
+        Box::new(
+            self.accounts
+                .values()
+                .map(|account| Ok(Cow::Borrowed(account)))
+                .chain(stored.map(|account| account.map(Cow::Owned))),
+        )
+    }
+
+    fn configure(&mut self, configure: ConfigureAccount) {
+        self.accounts
+            .values_mut()
+            .for_each(|account| configure(account));
+        self.configure = Some(configure);
+    }
+
+    fn len(&self) -> io::Result<usize> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM accounts", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(|count| count as usize)
+            .map_err(sql_error)
+    }
+
+    fn new_ledger(&self, client: ClientId) -> Option<Box<dyn LedgerStore>> {
+        Some(Box::new(SqliteLedgerStore::new(
+            self.conn.clone(),
+            client,
+            0,
+        )))
+    }
+
+    /// Write the account and its ledger changes, all or nothing, into the
+    /// database transaction of the current batch. If that fails the account is
+    /// dropped from memory, so the next read sees what the database holds.
+    fn save(&mut self, client: ClientId) -> io::Result<()> {
+        let Some(account) = self.accounts.get_mut(&client) else {
+            return Ok(());
+        };
+        let execute = |sql: &str| self.conn.lock().unwrap().execute_batch(sql);
+
+        if self.conn.lock().unwrap().is_autocommit() {
+            execute("BEGIN").map_err(sql_error)?;
+        }
+        execute("SAVEPOINT save").map_err(sql_error)?;
+        let result = account
+            .ledger
+            .flush()
+            .and_then(|_| write_account(&self.conn.lock().unwrap(), account).map_err(sql_error));
+        match result {
+            Ok(()) => execute("RELEASE save").map_err(sql_error),
+            Err(e) => {
+                execute("ROLLBACK TO save; RELEASE save").ok();
+                self.accounts.remove(&client);
+                Err(e)
+            }
+        }
+    }
+
+    fn is_ingested(&self, fingerprint: &str) -> io::Result<bool> {
+        self.conn
+            .lock()
+            .unwrap()
+            .prepare_cached("SELECT 1 FROM ingested_files WHERE fingerprint = ?1")
+            .and_then(|mut statement| statement.exists(params![fingerprint]))
+            .map_err(sql_error)
+    }
+
+    fn record_ingested(&mut self, fingerprint: &str) {
+        self.ingested.push(fingerprint.to_string());
+    }
+
+    /// Commit the batch's database transaction, along with the files recorded
+    /// as ingested, and drop the accounts read during it. If the commit fails
+    /// the batch is rolled back, so the next read sees the state from before it.
+    fn commit(&mut self) -> io::Result<()> {
+        self.accounts.clear();
+        let ingested = mem::take(&mut self.ingested);
+        let conn = self.conn.lock().unwrap();
+        if conn.is_autocommit() {
+            if ingested.is_empty() {
+                return Ok(());
+            }
+            conn.execute_batch("BEGIN").map_err(sql_error)?;
+        }
+        let result = write_ingested(&conn, &ingested)
+            .and_then(|_| conn.execute_batch("COMMIT"))
+            .map_err(sql_error);
+        if result.is_err() {
+            conn.execute_batch("ROLLBACK").ok();
+        }
+        result
+    }
+}
+
+/// `path` with `suffix` appended to its file name, e.g. the `-wal` file of a database
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Write `accounts`, their ledgers and `rejections` to a new SQLite database at
+/// `path`, replacing any database already there.
+///
+/// The database is written to a temporary file next to `path` and renamed over
+/// it once complete, so a failed export leaves the old file in place. The old
+/// database's journal and WAL files are removed first. A database opened by a
+/// live `SqliteAccountStore` is never replaced.
+///
+/// The database uses the same tables as `SqliteAccountStore`, plus `rejections`,
+/// so it can also seed a later run's `--state-db`.
+pub fn export<'a, P: AsRef<Path>>(
+    path: P,
+    accounts: impl IntoIterator<Item = io::Result<Cow<'a, Account>>>,
+    rejections: &[Rejection],
+) -> io::Result<()> {
+    let path = path.as_ref();
+    if OpenDatabase::is_open(path)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is the open state database", path.display()),
+        ));
+    }
+
+    let temp = with_suffix(path, &format!(".{}.tmp", std::process::id()));
+    remove_if_exists(&temp)?;
+    let result = write_export(&temp, accounts, rejections).and_then(|_| {
+        for suffix in ["-journal", "-wal", "-shm"] {
+            remove_if_exists(&with_suffix(path, suffix))?;
+        }
+        fs::rename(&temp, path)
+    });
+    if result.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    result
+}
+
+fn write_export<'a>(
+    path: &Path,
+    accounts: impl IntoIterator<Item = io::Result<Cow<'a, Account>>>,
+    rejections: &[Rejection],
+) -> io::Result<()> {
+    let mut conn = Connection::open(path).map_err(sql_error)?;
+    conn.execute_batch(SCHEMA).map_err(sql_error)?;
+    conn.execute_batch(REJECTIONS_SCHEMA).map_err(sql_error)?;
+
+    let transaction = conn.transaction().map_err(sql_error)?;
+    for account in accounts {
+        let account = account?;
+        write_account(&transaction, &account).map_err(sql_error)?;
+        for ledger_tx in account.ledger.transactions() {
+            write_transaction(&transaction, ledger_tx?.as_ref()).map_err(sql_error)?;
+        }
+        let history = account.ledger.history().collect::<io::Result<Vec<_>>>()?;
+        write_history(&transaction, account.client, 0, &history).map_err(sql_error)?;
+    }
+
+    {
+        let mut insert_rejection = transaction
+            .prepare("INSERT INTO rejections (seq, client, tx, code, reason) VALUES (?1, ?2, ?3, ?4, ?5)")
+            .map_err(sql_error)?;
+        for (seq, rejection) in rejections.iter().enumerate() {
+            insert_rejection
+                .execute(params![
+                    seq as i64,
+                    rejection.client,
+                    rejection.tx,
+                    rejection.code,
+                    rejection.reason,
+                ])
+                .map_err(sql_error)?;
+        }
+    }
+    transaction.commit().map_err(sql_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountManager;
+    use crate::limits::{LimitError, Limits, LimitsConfig};
+    use rust_decimal_macros::dec;
+    use std::iter;
+
+    async fn process_all(manager: &AccountManager, transactions: Vec<Transaction>) {
+        for transaction in transactions {
+            manager.process_transaction(transaction).await.ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_persists_across_opens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()));
+        process_all(
+            &manager,
+            vec![
+                Transaction::deposit(1, 1, dec!(10.5)).unwrap(),
+                Transaction::deposit(1, 2, dec!(4.25)).unwrap(),
+                Transaction::withdrawal(1, 3, dec!(2.0)).unwrap(),
+                Transaction::dispute(1, 1),
+                Transaction::deposit(2, 4, dec!(3.0)).unwrap(),
+                Transaction::dispute(2, 4),
+                Transaction::chargeback(2, 4),
+            ],
+        )
+        .await;
+        let before = manager.accounts().await.unwrap();
+        drop(manager);
+
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()));
+        let account = manager.get_account(1).await.unwrap().unwrap();
+        assert_eq!(account.available, before[&ClientId::new(1)].available);
+        assert_eq!(account.held, dec!(10.5));
+        assert_eq!(account.total, dec!(12.75));
+        assert!(account.ledger.is_disputed(1).unwrap());
+        assert_eq!(account.ledger.history().count(), 4);
+        assert_eq!(account.ledger.transactions().count(), 3);
+        assert!(manager.get_account(2).await.unwrap().unwrap().locked);
+
+        // Processing continues from the stored state
+        process_all(
+            &manager,
+            vec![
+                Transaction::resolve(1, 1),
+                Transaction::deposit(1, 2, dec!(1.0)).unwrap(),
+            ],
+        )
+        .await;
+        let account = manager.get_account(1).await.unwrap().unwrap();
+        assert_eq!(account.available, dec!(12.75));
+        assert_eq!(account.held, dec!(0));
+        assert!(manager.verify().await.unwrap().is_consistent());
+        assert!(manager.check_invariants().await.unwrap().is_empty());
+        drop(manager);
+
+        let conn = Connection::open(&path).unwrap();
+        let (state, disputes): (String, u32) = conn
+            .query_row(
+                "SELECT state, dispute_count FROM transactions WHERE client = 1 AND tx = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((state.as_str(), disputes), ("resolved", 1));
+    }
+
+    #[cfg(feature = "wide-client-ids")]
+    #[tokio::test]
+    async fn test_largest_wide_client_id_is_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()));
+        manager
+            .process_transaction(Transaction::deposit(ClientId::MAX, 1, dec!(2.0)).unwrap())
+            .await
+            .unwrap();
+        drop(manager);
+
+        let store = SqliteAccountStore::open(&path).unwrap();
+        assert_eq!(store.get(ClientId::MAX).unwrap().unwrap().total, dec!(2.0));
+    }
+
+    #[test]
+    fn test_ledger_store_round_trip() {
+        let store = SqliteAccountStore::open_in_memory().unwrap();
+        let mut ledger = Ledger::restore(store.new_ledger(ClientId::new(5)).unwrap(), Vec::new());
+
+        let mut deposit = MoneyTransaction::new(5, 1, dec!(1.2345)).unwrap();
+        deposit.mark_disputed().unwrap();
+        ledger.add_transaction(1, Transaction::Deposit(deposit.clone()));
+        ledger.record(LedgerEvent::Deposit(1));
+        ledger.record(LedgerEvent::Dispute(1));
+        ledger.flush().unwrap();
+
+        let reopened = Ledger::restore(store.new_ledger(ClientId::new(5)).unwrap(), Vec::new());
+        match reopened.get_transaction(1).unwrap() {
+            Some(Cow::Owned(Transaction::Deposit(loaded))) => {
+                assert_eq!(loaded.amount, deposit.amount);
+                assert_eq!(loaded.state, TransactionState::Disputed);
+                assert_eq!(loaded.dispute_history.len(), 1);
+                assert_eq!(loaded.dispute_history[0].action, DisputeAction::Dispute);
+                assert_eq!(
+                    loaded.timestamp.timestamp_micros(),
+                    deposit.timestamp.timestamp_micros()
+                );
+            }
+            other => panic!("Expected stored deposit, got {:?}", other),
+        }
+        assert!(reopened.contains(1).unwrap());
+        assert!(!reopened.contains(2).unwrap());
+        assert_eq!(reopened.history().count(), 2);
+
+        // Transactions and history are read a page at a time
+        let count = PAGE_SIZE as u32 + 2;
+        for tx in 2..=count {
+            ledger.add_transaction(tx, Transaction::deposit(5, tx, dec!(1.0)).unwrap());
+            ledger.record(LedgerEvent::Deposit(tx));
+        }
+        ledger.flush().unwrap();
+        assert_eq!(reopened.transactions().count(), count as usize);
+        let history = reopened.history().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(history.len(), count as usize + 1);
+        assert_eq!(history.last(), Some(&LedgerEvent::Deposit(count)));
+    }
+
+    #[tokio::test]
+    async fn test_limit_usage_persists_across_opens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let limits = LimitsConfig {
+            default: Limits {
+                daily_withdrawal_cap: Some(dec!(10.0)),
+                max_transactions: Some(3),
+                ..Limits::default()
+            },
+            overrides: HashMap::new(),
+        };
+        let open = || {
+            AccountManager::with_limits(limits.clone())
+                .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()))
+        };
+
+        let manager = open();
+        process_all(
+            &manager,
+            vec![
+                Transaction::deposit(1, 1, dec!(20.0)).unwrap(),
+                Transaction::withdrawal(1, 2, dec!(8.0)).unwrap(),
+            ],
+        )
+        .await;
+        drop(manager);
+
+        let manager = open();
+        let error = manager
+            .process_transaction(Transaction::withdrawal(1, 3, dec!(5.0)).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<LimitError>(),
+            Some(LimitError::DailyWithdrawalCapExceeded { .. })
+        ));
+        manager
+            .process_transaction(Transaction::withdrawal(1, 4, dec!(2.0)).unwrap())
+            .await
+            .unwrap();
+        drop(manager);
+
+        let manager = open();
+        let error = manager
+            .process_transaction(Transaction::deposit(1, 5, dec!(1.0)).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<LimitError>(),
+            Some(LimitError::TooManyTransactions { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_accounts_are_loaded_per_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()));
+        let results = manager
+            .process_batch(vec![
+                Transaction::deposit(1, 1, dec!(1.0)).unwrap(),
+                Transaction::deposit(2, 2, dec!(2.0)).unwrap(),
+                Transaction::withdrawal(2, 3, dec!(5.0)).unwrap(),
+            ])
+            .await;
+        assert!(results[0].is_ok() && results[1].is_ok() && results[2].is_err());
+
+        // The batch is committed, so another connection sees it
+        let conn = Connection::open(&path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM accounts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        drop(conn);
+        drop(manager);
+
+        // Opening reads nothing until an account is asked for
+        let mut store = SqliteAccountStore::open(&path).unwrap();
+        assert!(store.accounts.is_empty());
+        assert_eq!(store.len().unwrap(), 2);
+        assert_eq!(store.accounts().count(), 2);
+        assert!(store.accounts.is_empty());
+
+        let account = store.get_mut(ClientId::new(2)).unwrap().unwrap();
+        account.deposit(dec!(1.0));
+        store.save(ClientId::new(2)).unwrap();
+        assert_eq!(store.accounts.len(), 1);
+        store.commit().unwrap();
+        assert!(store.accounts.is_empty());
+        let account = store.get(ClientId::new(2)).unwrap().unwrap();
+        assert_eq!(account.available, dec!(3.0));
+        assert!(store.get(ClientId::new(3)).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let engine = crate::engine::Engine::builder().build();
+        for transaction in [
+            Transaction::deposit(1, 1, dec!(10.0)).unwrap(),
+            Transaction::withdrawal(1, 2, dec!(2.5)).unwrap(),
+            Transaction::dispute(1, 1),
+            Transaction::deposit(2, 3, dec!(1.0)).unwrap(),
+        ] {
+            engine.process(transaction).await.unwrap();
+        }
+        let rejections = vec![Rejection {
+            client: ClientId::new(2),
+            tx: 4,
+            code: "insufficient_funds",
+            reason: "Insufficient funds".to_string(),
+        }];
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.db");
+        std::fs::write(&path, b"not a database").unwrap();
+        std::fs::write(with_suffix(&path, "-wal"), b"stale").unwrap();
+        let accounts = engine.accounts().await.unwrap();
+        export(
+            &path,
+            accounts.values().map(|account| Ok(Cow::Borrowed(account))),
+            &rejections,
+        )
+        .unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let query = |sql: &str| -> Vec<String> {
+            let mut statement = conn.prepare(sql).unwrap();
+            statement
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        assert_eq!(
+            query("SELECT client || ',' || available || ',' || held FROM accounts ORDER BY client"),
+            vec!["1,-2.5,10.0", "2,1.0,0"]
+        );
+        assert_eq!(
+            query(
+                "SELECT tx || ',' || type || ',' || amount || ',' || state FROM transactions ORDER BY tx"
+            ),
+            vec![
+                "1,deposit,10.0,disputed",
+                "2,withdrawal,2.5,normal",
+                "3,deposit,1.0,normal"
+            ]
+        );
+        assert_eq!(
+            query("SELECT client || ',' || tx || ',' || code FROM rejections"),
+            vec!["2,4,insufficient_funds"]
+        );
+        drop(conn);
+        // Only the new database is left
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // The export can seed a persistent store
+        let store = SqliteAccountStore::open(&path).unwrap();
+        assert_eq!(store.len().unwrap(), 2);
+        let account = store.get(ClientId::new(1)).unwrap().unwrap();
+        assert!(account.ledger.is_disputed(1).unwrap());
+        assert_eq!(account.ledger.history().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_export_refuses_open_state_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()));
+        process_all(
+            &manager,
+            vec![Transaction::deposit(1, 1, dec!(1.0)).unwrap()],
+        )
+        .await;
+
+        let alias = dir.path().join(".").join("state.db");
+        let error = manager
+            .with_accounts(|accounts| export(&alias, accounts, &[]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(manager.total_accounts().await.unwrap(), 1);
+
+        // Once the store is closed the file can be replaced
+        drop(manager);
+        export(&path, iter::empty(), &[]).unwrap();
+        assert_eq!(SqliteAccountStore::open(&path).unwrap().len().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_open_drops_the_old_row_tx_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE dispute_events (
+                    client INTEGER NOT NULL,
+                    tx INTEGER NOT NULL,
+                    seq INTEGER NOT NULL,
+                    action TEXT NOT NULL,
+                    row_tx INTEGER NOT NULL,
+                    timestamp TEXT NOT NULL,
+                    PRIMARY KEY (client, tx, seq)
+                );",
+            )
+            .unwrap();
+
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()));
+        process_all(
+            &manager,
+            vec![
+                Transaction::deposit(1, 1, dec!(1.0)).unwrap(),
+                Transaction::dispute(1, 1),
+            ],
+        )
+        .await;
+        assert_eq!(
+            manager.get_account(1).await.unwrap().unwrap().held,
+            dec!(1.0)
+        );
+        drop(manager);
+
+        let conn = Connection::open(&path).unwrap();
+        let columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info('dispute_events')")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(columns, ["client", "tx", "seq", "action", "timestamp"]);
+    }
+
+    #[tokio::test]
+    async fn test_open_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open(&path).unwrap()));
+        process_all(
+            &manager,
+            vec![Transaction::deposit(1, 1, dec!(1.0)).unwrap()],
+        )
+        .await;
+        drop(manager);
+
+        let manager = AccountManager::new()
+            .with_account_store(Box::new(SqliteAccountStore::open_read_only(&path).unwrap()));
+        assert_eq!(
+            manager.get_account(1).await.unwrap().unwrap().available,
+            dec!(1.0)
+        );
+        assert!(manager.verify().await.unwrap().is_consistent());
+
+        // Saving fails, so the transactions fail and nothing they did is kept
+        for transaction in [
+            Transaction::deposit(1, 2, dec!(1.0)).unwrap(),
+            Transaction::deposit(2, 3, dec!(1.0)).unwrap(),
+        ] {
+            let error = manager.process_transaction(transaction).await.unwrap_err();
+            assert_eq!(crate::engine::error_code(error.as_ref()), "internal");
+        }
+        let account = manager.get_account(1).await.unwrap().unwrap();
+        assert_eq!(account.total, dec!(1.0));
+        assert!(!account.ledger.contains(2).unwrap());
+        assert!(manager.get_balance(2).await.unwrap().is_none());
+        drop(manager);
+        let store = SqliteAccountStore::open_read_only(&path).unwrap();
+        assert_eq!(
+            store.get(ClientId::new(1)).unwrap().unwrap().total,
+            dec!(1.0)
+        );
+        drop(store);
+
+        // Neither the schema nor WAL mode is applied to a database that lacks them
+        let empty = dir.path().join("empty.db");
+        fs::File::create(&empty).unwrap();
+        drop(SqliteAccountStore::open_read_only(&empty).unwrap());
+        assert!(!with_suffix(&empty, "-wal").exists());
+        let conn = Connection::open(&empty).unwrap();
+        let tables: u32 = conn
+            .query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tables, 0);
+        let mode: String = conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "delete");
+    }
+}
@@ -0,0 +1,176 @@
+use crate::account::{Account, LedgerEvent};
+use crate::transaction::{ClientId, Transaction};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::iter;
+use std::sync::Arc;
+
+/// Storage for the transactions of one ledger.
+///
+/// `HashMap<u32, Transaction>` is the in-memory implementation used by default.
+pub trait LedgerStore: fmt::Debug + Send + Sync {
+    fn get(&self, tx_id: u32) -> io::Result<Option<Cow<'_, Transaction>>>;
+
+    /// Mutable access to a transaction. Stores that keep transactions outside
+    /// memory load it first and write it back on the next `flush`.
+    fn get_mut(&mut self, tx_id: u32) -> io::Result<Option<&mut Transaction>>;
+
+    /// Add a new transaction. Stores that keep transactions outside memory
+    /// write it on the next `flush`.
+    fn insert(&mut self, tx_id: u32, transaction: Transaction);
+
+    fn contains(&self, tx_id: u32) -> io::Result<bool> {
+        Ok(self.get(tx_id)?.is_some())
+    }
+
+    /// Every transaction, in no particular order
+    fn transactions(&self) -> Box<dyn Iterator<Item = io::Result<Cow<'_, Transaction>>> + '_>;
+
+    /// Whether the store keeps the history events passed to `flush`, so the
+    /// ledger need not hold them in memory
+    fn keeps_history(&self) -> bool {
+        false
+    }
+
+    /// History events written by earlier flushes, oldest first. Empty unless
+    /// `keeps_history`.
+    fn history(&self) -> Box<dyn Iterator<Item = io::Result<LedgerEvent>> + '_> {
+        Box::new(iter::empty())
+    }
+
+    /// Persist changes made since the last flush, along with the history events
+    /// recorded in that time
+    fn flush(&mut self, _events: &[LedgerEvent]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn clone_box(&self) -> Box<dyn LedgerStore>;
+}
+
+impl Clone for Box<dyn LedgerStore> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl LedgerStore for HashMap<u32, Transaction> {
+    fn get(&self, tx_id: u32) -> io::Result<Option<Cow<'_, Transaction>>> {
+        Ok(HashMap::get(self, &tx_id).map(Cow::Borrowed))
+    }
+
+    fn get_mut(&mut self, tx_id: u32) -> io::Result<Option<&mut Transaction>> {
+        Ok(HashMap::get_mut(self, &tx_id))
+    }
+
+    fn insert(&mut self, tx_id: u32, transaction: Transaction) {
+        HashMap::insert(self, tx_id, transaction);
+    }
+
+    fn contains(&self, tx_id: u32) -> io::Result<bool> {
+        Ok(self.contains_key(&tx_id))
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = io::Result<Cow<'_, Transaction>>> + '_> {
+        Box::new(
+            self.values()
+                .map(|transaction| Ok(Cow::Borrowed(transaction))),
+        )
+    }
+
+    fn clone_box(&self) -> Box<dyn LedgerStore> {
+        Box::new(self.clone())
+    }
+}
+
+/// Settings an `AccountManager` applies to every account it takes from a store
+pub type ConfigureAccount = Arc<dyn Fn(&mut Account) + Send + Sync>;
+
+/// Storage for the accounts of an `AccountManager`.
+///
+/// `HashMap<ClientId, Account>` is the in-memory implementation used by default.
+pub trait AccountStore: fmt::Debug + Send + Sync {
+    fn get(&self, client: ClientId) -> io::Result<Option<Cow<'_, Account>>>;
+
+    /// Mutable access to an account. Stores that keep accounts outside memory
+    /// load it first and write it back on the next `save`.
+    fn get_mut(&mut self, client: ClientId) -> io::Result<Option<&mut Account>>;
+
+    fn insert(&mut self, account: Account);
+
+    /// Every account, in no particular order
+    fn accounts(&self) -> Box<dyn Iterator<Item = io::Result<Cow<'_, Account>>> + '_>;
+
+    /// Apply `configure` to every account held now and every account loaded later
+    fn configure(&mut self, configure: ConfigureAccount);
+
+    /// Number of accounts, counting inserted ones once they are saved
+    fn len(&self) -> io::Result<usize>;
+
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Ledger storage for a new account, or `None` for the manager's default
+    fn new_ledger(&self, _client: ClientId) -> Option<Box<dyn LedgerStore>> {
+        None
+    }
+
+    /// Persist `client`'s account after a transaction has been processed
+    fn save(&mut self, _client: ClientId) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether a file with this fingerprint was recorded by `record_ingested`
+    fn is_ingested(&self, _fingerprint: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Record a file as ingested in the next `commit`, together with the
+    /// accounts it changed. Stores that keep accounts in memory need not keep
+    /// it, since the `Engine` remembers the files it read for as long as it
+    /// holds their accounts.
+    fn record_ingested(&mut self, _fingerprint: &str) {}
+
+    /// Make everything saved since the last commit durable. Called once per
+    /// batch of transactions.
+    fn commit(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AccountStore for HashMap<ClientId, Account> {
+    fn get(&self, client: ClientId) -> io::Result<Option<Cow<'_, Account>>> {
+        Ok(HashMap::get(self, &client).map(Cow::Borrowed))
+    }
+
+    fn get_mut(&mut self, client: ClientId) -> io::Result<Option<&mut Account>> {
+        Ok(HashMap::get_mut(self, &client))
+    }
+
+    fn insert(&mut self, account: Account) {
+        HashMap::insert(self, account.client, account);
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = io::Result<Cow<'_, Account>>> + '_> {
+        Box::new(self.values().map(|account| Ok(Cow::Borrowed(account))))
+    }
+
+    /// Every account is already in memory, so `configure` is applied right away
+    fn configure(&mut self, configure: ConfigureAccount) {
+        self.values_mut().for_each(|account| configure(account));
+    }
+
+    fn len(&self) -> io::Result<usize> {
+        Ok(HashMap::len(self))
+    }
+
+    /// Flush the ledger, which matters for ledgers that spill to disk
+    fn save(&mut self, client: ClientId) -> io::Result<()> {
+        match HashMap::get_mut(self, &client) {
+            Some(account) => account.ledger.flush(),
+            None => Ok(()),
+        }
+    }
+}
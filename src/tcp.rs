@@ -0,0 +1,167 @@
+use crate::csv;
+use crate::engine::{Engine, error_code};
+use crate::transaction::Transaction;
+use log::{error, info};
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+use tokio_util::sync::CancellationToken;
+
+/// Longest row accepted, in bytes; a connection sending a longer one is closed
+const MAX_LINE_LENGTH: usize = 1024;
+
+/// Pause after a failed `accept`, which is usually transient (e.g. out of file descriptors)
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A parsed row waiting for the processor, with where to send its response line
+struct PendingRow {
+    transaction: Transaction,
+    reply: oneshot::Sender<String>,
+}
+
+fn rejection_line(code: &str, message: &str) -> String {
+    format!("err {} {}", code, message)
+}
+
+/// Whether `line` is a CSV header, in any case
+fn is_header(line: &str) -> bool {
+    line.get(..5)
+        .is_some_and(|start| start.eq_ignore_ascii_case("type,"))
+}
+
+/// Process rows from every connection, in arrival order, on one task
+async fn run_processor(engine: Engine, mut rx: mpsc::Receiver<PendingRow>) {
+    while let Some(row) = rx.recv().await {
+        let response = match engine.process(row.transaction).await {
+            Ok(_) => "ok".to_string(),
+            Err(e) => rejection_line(error_code(e.as_ref()), &e.to_string()),
+        };
+        // The connection may already be gone; its row was still applied
+        row.reply.send(response).ok();
+    }
+}
+
+/// Read rows from one connection and write one response line per row, in order.
+///
+/// Blank lines and a `type, client, tx, amount` header line are ignored. A line longer
+/// than `MAX_LINE_LENGTH` is rejected and ends the connection.
+async fn handle_connection(
+    stream: TcpStream,
+    processor: mpsc::Sender<PendingRow>,
+    channel_capacity: usize,
+) -> io::Result<()> {
+    let (read_half, write_half) = stream.into_split();
+    let (responses_tx, mut responses_rx) =
+        mpsc::channel::<oneshot::Receiver<String>>(channel_capacity);
+
+    // Responses are written by a separate task so rows can be pipelined
+    let writer = tokio::spawn(async move {
+        let mut writer = BufWriter::new(write_half);
+        while let Some(response) = responses_rx.recv().await {
+            let line = response
+                .await
+                .unwrap_or_else(|_| rejection_line("unavailable", "Processor is shutting down"));
+            writer.write_all(line.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            if responses_rx.is_empty() {
+                writer.flush().await?;
+            }
+        }
+        writer.flush().await
+    });
+
+    let mut lines = FramedRead::new(read_half, LinesCodec::new_with_max_length(MAX_LINE_LENGTH));
+    while let Some(line) = lines.next().await {
+        let (reply, response) = oneshot::channel();
+        let line = match line {
+            Ok(line) => line,
+            Err(LinesCodecError::MaxLineLengthExceeded) => {
+                let message = format!("Line is longer than {} bytes", MAX_LINE_LENGTH);
+                reply.send(rejection_line("invalid_input", &message)).ok();
+                responses_tx.send(response).await.ok();
+                break;
+            }
+            Err(LinesCodecError::Io(e)) => return Err(e),
+        };
+        let line = line.trim();
+        if line.is_empty() || is_header(line) {
+            continue;
+        }
+
+        match csv::parse_row(line) {
+            Ok(transaction) => {
+                if processor
+                    .send(PendingRow { transaction, reply })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Err(e) => {
+                reply
+                    .send(rejection_line("invalid_input", &e.to_string()))
+                    .ok();
+            }
+        }
+        if responses_tx.send(response).await.is_err() {
+            break;
+        }
+    }
+
+    drop(responses_tx);
+    writer.await.map_err(io::Error::other)?
+}
+
+/// Accept line-protocol connections on `listener` until `cancel_token` is cancelled.
+///
+/// All connections share one processor fed through a bounded mpsc channel, so a
+/// slow engine pushes back on every client. A failed `accept` is logged and retried
+/// after `ACCEPT_BACKOFF` rather than stopping the listener.
+pub async fn serve(
+    engine: Engine,
+    listener: TcpListener,
+    channel_capacity: usize,
+    cancel_token: CancellationToken,
+) -> io::Result<()> {
+    let channel_capacity = channel_capacity.max(1);
+    let (tx, rx) = mpsc::channel::<PendingRow>(channel_capacity);
+    let processor = tokio::spawn(run_processor(engine, rx));
+
+    loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Failed to accept a line-protocol connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                info!("Accepted line-protocol connection from {}", peer);
+
+                let processor_tx = tx.clone();
+                let connection_token = cancel_token.clone();
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = connection_token.cancelled() => {}
+                        result = handle_connection(stream, processor_tx, channel_capacity) => {
+                            if let Err(e) = result {
+                                error!("Line-protocol connection {} failed: {}", peer, e);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    drop(tx);
+    processor.await.map_err(io::Error::other)
+}
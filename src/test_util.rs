@@ -0,0 +1,12 @@
+//! Helpers shared by unit tests
+
+use std::io::Write;
+use tempfile::NamedTempFile;
+
+/// A file with a unique name holding `contents`, removed when dropped
+pub fn temp_file(contents: impl AsRef<[u8]>) -> NamedTempFile {
+    let mut file = NamedTempFile::new().expect("Failed to create temporary file");
+    file.write_all(contents.as_ref())
+        .expect("Failed to write temporary file");
+    file
+}
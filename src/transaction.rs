@@ -1,16 +1,153 @@
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt;
+use std::num::IntErrorKind;
+use std::str::FromStr;
+
+/// Integer behind `ClientId`: `u32`, or `u64` with the `wide-client-ids` feature
+#[cfg(not(feature = "wide-client-ids"))]
+pub type ClientIdRepr = u32;
+#[cfg(feature = "wide-client-ids")]
+pub type ClientIdRepr = u64;
+
+/// Identifies a client and their account.
+///
+/// Integer literals convert with `into()`, so `Transaction::dispute(1, 7)` works.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize)]
+#[serde(transparent)]
+pub struct ClientId(ClientIdRepr);
+
+impl ClientId {
+    /// The largest ID accepted from text, the APIs or a database
+    #[cfg(not(feature = "wide-client-ids"))]
+    pub const MAX: ClientId = ClientId(ClientIdRepr::MAX);
+    /// The largest ID accepted from text, the APIs or a database; SQLite stores signed
+    /// 64-bit integers, so wide IDs stop at `i64::MAX`
+    #[cfg(feature = "wide-client-ids")]
+    pub const MAX: ClientId = ClientId(i64::MAX as ClientIdRepr);
+
+    pub const fn new(id: ClientIdRepr) -> Self {
+        Self(id)
+    }
+
+    pub const fn get(self) -> ClientIdRepr {
+        self.0
+    }
+
+    /// The ID widened to `u64`, whatever `ClientIdRepr` is
+    #[allow(clippy::useless_conversion)]
+    pub fn as_u64(self) -> u64 {
+        u64::from(self.0)
+    }
+
+    /// Narrow an ID from a wider source, such as the gRPC API or a database
+    #[allow(clippy::absurd_extreme_comparisons)]
+    pub fn from_u64(id: u64) -> Result<Self, ClientIdError> {
+        ClientIdRepr::try_from(id)
+            .ok()
+            .filter(|id| *id <= Self::MAX.0)
+            .map(Self)
+            .ok_or_else(|| ClientIdError::OutOfRange(id.to_string()))
+    }
+}
+
+impl From<ClientIdRepr> for ClientId {
+    fn from(id: ClientIdRepr) -> Self {
+        Self(id)
+    }
+}
+
+impl PartialEq<ClientIdRepr> for ClientId {
+    fn eq(&self, other: &ClientIdRepr) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = ClientIdError;
+
+    #[allow(clippy::absurd_extreme_comparisons)]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.parse::<ClientIdRepr>() {
+            Ok(id) if id <= Self::MAX.0 => Ok(Self(id)),
+            Ok(_) => Err(ClientIdError::OutOfRange(s.to_string())),
+            Err(e) if *e.kind() == IntErrorKind::PosOverflow => {
+                Err(ClientIdError::OutOfRange(s.to_string()))
+            }
+            Err(_)
+                if s.strip_prefix('-')
+                    .is_some_and(|rest| rest.parse::<u64>().is_ok()) =>
+            {
+                Err(ClientIdError::OutOfRange(s.to_string()))
+            }
+            Err(_) => Err(ClientIdError::Invalid(s.to_string())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ClientId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = u64::deserialize(deserializer)?;
+        Self::from_u64(id).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientIdError {
+    OutOfRange(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ClientIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIdError::OutOfRange(id) => write!(
+                f,
+                "Client ID {} is out of range (0 to {})",
+                id,
+                ClientId::MAX
+            ),
+            ClientIdError::Invalid(id) => write!(f, "Invalid client ID: {:?}", id),
+        }
+    }
+}
+
+impl std::error::Error for ClientIdError {}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransactionError {
     AlreadyDisputed,
     NotDisputed,
     AlreadyChargedback,
-    #[allow(dead_code)]
+    AlreadyReversed,
+    CannotReverseDisputed,
+    DisputeLimitReached(u32),
     InvalidAmount(String),
 }
 
+impl TransactionError {
+    /// Stable machine-readable name for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::AlreadyDisputed => "already_disputed",
+            TransactionError::NotDisputed => "not_disputed",
+            TransactionError::AlreadyChargedback => "already_chargedback",
+            TransactionError::AlreadyReversed => "already_reversed",
+            TransactionError::CannotReverseDisputed => "cannot_reverse_disputed",
+            TransactionError::DisputeLimitReached(_) => "dispute_limit_reached",
+            TransactionError::InvalidAmount(_) => "invalid_amount",
+        }
+    }
+}
+
 impl fmt::Display for TransactionError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -19,6 +156,13 @@ impl fmt::Display for TransactionError {
             TransactionError::AlreadyChargedback => {
                 write!(f, "Cannot dispute a chargedback transaction")
             }
+            TransactionError::AlreadyReversed => write!(f, "Transaction has already been reversed"),
+            TransactionError::CannotReverseDisputed => {
+                write!(f, "Cannot reverse a disputed or chargedback transaction")
+            }
+            TransactionError::DisputeLimitReached(max) => {
+                write!(f, "Transaction has reached the maximum of {} disputes", max)
+            }
             TransactionError::InvalidAmount(msg) => write!(f, "{}", msg),
         }
     }
@@ -28,13 +172,16 @@ impl std::error::Error for TransactionError {}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ClientTransaction {
-    pub client: u16,
+    pub client: ClientId,
     pub tx: u32,
 }
 
 impl ClientTransaction {
-    pub fn new(client: u16, tx: u32) -> Self {
-        Self { client, tx }
+    pub fn new(client: impl Into<ClientId>, tx: u32) -> Self {
+        Self {
+            client: client.into(),
+            tx,
+        }
     }
 }
 
@@ -43,6 +190,85 @@ pub enum TransactionState {
     Normal,
     Disputed,
     Chargedback,
+    Reversed,
+    Resolved,
+}
+
+impl fmt::Display for TransactionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionState::Normal => write!(f, "normal"),
+            TransactionState::Disputed => write!(f, "disputed"),
+            TransactionState::Chargedback => write!(f, "chargedback"),
+            TransactionState::Reversed => write!(f, "reversed"),
+            TransactionState::Resolved => write!(f, "resolved"),
+        }
+    }
+}
+
+/// How often a transaction may be disputed, and what a chargeback does to the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputePolicy {
+    /// Maximum number of disputes per transaction, `None` for unlimited
+    pub max_disputes: Option<u32>,
+    /// Lock the account on a chargeback. A locked account only accepts further chargebacks.
+    pub lock_on_chargeback: bool,
+}
+
+impl Default for DisputePolicy {
+    fn default() -> Self {
+        Self {
+            max_disputes: None,
+            lock_on_chargeback: true,
+        }
+    }
+}
+
+/// Rules deposit and withdrawal amounts must follow on top of being positive,
+/// which `MoneyTransaction::new` always enforces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AmountPolicy {
+    /// Smallest amount accepted
+    pub min_amount: Option<Decimal>,
+    /// Most digits an amount may have after the decimal point
+    pub max_decimal_places: Option<u32>,
+}
+
+impl AmountPolicy {
+    pub fn check(&self, amount: Decimal) -> Result<(), TransactionError> {
+        if let Some(min) = self.min_amount
+            && amount < min
+        {
+            return Err(TransactionError::InvalidAmount(format!(
+                "Transaction amount {} is below the minimum of {}",
+                amount, min
+            )));
+        }
+        if let Some(places) = self.max_decimal_places
+            && amount.normalize().scale() > places
+        {
+            return Err(TransactionError::InvalidAmount(format!(
+                "Transaction amount {} has more than {} decimal places",
+                amount, places
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeAction {
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+/// One step in a transaction's dispute history. The dispute, resolve or chargeback
+/// row named the transaction it acts on, so its `tx` is the owning transaction's id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeEvent {
+    pub action: DisputeAction,
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -51,10 +277,12 @@ pub struct MoneyTransaction {
     pub amount: Decimal,
     pub timestamp: DateTime<Utc>,
     pub state: TransactionState,
+    pub dispute_count: u32,
+    pub dispute_history: Vec<DisputeEvent>,
 }
 
 impl MoneyTransaction {
-    pub fn new(client: u16, tx: u32, amount: Decimal) -> Result<Self, String> {
+    pub fn new(client: impl Into<ClientId>, tx: u32, amount: Decimal) -> Result<Self, String> {
         if amount <= Decimal::ZERO {
             return Err(format!(
                 "Transaction amount must be positive, got: {}",
@@ -67,6 +295,8 @@ impl MoneyTransaction {
             amount,
             timestamp: Utc::now(),
             state: TransactionState::Normal,
+            dispute_count: 0,
+            dispute_history: Vec::new(),
         })
     }
 
@@ -78,14 +308,48 @@ impl MoneyTransaction {
         self.state == TransactionState::Chargedback
     }
 
+    pub fn is_reversed(&self) -> bool {
+        self.state == TransactionState::Reversed
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.state == TransactionState::Resolved
+    }
+
+    /// When the currently open dispute was raised, if any
+    pub fn dispute_opened_at(&self) -> Option<DateTime<Utc>> {
+        if !self.is_disputed() {
+            return None;
+        }
+        self.dispute_history
+            .iter()
+            .rev()
+            .find(|event| event.action == DisputeAction::Dispute)
+            .map(|event| event.timestamp)
+    }
+
     pub fn mark_disputed(&mut self) -> Result<(), TransactionError> {
+        self.mark_disputed_under(&DisputePolicy::default())
+    }
+
+    pub fn mark_disputed_under(&mut self, policy: &DisputePolicy) -> Result<(), TransactionError> {
         if self.state == TransactionState::Disputed {
             return Err(TransactionError::AlreadyDisputed);
         }
         if self.state == TransactionState::Chargedback {
             return Err(TransactionError::AlreadyChargedback);
         }
+        if self.state == TransactionState::Reversed {
+            return Err(TransactionError::AlreadyReversed);
+        }
+        if let Some(max) = policy.max_disputes
+            && self.dispute_count >= max
+        {
+            return Err(TransactionError::DisputeLimitReached(max));
+        }
         self.state = TransactionState::Disputed;
+        self.dispute_count += 1;
+        self.record(DisputeAction::Dispute);
         Ok(())
     }
 
@@ -93,7 +357,8 @@ impl MoneyTransaction {
         if self.state != TransactionState::Disputed {
             return Err(TransactionError::NotDisputed);
         }
-        self.state = TransactionState::Normal;
+        self.state = TransactionState::Resolved;
+        self.record(DisputeAction::Resolve);
         Ok(())
     }
 
@@ -102,8 +367,33 @@ impl MoneyTransaction {
             return Err(TransactionError::NotDisputed);
         }
         self.state = TransactionState::Chargedback;
+        self.record(DisputeAction::Chargeback);
+        Ok(())
+    }
+
+    /// Whether the transaction may be reversed, without marking it
+    pub fn check_reversible(&self) -> Result<(), TransactionError> {
+        match self.state {
+            TransactionState::Normal | TransactionState::Resolved => Ok(()),
+            TransactionState::Reversed => Err(TransactionError::AlreadyReversed),
+            TransactionState::Disputed | TransactionState::Chargedback => {
+                Err(TransactionError::CannotReverseDisputed)
+            }
+        }
+    }
+
+    pub fn mark_reversed(&mut self) -> Result<(), TransactionError> {
+        self.check_reversible()?;
+        self.state = TransactionState::Reversed;
         Ok(())
     }
+
+    fn record(&mut self, action: DisputeAction) {
+        self.dispute_history.push(DisputeEvent {
+            action,
+            timestamp: Utc::now(),
+        });
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -113,25 +403,59 @@ pub enum Transaction {
     Dispute(ClientTransaction),
     Resolve(ClientTransaction),
     Chargeback(ClientTransaction),
+    Reversal(ClientTransaction),
 }
 
 impl Transaction {
-    pub fn client_id(&self) -> u16 {
+    pub fn deposit(client: impl Into<ClientId>, tx: u32, amount: Decimal) -> Result<Self, String> {
+        Ok(Transaction::Deposit(MoneyTransaction::new(
+            client, tx, amount,
+        )?))
+    }
+
+    pub fn withdrawal(
+        client: impl Into<ClientId>,
+        tx: u32,
+        amount: Decimal,
+    ) -> Result<Self, String> {
+        Ok(Transaction::Withdrawal(MoneyTransaction::new(
+            client, tx, amount,
+        )?))
+    }
+
+    pub fn dispute(client: impl Into<ClientId>, tx: u32) -> Self {
+        Transaction::Dispute(ClientTransaction::new(client, tx))
+    }
+
+    pub fn resolve(client: impl Into<ClientId>, tx: u32) -> Self {
+        Transaction::Resolve(ClientTransaction::new(client, tx))
+    }
+
+    pub fn chargeback(client: impl Into<ClientId>, tx: u32) -> Self {
+        Transaction::Chargeback(ClientTransaction::new(client, tx))
+    }
+
+    pub fn reversal(client: impl Into<ClientId>, tx: u32) -> Self {
+        Transaction::Reversal(ClientTransaction::new(client, tx))
+    }
+
+    pub fn client_id(&self) -> ClientId {
         match self {
             Transaction::Deposit(tx) | Transaction::Withdrawal(tx) => tx.id.client,
-            Transaction::Dispute(id) | Transaction::Resolve(id) | Transaction::Chargeback(id) => {
-                id.client
-            }
+            Transaction::Dispute(id)
+            | Transaction::Resolve(id)
+            | Transaction::Chargeback(id)
+            | Transaction::Reversal(id) => id.client,
         }
     }
 
-    #[allow(dead_code)]
     pub fn transaction_id(&self) -> u32 {
         match self {
             Transaction::Deposit(tx) | Transaction::Withdrawal(tx) => tx.id.tx,
-            Transaction::Dispute(id) | Transaction::Resolve(id) | Transaction::Chargeback(id) => {
-                id.tx
-            }
+            Transaction::Dispute(id)
+            | Transaction::Resolve(id)
+            | Transaction::Chargeback(id)
+            | Transaction::Reversal(id) => id.tx,
         }
     }
 }
@@ -141,6 +465,52 @@ mod tests {
     use super::*;
     use rust_decimal_macros::dec;
 
+    #[test]
+    fn test_client_id_parsing() {
+        assert_eq!("70000".parse(), Ok(ClientId::new(70000)));
+        assert_eq!(" 4294967295 ".parse(), Ok(ClientId::new(4_294_967_295)));
+        assert_eq!(
+            "18446744073709551616".parse::<ClientId>(),
+            Err(ClientIdError::OutOfRange(
+                "18446744073709551616".to_string()
+            ))
+        );
+        assert_eq!(
+            "-1".parse::<ClientId>(),
+            Err(ClientIdError::OutOfRange("-1".to_string()))
+        );
+        assert_eq!(
+            "abc".parse::<ClientId>(),
+            Err(ClientIdError::Invalid("abc".to_string()))
+        );
+        assert_eq!(ClientId::from_u64(70000), Ok(ClientId::new(70000)));
+        assert_eq!(
+            ClientIdError::Invalid("abc".to_string()).to_string(),
+            "Invalid client ID: \"abc\""
+        );
+    }
+
+    #[cfg(feature = "wide-client-ids")]
+    #[test]
+    fn test_wide_client_ids_stop_at_i64_max() {
+        assert_eq!(
+            "9223372036854775807".parse(),
+            Ok(ClientId::new(i64::MAX as u64))
+        );
+        assert_eq!(
+            u64::MAX.to_string().parse::<ClientId>(),
+            Err(ClientIdError::OutOfRange(u64::MAX.to_string()))
+        );
+        assert_eq!(
+            ClientId::from_u64(u64::MAX),
+            Err(ClientIdError::OutOfRange(u64::MAX.to_string()))
+        );
+        assert_eq!(
+            ClientIdError::OutOfRange(u64::MAX.to_string()).to_string(),
+            "Client ID 18446744073709551615 is out of range (0 to 9223372036854775807)"
+        );
+    }
+
     #[test]
     fn test_money_transaction_creation() {
         let result = MoneyTransaction::new(1, 100, dec!(50.00));
@@ -170,6 +540,20 @@ mod tests {
         assert_eq!(dispute.client_id(), 10);
     }
 
+    #[test]
+    fn test_transaction_constructors() {
+        let deposit = Transaction::deposit(1, 10, dec!(5.00)).unwrap();
+        assert!(matches!(deposit, Transaction::Deposit(_)));
+        assert_eq!(deposit.transaction_id(), 10);
+
+        assert!(Transaction::withdrawal(1, 11, dec!(-1.00)).is_err());
+        assert_eq!(
+            Transaction::dispute(2, 12),
+            Transaction::Dispute(ClientTransaction::new(2, 12))
+        );
+        assert_eq!(Transaction::reversal(3, 13).client_id(), 3);
+    }
+
     #[test]
     fn test_is_disputed() {
         let mut tx = MoneyTransaction::new(1, 100, dec!(50.00)).unwrap();
@@ -226,4 +610,89 @@ mod tests {
         // Cannot dispute a chargedback transaction
         assert!(tx.mark_disputed().is_err());
     }
+
+    #[test]
+    fn test_reversal_state_transitions() {
+        let mut tx = MoneyTransaction::new(1, 100, dec!(50.00)).unwrap();
+
+        // Normal transactions can be reversed once
+        assert!(tx.mark_reversed().is_ok());
+        assert!(tx.is_reversed());
+        assert_eq!(tx.mark_reversed(), Err(TransactionError::AlreadyReversed));
+
+        // A reversed transaction cannot be disputed
+        assert_eq!(tx.mark_disputed(), Err(TransactionError::AlreadyReversed));
+
+        // A disputed transaction cannot be reversed
+        let mut disputed = MoneyTransaction::new(1, 101, dec!(50.00)).unwrap();
+        disputed.mark_disputed().unwrap();
+        assert_eq!(
+            disputed.mark_reversed(),
+            Err(TransactionError::CannotReverseDisputed)
+        );
+    }
+
+    #[test]
+    fn test_resolved_state_and_dispute_history() {
+        let mut tx = MoneyTransaction::new(1, 100, dec!(50.00)).unwrap();
+
+        tx.mark_disputed().unwrap();
+        assert!(tx.dispute_opened_at().is_some());
+        tx.resolve_dispute().unwrap();
+        assert!(tx.is_resolved());
+        assert!(tx.dispute_opened_at().is_none());
+
+        tx.mark_disputed().unwrap();
+        tx.mark_chargedback().unwrap();
+
+        assert_eq!(tx.dispute_count, 2);
+        let actions: Vec<_> = tx.dispute_history.iter().map(|e| e.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                DisputeAction::Dispute,
+                DisputeAction::Resolve,
+                DisputeAction::Dispute,
+                DisputeAction::Chargeback
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_disputes_policy() {
+        let policy = DisputePolicy {
+            max_disputes: Some(1),
+            ..DisputePolicy::default()
+        };
+        let mut tx = MoneyTransaction::new(1, 100, dec!(50.00)).unwrap();
+
+        tx.mark_disputed_under(&policy).unwrap();
+        tx.resolve_dispute().unwrap();
+
+        assert_eq!(
+            tx.mark_disputed_under(&policy),
+            Err(TransactionError::DisputeLimitReached(1))
+        );
+        assert!(tx.is_resolved());
+        assert_eq!(tx.dispute_count, 1);
+    }
+
+    #[test]
+    fn test_amount_policy() {
+        let policy = AmountPolicy {
+            min_amount: Some(dec!(0.01)),
+            max_decimal_places: Some(2),
+        };
+        assert_eq!(policy.check(dec!(0.01)), Ok(()));
+        assert_eq!(policy.check(dec!(1.5000)), Ok(()));
+        assert_eq!(
+            policy.check(dec!(0.005)).unwrap_err().to_string(),
+            "Transaction amount 0.005 is below the minimum of 0.01"
+        );
+        assert_eq!(
+            policy.check(dec!(1.234)).unwrap_err().to_string(),
+            "Transaction amount 1.234 has more than 2 decimal places"
+        );
+        assert_eq!(AmountPolicy::default().check(dec!(0.00001)), Ok(()));
+    }
 }
@@ -0,0 +1,449 @@
+use crate::engine::Engine;
+use crate::report;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+
+/// Default time between directory scans
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Subdirectory that receives files whose input was read to the end
+pub const DONE_DIR: &str = "done";
+
+/// Subdirectory that receives files that could not be read to the end
+pub const FAILED_DIR: &str = "failed";
+
+/// Size and modification time of a file, as seen on one scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Where a file ended up after it was processed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchedFile {
+    pub path: PathBuf,
+    pub summary_path: PathBuf,
+    pub failed: bool,
+}
+
+/// Picks up CSV files dropped into a directory and runs each through one `Engine`.
+///
+/// A file is only processed once its size and modification time are unchanged
+/// between two consecutive scans, so files still being uploaded are left alone.
+/// Hidden files (such as `.upload.csv` temporaries) and non-`.csv` files are ignored.
+/// A file whose contents were ingested before is skipped; the engine's account
+/// store records ingested files with the accounts, so with a store that persists
+/// them this holds across restarts.
+///
+/// An I/O error while handling one file is logged and the file is moved to
+/// `failed/`; only errors reading the directory itself stop the watcher.
+#[derive(Debug)]
+pub struct DirectoryWatcher {
+    engine: Engine,
+    dir: PathBuf,
+    poll_interval: Duration,
+    seen: HashMap<PathBuf, Snapshot>,
+    /// Files that failed and could not be moved out of the way either. They are
+    /// left alone until they change, rather than retried on every scan.
+    stuck: HashMap<PathBuf, Snapshot>,
+}
+
+impl DirectoryWatcher {
+    pub fn new<P: AsRef<Path>>(engine: Engine, dir: P) -> Self {
+        Self {
+            engine,
+            dir: dir.as_ref().to_path_buf(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            seen: HashMap::new(),
+            stuck: HashMap::new(),
+        }
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Scan until `cancel_token` is cancelled
+    pub async fn run(&mut self, cancel_token: CancellationToken) -> io::Result<()> {
+        fs::create_dir_all(self.dir.join(DONE_DIR))?;
+        fs::create_dir_all(self.dir.join(FAILED_DIR))?;
+
+        loop {
+            self.poll_once(&cancel_token).await?;
+            tokio::select! {
+                _ = cancel_token.cancelled() => return Ok(()),
+                _ = tokio::time::sleep(self.poll_interval) => {}
+            }
+        }
+    }
+
+    /// Scan the directory once and process every file that has stopped changing
+    pub async fn poll_once(
+        &mut self,
+        cancel_token: &CancellationToken,
+    ) -> io::Result<Vec<WatchedFile>> {
+        let mut current = HashMap::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if !is_candidate(&path) {
+                continue;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    // Most likely removed since the directory was listed
+                    warn!("Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let snapshot = Snapshot {
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            };
+            current.insert(path, snapshot);
+        }
+
+        self.stuck
+            .retain(|path, snapshot| current.get(path) == Some(snapshot));
+        let mut ready: Vec<_> = current
+            .iter()
+            .filter(|(path, snapshot)| {
+                self.seen.get(*path) == Some(snapshot) && !self.stuck.contains_key(*path)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+
+        let mut finished = Vec::with_capacity(ready.len());
+        for path in ready {
+            if cancel_token.is_cancelled() {
+                break;
+            }
+            let snapshot = current.remove(&path);
+            match self.process_file(&path, cancel_token).await {
+                Ok(watched) => finished.push(watched),
+                Err(e) => {
+                    error!("Error handling {}: {}", path.display(), e);
+                    // A file that was already moved only lacks its summary
+                    if !path.exists() {
+                        continue;
+                    }
+                    match self.move_to_failed(&path, &e) {
+                        Ok(watched) => finished.push(watched),
+                        Err(e) => {
+                            error!("Could not move {} to failed: {}", path.display(), e);
+                            self.stuck.extend(snapshot.map(|snapshot| (path, snapshot)));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.seen = current;
+        Ok(finished)
+    }
+
+    /// Move a file whose handling hit `error` to `failed/` and write a summary
+    /// recording the error
+    fn move_to_failed(&self, path: &Path, error: &io::Error) -> io::Result<WatchedFile> {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let target_dir = self.dir.join(FAILED_DIR);
+        fs::create_dir_all(&target_dir)?;
+        let target = unique_path(&target_dir, &file_name);
+        fs::rename(path, &target)?;
+
+        let summary_path = summary_path(&target);
+        let mut writer = BufWriter::new(File::create(&summary_path)?);
+        writeln!(writer, "file: {}", file_name)?;
+        writeln!(writer, "status: failed")?;
+        writeln!(writer, "error: {}", error)?;
+        writer.flush()?;
+        Ok(WatchedFile {
+            path: target,
+            summary_path,
+            failed: true,
+        })
+    }
+
+    async fn process_file(
+        &self,
+        path: &Path,
+        cancel_token: &CancellationToken,
+    ) -> io::Result<WatchedFile> {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        info!("Processing dropped file: {}", file_name);
+
+        let mut summary = self.engine.process_csv(path, cancel_token.clone()).await;
+        if cancel_token.is_cancelled() && summary.read_error.is_none() {
+            // Rows after the cancellation point were never read
+            summary.read_error = Some("Processing cancelled".to_string());
+        }
+        let failed = summary.read_error.is_some();
+        if let Some(e) = &summary.read_error {
+            error!("Error processing {}: {}", file_name, e);
+        }
+
+        let target_dir = self.dir.join(if failed { FAILED_DIR } else { DONE_DIR });
+        fs::create_dir_all(&target_dir)?;
+        let target = unique_path(&target_dir, &file_name);
+        fs::rename(path, &target)?;
+
+        let summary_path = summary_path(&target);
+        let mut writer = BufWriter::new(File::create(&summary_path)?);
+        report::write_process_summary(&file_name, &summary, &mut writer)?;
+        writer.flush()?;
+
+        info!(
+            "Finished {}: {} processed, {} replayed, {} rejected{}",
+            file_name,
+            summary.processed,
+            summary.replayed,
+            summary.rejected.len(),
+            if summary.skipped {
+                " (skipped, already ingested)"
+            } else {
+                ""
+            }
+        );
+        Ok(WatchedFile {
+            path: target,
+            summary_path,
+            failed,
+        })
+    }
+}
+
+fn is_candidate(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    !name.starts_with('.')
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+}
+
+/// `<target>.summary`, next to a moved file
+fn summary_path(target: &Path) -> PathBuf {
+    target.with_file_name(format!(
+        "{}.summary",
+        target.file_name().unwrap_or_default().to_string_lossy()
+    ))
+}
+
+/// `dir/name`, or `dir/name.N` if a file of that name was already moved there
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let mut target = dir.join(name);
+    let mut n = 1;
+    while target.exists() {
+        target = dir.join(format!("{}.{}", name, n));
+        n += 1;
+    }
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite::SqliteAccountStore;
+    use rust_decimal_macros::dec;
+
+    fn temp_watch_dir() -> tempfile::TempDir {
+        tempfile::tempdir().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_file_to_settle() {
+        let temp = temp_watch_dir();
+        let dir = temp.path();
+        let cancel_token = CancellationToken::new();
+        let mut watcher = DirectoryWatcher::new(Engine::default(), dir);
+
+        fs::write(dir.join("a.csv"), "type, client, tx, amount\n").unwrap();
+        fs::write(dir.join(".b.csv"), "type, client, tx, amount\n").unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        assert!(watcher.poll_once(&cancel_token).await.unwrap().is_empty());
+
+        // Still growing, so it is not picked up on this scan either
+        fs::write(
+            dir.join("a.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\n",
+        )
+        .unwrap();
+        assert!(watcher.poll_once(&cancel_token).await.unwrap().is_empty());
+
+        let finished = watcher.poll_once(&cancel_token).await.unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].path, dir.join(DONE_DIR).join("a.csv"));
+        assert!(!finished[0].failed);
+        assert!(!dir.join("a.csv").exists());
+        assert!(dir.join(".b.csv").exists());
+        assert!(dir.join("notes.txt").exists());
+
+        let summary = fs::read_to_string(&finished[0].summary_path).unwrap();
+        assert!(summary.contains("status: done\nprocessed: 1\n"));
+        assert_eq!(
+            watcher
+                .engine()
+                .account(1)
+                .await
+                .unwrap()
+                .unwrap()
+                .available,
+            dec!(5.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_moves_unreadable_file_to_failed() {
+        let temp = temp_watch_dir();
+        let dir = temp.path();
+        let cancel_token = CancellationToken::new();
+        let mut watcher = DirectoryWatcher::new(Engine::default(), dir);
+
+        fs::write(
+            dir.join("bad.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\ndeposit, x, 2, 1.0\n",
+        )
+        .unwrap();
+        // A file of the same name was already processed earlier
+        fs::create_dir_all(dir.join(FAILED_DIR)).unwrap();
+        fs::write(dir.join(FAILED_DIR).join("bad.csv"), "").unwrap();
+
+        watcher.poll_once(&cancel_token).await.unwrap();
+        let finished = watcher.poll_once(&cancel_token).await.unwrap();
+        assert_eq!(finished.len(), 1);
+        assert!(finished[0].failed);
+        assert_eq!(finished[0].path, dir.join(FAILED_DIR).join("bad.csv.1"));
+
+        let summary = fs::read_to_string(&finished[0].summary_path).unwrap();
+        assert!(summary.contains("status: failed\n"));
+    }
+
+    #[tokio::test]
+    async fn test_file_error_does_not_stop_watcher() {
+        let temp = temp_watch_dir();
+        let dir = temp.path();
+        let cancel_token = CancellationToken::new();
+        let mut watcher = DirectoryWatcher::new(Engine::default(), dir);
+
+        // `done/` cannot be created, so the file is moved to `failed/` instead
+        fs::write(dir.join(DONE_DIR), "").unwrap();
+        fs::write(
+            dir.join("a.csv"),
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\n",
+        )
+        .unwrap();
+        watcher.poll_once(&cancel_token).await.unwrap();
+        let finished = watcher.poll_once(&cancel_token).await.unwrap();
+        assert_eq!(finished.len(), 1);
+        assert!(finished[0].failed);
+        assert_eq!(finished[0].path, dir.join(FAILED_DIR).join("a.csv"));
+        let summary = fs::read_to_string(&finished[0].summary_path).unwrap();
+        assert!(summary.contains("status: failed\nerror: "), "{}", summary);
+
+        // With nowhere to move it the file stays put and is not retried
+        fs::remove_dir_all(dir.join(FAILED_DIR)).unwrap();
+        fs::write(dir.join(FAILED_DIR), "").unwrap();
+        fs::write(
+            dir.join("b.csv"),
+            "type, client, tx, amount\ndeposit, 1, 2, 5.0\n",
+        )
+        .unwrap();
+        watcher.poll_once(&cancel_token).await.unwrap();
+        assert!(watcher.poll_once(&cancel_token).await.unwrap().is_empty());
+        assert!(dir.join("b.csv").exists());
+        assert!(watcher.poll_once(&cancel_token).await.unwrap().is_empty());
+        assert_eq!(
+            watcher
+                .engine()
+                .account(1)
+                .await
+                .unwrap()
+                .unwrap()
+                .available,
+            dec!(10.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resent_file_is_skipped_across_restarts() {
+        let temp = temp_watch_dir();
+        let dir = temp.path();
+        let state_db = temp.path().join("state.db");
+        let cancel_token = CancellationToken::new();
+        let contents = "type, client, tx, amount\ndeposit, 1, 1, 5.0\ndeposit, 1, 2, 1.0\n";
+        let engine = || {
+            Engine::builder()
+                .account_store(SqliteAccountStore::open(&state_db).unwrap())
+                .build()
+        };
+
+        let mut watcher = DirectoryWatcher::new(engine(), dir);
+        fs::write(dir.join("day1.csv"), contents).unwrap();
+        watcher.poll_once(&cancel_token).await.unwrap();
+        assert_eq!(watcher.poll_once(&cancel_token).await.unwrap().len(), 1);
+        drop(watcher);
+
+        // Same contents under a new name, picked up by a restarted watcher
+        let mut watcher = DirectoryWatcher::new(engine(), dir);
+        fs::write(dir.join("day1_resend.csv"), contents).unwrap();
+        watcher.poll_once(&cancel_token).await.unwrap();
+        let finished = watcher.poll_once(&cancel_token).await.unwrap();
+        assert_eq!(finished.len(), 1);
+        assert!(!finished[0].failed);
+
+        let summary = fs::read_to_string(&finished[0].summary_path).unwrap();
+        assert!(summary.contains("status: skipped\nprocessed: 0\n"));
+        let balance = watcher.engine().balance(1).await.unwrap().unwrap();
+        assert_eq!(balance.total, dec!(6.0));
+    }
+
+    #[tokio::test]
+    async fn test_resent_file_is_applied_after_restart_without_state() {
+        let temp = temp_watch_dir();
+        let dir = temp.path();
+        let cancel_token = CancellationToken::new();
+        let contents = "type, client, tx, amount\ndeposit, 1, 1, 5.0\n";
+
+        let mut watcher = DirectoryWatcher::new(Engine::default(), dir);
+        fs::write(dir.join("day1.csv"), contents).unwrap();
+        watcher.poll_once(&cancel_token).await.unwrap();
+        assert_eq!(watcher.poll_once(&cancel_token).await.unwrap().len(), 1);
+
+        // The balances went with the engine, so the file is not skipped
+        let mut watcher = DirectoryWatcher::new(Engine::default(), dir);
+        fs::write(dir.join("day1_resend.csv"), contents).unwrap();
+        watcher.poll_once(&cancel_token).await.unwrap();
+        let finished = watcher.poll_once(&cancel_token).await.unwrap();
+        let summary = fs::read_to_string(&finished[0].summary_path).unwrap();
+        assert!(
+            summary.contains("status: done\nprocessed: 1\n"),
+            "{}",
+            summary
+        );
+        let balance = watcher.engine().balance(1).await.unwrap().unwrap();
+        assert_eq!(balance.total, dec!(5.0));
+    }
+}
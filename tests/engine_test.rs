@@ -0,0 +1,64 @@
+use rust_decimal_macros::dec;
+use std::fs;
+use tokio_util::sync::CancellationToken;
+use transactions::{Engine, Transaction, report};
+
+/// Run a CSV through an embedded engine and compare with the expected output
+async fn assert_engine_output_matches(input_csv: &str, expected_csv: &str) {
+    let engine = Engine::builder().build();
+    let summary = engine
+        .process_csv(input_csv, CancellationToken::new())
+        .await;
+    assert_eq!(summary.read_error, None);
+
+    let mut output = Vec::new();
+    report::write_accounts(&engine.balances().await.unwrap(), false, &mut output)
+        .expect("Failed to write accounts");
+
+    let actual = String::from_utf8(output)
+        .expect("Output is not UTF-8")
+        .replace(", ", ",");
+    let expected = fs::read_to_string(expected_csv).expect("Failed to read expected output");
+
+    assert_eq!(actual.trim_end(), expected.trim_end());
+}
+
+#[tokio::test]
+async fn test_engine_csv_processing() {
+    assert_engine_output_matches(
+        "tests/input/test_data.csv",
+        "tests/expected/test_data_expected.csv",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_engine_dispute_and_chargeback() {
+    assert_engine_output_matches(
+        "tests/input/dispute_chargeback.csv",
+        "tests/expected/dispute_chargeback_expected.csv",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_engine_direct_transactions() {
+    let engine = Engine::default();
+
+    engine
+        .process(Transaction::deposit(1, 1, dec!(10.0)).unwrap())
+        .await
+        .unwrap();
+    engine
+        .process(Transaction::withdrawal(1, 2, dec!(4.0)).unwrap())
+        .await
+        .unwrap();
+    engine.process(Transaction::dispute(1, 1)).await.unwrap();
+    assert!(engine.process(Transaction::resolve(1, 2)).await.is_err());
+
+    let account = engine.account(1).await.unwrap().unwrap();
+    assert_eq!(account.available(), dec!(-4.0));
+    assert_eq!(account.held(), dec!(10.0));
+    assert_eq!(account.total(), dec!(6.0));
+    assert!(engine.account(2).await.unwrap().is_none());
+}
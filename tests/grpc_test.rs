@@ -0,0 +1,129 @@
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use transactions::Engine;
+use transactions::grpc::proto::transaction_service_client::TransactionServiceClient;
+use transactions::grpc::proto::{
+    ErrorReason, GetAccountRequest, ListAccountsRequest, Transaction, TransactionType,
+};
+use transactions::grpc::{self, GrpcService};
+
+/// Start the gRPC API on an ephemeral localhost port and connect a client
+async fn start_server() -> (
+    TransactionServiceClient<tonic::transport::Channel>,
+    CancellationToken,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind");
+    let addr = listener.local_addr().unwrap();
+    let cancel_token = CancellationToken::new();
+    tokio::spawn(grpc::serve(
+        GrpcService::new(Engine::default(), 4),
+        listener,
+        cancel_token.clone(),
+    ));
+
+    let client = TransactionServiceClient::connect(format!("http://{}", addr))
+        .await
+        .expect("Failed to connect");
+    (client, cancel_token)
+}
+
+fn transaction(tx_type: TransactionType, client: u64, tx: u32, amount: &str) -> Transaction {
+    Transaction {
+        r#type: tx_type.into(),
+        client,
+        tx,
+        amount: amount.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_submit_and_get_account() {
+    let (mut client, cancel_token) = start_server().await;
+
+    let ack = client
+        .submit(transaction(TransactionType::Deposit, 1, 1, "2.5"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.accepted);
+
+    let ack = client
+        .submit(transaction(TransactionType::Withdrawal, 1, 2, "5.0"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!ack.accepted);
+    assert_eq!(ack.reason(), ErrorReason::InsufficientFunds);
+    assert_eq!(ack.message, "Insufficient funds");
+
+    let account = client
+        .get_account(GetAccountRequest { client: 1 })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(account.available, "2.5");
+    assert!(!account.locked);
+
+    let status = client
+        .get_account(GetAccountRequest { client: 9 })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    cancel_token.cancel();
+}
+
+#[tokio::test]
+async fn test_stream_transactions_acks_in_order() {
+    let (mut client, cancel_token) = start_server().await;
+
+    let (tx, rx) = mpsc::channel(8);
+    let mut acks = client
+        .stream_transactions(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let requests = vec![
+        transaction(TransactionType::Deposit, 1, 1, "10.0"),
+        transaction(TransactionType::Dispute, 1, 1, ""),
+        transaction(TransactionType::Chargeback, 1, 1, ""),
+        transaction(TransactionType::Deposit, 1, 2, "1.0"),
+        transaction(TransactionType::Deposit, 2, 3, "1.0"),
+    ];
+    for request in requests {
+        tx.send(request).await.unwrap();
+    }
+    drop(tx);
+
+    let mut results = Vec::new();
+    while let Some(ack) = acks.message().await.unwrap() {
+        results.push((ack.tx, ack.accepted, ack.reason()));
+    }
+    assert_eq!(
+        results,
+        vec![
+            (1, true, ErrorReason::Unspecified),
+            (1, true, ErrorReason::Unspecified),
+            (1, true, ErrorReason::Unspecified),
+            (2, false, ErrorReason::AccountLocked),
+            (3, true, ErrorReason::Unspecified),
+        ]
+    );
+
+    let accounts = client
+        .list_accounts(ListAccountsRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .accounts;
+    assert_eq!(accounts.len(), 2);
+    assert!(accounts[0].locked);
+    assert_eq!(accounts[1].client, 2);
+
+    cancel_token.cancel();
+}
@@ -4,9 +4,15 @@ use std::process::Command;
 
 /// Helper function to run the transaction processor and compare output with expected CSV
 fn assert_csv_output_matches(input_csv: &str, expected_csv: &str) {
+    assert_csv_output_matches_with_args(input_csv, expected_csv, &[]);
+}
+
+/// Same as `assert_csv_output_matches`, passing extra command line arguments
+fn assert_csv_output_matches_with_args(input_csv: &str, expected_csv: &str, extra_args: &[&str]) {
     // Run the program with input CSV
     let output = Command::new("cargo")
-        .args(&["run", "--", input_csv])
+        .args(["run", "--", input_csv])
+        .args(extra_args)
         .output()
         .expect("Failed to execute command");
 
@@ -74,10 +80,10 @@ fn assert_csv_output_matches(input_csv: &str, expected_csv: &str) {
     for col_name in actual_sorted.get_column_names() {
         let actual_col = actual_sorted
             .column(col_name)
-            .expect(&format!("Column {} not found in actual", col_name));
+            .unwrap_or_else(|_| panic!("Column {} not found in actual", col_name));
         let expected_col = expected_sorted
             .column(col_name)
-            .expect(&format!("Column {} not found in expected", col_name));
+            .unwrap_or_else(|_| panic!("Column {} not found in expected", col_name));
 
         assert!(
             actual_col.equals(expected_col),
@@ -128,3 +134,145 @@ fn test_dispute_and_chargeback() {
         "tests/expected/dispute_chargeback_expected.csv",
     );
 }
+
+#[test]
+fn test_client_limits() {
+    assert_csv_output_matches_with_args(
+        "tests/input/limits.csv",
+        "tests/expected/limits_expected.csv",
+        &["--limits", "tests/input/limits_config.csv"],
+    );
+}
+
+#[test]
+fn test_credit_line() {
+    assert_csv_output_matches_with_args(
+        "tests/input/credit_line.csv",
+        "tests/expected/credit_line_expected.csv",
+        &["--limits", "tests/input/credit_line_limits.csv"],
+    );
+}
+
+#[test]
+fn test_reversal() {
+    assert_csv_output_matches(
+        "tests/input/reversal.csv",
+        "tests/expected/reversal_expected.csv",
+    );
+}
+
+#[test]
+fn test_disputes_report() {
+    let report_path = "tests/input/disputes_report.csv.report";
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "tests/input/disputes_report.csv",
+            "--disputes-report",
+            report_path,
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let report = fs::read_to_string(report_path).expect("Failed to read disputes report");
+    fs::remove_file(report_path).ok();
+
+    // Drop the dispute age column, which depends on wall-clock time
+    let rows: Vec<String> = report
+        .lines()
+        .map(|line| line.rsplit_once(", ").map(|(row, _)| row).unwrap_or(line))
+        .map(str::to_string)
+        .collect();
+
+    assert_eq!(
+        rows,
+        vec![
+            "client, tx, type, amount, state, disputes",
+            "1, 1, deposit, 10.0, resolved, 1",
+            "1, 2, deposit, 5.0, disputed, 1",
+            "2, 3, deposit, 7.5, chargedback, 1",
+        ]
+    );
+}
+
+/// Run the processor with `args` and return its exit code and stderr
+fn run(args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new("cargo")
+        .args(["run", "--"])
+        .args(args)
+        .output()
+        .expect("Failed to execute command");
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(
+        run(&["process", "tests/input/dispute_resolve.csv"]).0,
+        Some(0)
+    );
+    assert_eq!(
+        run(&[
+            "process",
+            "tests/input/dispute_resolve.csv",
+            "--check-invariants-at-end"
+        ])
+        .0,
+        Some(0)
+    );
+    // Rejected withdrawals are a partial failure
+    assert_eq!(run(&["process", "tests/input/test_data.csv"]).0, Some(1));
+    assert_eq!(run(&["process", "tests/input/missing.csv"]).0, Some(2));
+    // The first malformed row stops processing unless rows are skipped
+    assert_eq!(run(&["tests/input/malformed.csv"]).0, Some(2));
+    assert_eq!(
+        run(&["tests/input/malformed.csv", "--on-malformed-row", "skip"]).0,
+        Some(1)
+    );
+    // Settings flags may come before the file
+    assert_eq!(
+        run(&["--on-malformed-row", "skip", "tests/input/malformed.csv"]).0,
+        Some(1)
+    );
+    assert_eq!(run(&["process", "--bogus"]).0, Some(2));
+    // A command rejects flags for settings it does not use, and values must parse
+    let (code, stderr) = run(&["validate", "tests/input/malformed.csv", "--limits", "x.csv"]);
+    assert_eq!(code, Some(2));
+    assert!(
+        stderr.contains("unexpected argument '--limits'"),
+        "{}",
+        stderr
+    );
+    let (code, stderr) = run(&["process", "tests/input/empty.csv", "--batch-size", "abc"]);
+    assert_eq!(code, Some(2));
+    assert!(stderr.contains("invalid value 'abc'"), "{}", stderr);
+
+    for command in [
+        "process", "validate", "serve", "watch", "replay", "generate", "report",
+    ] {
+        assert_eq!(run(&[command, "--help"]).0, Some(0), "{}", command);
+    }
+}
+
+#[test]
+fn test_validate() {
+    let (code, stderr) = run(&["validate", "tests/input/malformed.csv"]);
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        stderr.lines().rev().take(3).collect::<Vec<_>>(),
+        vec![
+            "2 valid rows, 2 malformed",
+            "line 4: Invalid client ID: \"x\"",
+            "line 3: Unknown transaction type: foo",
+        ]
+    );
+
+    let (code, stderr) = run(&["validate", "tests/input/test_data.csv"]);
+    assert_eq!(code, Some(0));
+    assert!(stderr.ends_with(" valid rows, 0 malformed\n"), "{}", stderr);
+}
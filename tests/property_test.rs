@@ -0,0 +1,196 @@
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use transactions::{ClientId, ClientIdRepr, Engine, ProcessOutcome, Transaction, invariants};
+
+/// Kind of row, with the amount in cents for deposits and withdrawals
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Deposit(i64),
+    Withdrawal(i64),
+    Dispute,
+    Resolve,
+    Chargeback,
+    Reversal,
+}
+
+/// A few clients and transaction IDs, so generated rows collide often
+fn arb_row() -> impl Strategy<Value = (ClientId, u32, Op)> {
+    let op = prop_oneof![
+        3 => (1..10_000i64).prop_map(Op::Deposit),
+        2 => (1..10_000i64).prop_map(Op::Withdrawal),
+        2 => Just(Op::Dispute),
+        1 => Just(Op::Resolve),
+        1 => Just(Op::Chargeback),
+        1 => Just(Op::Reversal),
+    ];
+    (
+        (1..=3 as ClientIdRepr).prop_map(ClientId::new),
+        1..=8u32,
+        op,
+    )
+}
+
+fn to_transaction(client: ClientId, tx: u32, op: Op) -> Transaction {
+    match op {
+        Op::Deposit(cents) => Transaction::deposit(client, tx, Decimal::new(cents, 2)).unwrap(),
+        Op::Withdrawal(cents) => {
+            Transaction::withdrawal(client, tx, Decimal::new(cents, 2)).unwrap()
+        }
+        Op::Dispute => Transaction::dispute(client, tx),
+        Op::Resolve => Transaction::resolve(client, tx),
+        Op::Chargeback => Transaction::chargeback(client, tx),
+        Op::Reversal => Transaction::reversal(client, tx),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModelState {
+    Normal,
+    Disputed,
+    Resolved,
+    Chargedback,
+    Reversed,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModelTx {
+    is_deposit: bool,
+    amount: Decimal,
+    state: ModelState,
+}
+
+/// Straightforward reference implementation of one client's account
+#[derive(Debug, Default)]
+struct ModelAccount {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    txs: HashMap<u32, ModelTx>,
+}
+
+impl ModelAccount {
+    /// `Ok(true)` for a replayed row, `Err(())` for a rejected one
+    fn apply(&mut self, tx: u32, op: Op) -> Result<bool, ()> {
+        let state = self.txs.get(&tx).map(|model_tx| model_tx.state);
+        let replay = matches!(
+            (op, state),
+            (
+                Op::Dispute,
+                Some(ModelState::Disputed | ModelState::Chargedback)
+            ) | (Op::Resolve, Some(ModelState::Resolved))
+                | (Op::Chargeback, Some(ModelState::Chargedback))
+        );
+        if replay {
+            return Ok(true);
+        }
+        if self.locked && !matches!(op, Op::Chargeback) {
+            return Err(());
+        }
+
+        match op {
+            Op::Deposit(cents) | Op::Withdrawal(cents) => {
+                if state.is_some() {
+                    return Err(());
+                }
+                let amount = Decimal::new(cents, 2);
+                let is_deposit = matches!(op, Op::Deposit(_));
+                if is_deposit {
+                    self.available += amount;
+                    self.total += amount;
+                } else {
+                    if self.available < amount {
+                        return Err(());
+                    }
+                    self.available -= amount;
+                    self.total -= amount;
+                }
+                self.txs.insert(
+                    tx,
+                    ModelTx {
+                        is_deposit,
+                        amount,
+                        state: ModelState::Normal,
+                    },
+                );
+            }
+            _ => {
+                let model_tx = self.txs.get_mut(&tx).ok_or(())?;
+                let amount = model_tx.amount;
+                match (op, model_tx.state) {
+                    (Op::Dispute, ModelState::Normal | ModelState::Resolved) => {
+                        model_tx.state = ModelState::Disputed;
+                        self.available -= amount;
+                        self.held += amount;
+                    }
+                    (Op::Resolve, ModelState::Disputed) => {
+                        model_tx.state = ModelState::Resolved;
+                        self.held -= amount;
+                        self.available += amount;
+                    }
+                    (Op::Chargeback, ModelState::Disputed) => {
+                        model_tx.state = ModelState::Chargedback;
+                        self.held -= amount;
+                        self.total -= amount;
+                        self.locked = true;
+                    }
+                    (Op::Reversal, ModelState::Normal | ModelState::Resolved) => {
+                        // A deposit whose funds were withdrawn cannot be taken back
+                        if model_tx.is_deposit && self.available < amount {
+                            return Err(());
+                        }
+                        model_tx.state = ModelState::Reversed;
+                        let signed = if model_tx.is_deposit { -amount } else { amount };
+                        self.available += signed;
+                        self.total += signed;
+                    }
+                    _ => return Err(()),
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn engine_matches_reference_model(rows in prop::collection::vec(arb_row(), 1..80)) {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let engine = Engine::default();
+        let mut model: HashMap<ClientId, ModelAccount> = HashMap::new();
+
+        for (i, &(client, tx, op)) in rows.iter().enumerate() {
+            let expected = model.entry(client).or_default().apply(tx, op);
+            let actual = runtime.block_on(engine.process(to_transaction(client, tx, op)));
+            match (expected, actual) {
+                (Ok(replayed), Ok(outcome)) => prop_assert_eq!(
+                    outcome == ProcessOutcome::Replayed,
+                    replayed,
+                    "row {}: {:?}", i, (client, tx, op)
+                ),
+                (Err(()), Err(_)) => {}
+                (expected, actual) => prop_assert!(
+                    false,
+                    "row {}: {:?}: model {:?}, engine {:?}",
+                    i, (client, tx, op), expected, actual.map_err(|e| e.to_string())
+                ),
+            }
+        }
+
+        let accounts = runtime.block_on(engine.accounts()).unwrap();
+        for (client, expected) in &model {
+            let account = &accounts[client];
+            prop_assert_eq!(account.available(), expected.available);
+            prop_assert_eq!(account.held(), expected.held);
+            prop_assert_eq!(account.total(), expected.total);
+            prop_assert_eq!(account.is_locked(), expected.locked);
+            prop_assert_eq!(invariants::check_account(account), vec![]);
+        }
+
+        let report = runtime.block_on(engine.verify()).unwrap();
+        prop_assert!(report.is_consistent(), "{:?}", report.violations);
+    }
+}
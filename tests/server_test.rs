@@ -0,0 +1,149 @@
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use transactions::{Engine, server};
+
+/// Start the HTTP API on an ephemeral localhost port
+async fn start_server() -> (String, CancellationToken) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind");
+    let addr = listener.local_addr().unwrap().to_string();
+    let cancel_token = CancellationToken::new();
+    tokio::spawn(server::serve(
+        Engine::default(),
+        listener,
+        cancel_token.clone(),
+    ));
+    (addr, cancel_token)
+}
+
+/// Minimal HTTP/1.1 client returning the status code and JSON body
+async fn request(addr: &str, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).await.expect("Failed to connect");
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .expect("Malformed HTTP response");
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = serde_json::from_str(body).unwrap_or(Value::Null);
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_post_transaction_and_get_account() {
+    let (addr, cancel_token) = start_server().await;
+
+    let (status, body) = request(
+        &addr,
+        "POST",
+        "/transactions",
+        Some(json!({"type": "deposit", "client": 1, "tx": 1, "amount": "10.5"})),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body, json!({"client": 1, "tx": 1, "accepted": true}));
+
+    let (status, body) = request(&addr, "GET", "/accounts/1", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({"client": 1, "available": "10.5", "held": "0", "total": "10.5", "locked": false})
+    );
+
+    let (status, body) = request(&addr, "GET", "/accounts/2", None).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["code"], "not_found");
+
+    cancel_token.cancel();
+}
+
+#[tokio::test]
+async fn test_rejected_transaction_carries_reason() {
+    let (addr, cancel_token) = start_server().await;
+
+    let (status, body) = request(
+        &addr,
+        "POST",
+        "/transactions",
+        Some(json!({"type": "withdrawal", "client": 1, "tx": 1, "amount": "5.0"})),
+    )
+    .await;
+    assert_eq!(status, 422);
+    assert_eq!(body["accepted"], false);
+    assert_eq!(body["error"]["code"], "insufficient_funds");
+    assert_eq!(body["error"]["message"], "Insufficient funds");
+
+    let (status, body) = request(
+        &addr,
+        "POST",
+        "/transactions",
+        Some(json!({"type": "bogus", "client": 1, "tx": 2, "amount": null})),
+    )
+    .await;
+    assert_eq!(status, 422);
+    assert_eq!(body["error"]["code"], "invalid_input");
+
+    cancel_token.cancel();
+}
+
+#[tokio::test]
+async fn test_batch_and_transaction_state() {
+    let (addr, cancel_token) = start_server().await;
+
+    let (status, body) = request(
+        &addr,
+        "POST",
+        "/transactions/batch",
+        Some(json!([
+            {"type": "deposit", "client": 2, "tx": 1, "amount": "3.0"},
+            {"type": "deposit", "client": 1, "tx": 2, "amount": "4.0"},
+            {"type": "dispute", "client": 1, "tx": 2, "amount": null},
+            {"type": "resolve", "client": 2, "tx": 1, "amount": null}
+        ])),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let accepted: Vec<_> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["accepted"].as_bool().unwrap())
+        .collect();
+    assert_eq!(accepted, vec![true, true, true, false]);
+    assert_eq!(body[3]["error"]["code"], "not_disputed");
+
+    let (status, body) = request(&addr, "GET", "/accounts", None).await;
+    assert_eq!(status, 200);
+    let clients: Vec<_> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|a| a["client"].as_u64().unwrap())
+        .collect();
+    assert_eq!(clients, vec![1, 2]);
+    assert_eq!(body[0]["held"], "4.0");
+
+    let (status, body) = request(&addr, "GET", "/accounts/1/transactions/2", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        json!({"client": 1, "tx": 2, "type": "deposit", "amount": "4.0", "state": "disputed", "disputes": 1})
+    );
+
+    let (status, _) = request(&addr, "GET", "/accounts/1/transactions/9", None).await;
+    assert_eq!(status, 404);
+
+    cancel_token.cancel();
+}
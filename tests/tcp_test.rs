@@ -0,0 +1,119 @@
+use rust_decimal_macros::dec;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use transactions::{Engine, tcp};
+
+/// Start the line-protocol listener on an ephemeral localhost port
+async fn start_server(engine: Engine) -> (SocketAddr, CancellationToken) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind");
+    let addr = listener.local_addr().unwrap();
+    let cancel_token = CancellationToken::new();
+    tokio::spawn(tcp::serve(engine, listener, 4, cancel_token.clone()));
+    (addr, cancel_token)
+}
+
+/// Send `input`, close the write side and collect every response line
+async fn send_rows(addr: SocketAddr, input: &str) -> Vec<String> {
+    let mut stream = TcpStream::connect(addr).await.expect("Failed to connect");
+    stream.write_all(input.as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut lines = BufReader::new(stream).lines();
+    let mut responses = Vec::new();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        responses.push(line);
+    }
+    responses
+}
+
+#[tokio::test]
+async fn test_one_response_per_row() {
+    let (addr, cancel_token) = start_server(Engine::default()).await;
+
+    let responses = send_rows(
+        addr,
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 10.0\n\
+         \n\
+         withdrawal, 1, 2, 20.0\n\
+         bogus, 1, 3, 1.0\n\
+         dispute, 1, 1,\n",
+    )
+    .await;
+
+    assert_eq!(responses.len(), 4);
+    assert_eq!(responses[0], "ok");
+    assert_eq!(responses[1], "err insufficient_funds Insufficient funds");
+    assert!(responses[2].starts_with("err invalid_input "));
+    assert_eq!(responses[3], "ok");
+
+    cancel_token.cancel();
+}
+
+#[tokio::test]
+async fn test_concurrent_connections_share_engine() {
+    let engine = Engine::default();
+    let (addr, cancel_token) = start_server(engine.clone()).await;
+
+    let mut handles = Vec::new();
+    for client in 1..=8u16 {
+        let input: String = (0..50u32)
+            .map(|i| {
+                format!(
+                    "deposit, {}, {}, 1.0\n",
+                    client,
+                    u32::from(client) * 1000 + i
+                )
+            })
+            .collect();
+        handles.push(tokio::spawn(async move { send_rows(addr, &input).await }));
+    }
+    for handle in handles {
+        let responses = handle.await.unwrap();
+        assert_eq!(responses.len(), 50);
+        assert!(responses.iter().all(|response| response == "ok"));
+    }
+
+    let accounts = engine.accounts().await.unwrap();
+    assert_eq!(accounts.len(), 8);
+    assert!(
+        accounts
+            .values()
+            .all(|account| account.total() == dec!(50.0))
+    );
+
+    cancel_token.cancel();
+}
+
+#[tokio::test]
+async fn test_header_in_any_case_and_overlong_line() {
+    let (addr, cancel_token) = start_server(Engine::default()).await;
+
+    let responses = send_rows(
+        addr,
+        "Type,Client,Tx,Amount\n\
+         typo, 1, 1, 1.0\n\
+         deposit, 1, 2, 1.0\n",
+    )
+    .await;
+    assert_eq!(responses.len(), 2);
+    assert!(responses[0].starts_with("err invalid_input "));
+    assert_eq!(responses[1], "ok");
+
+    // The connection ends at a line that is too long
+    let input = format!(
+        "deposit, 2, 3, 1.0\ndeposit, 2, 4, 1.{}\ndeposit, 2, 5, 1.0\n",
+        "0".repeat(2000)
+    );
+    let responses = send_rows(addr, &input).await;
+    assert_eq!(
+        responses,
+        vec!["ok", "err invalid_input Line is longer than 1024 bytes"]
+    );
+
+    cancel_token.cancel();
+}